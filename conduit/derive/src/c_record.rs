@@ -0,0 +1,281 @@
+//! `#[derive(CRecord)]`, the C declaration matching a type's `OnDevice`
+//! encoding. Shares the `on_device` attributes with `OnDevice`.
+
+use proc_macro2::{Span, TokenStream};
+use quote::quote;
+use syn::{Data, DeriveInput, Error, Fields};
+
+use crate::{ContainerAttrs, FieldSpec, Kind, VariantAttrs};
+
+pub(crate) fn expand(input: DeriveInput) -> syn::Result<TokenStream> {
+    let container = ContainerAttrs::parse(&input.attrs)?;
+    let name = &input.ident;
+    let name_str = name.to_string();
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let mut c_name = name_str.clone();
+
+    let (variable, definition) = match &input.data {
+        Data::Struct(data) => {
+            let fields = FieldSpec::parse_all(&data.fields)?;
+            let variable = variable(&fields);
+            let members = members(&fields);
+            (
+                variable,
+                quote! {
+                    let mut out = ::std::format!("typedef struct {}_s {{\n", #name_str);
+                    for line in c_lines(#members, 1) {
+                        out.push_str(&line);
+                    }
+                    out.push_str(&::std::format!("}} {};\n", #name_str));
+                    out
+                },
+            )
+        }
+        Data::Enum(data) => {
+            let Some(discriminant) = container.discriminant else {
+                return Err(Error::new(
+                    Span::call_site(),
+                    "enums need #[on_device(discriminant = \"u8\" | \"u16\" | \"u32\")]",
+                ));
+            };
+            let tag_ty = c_int(discriminant.width.ty());
+            // without any fields the enum is just its tag, and members of
+            // this type are declared as the tag's integer
+            let fieldless = data
+                .variants
+                .iter()
+                .all(|v| matches!(v.fields, Fields::Unit));
+            let type_name = match fieldless {
+                true => {
+                    c_name = tag_ty.to_string();
+                    name_str.clone()
+                }
+                false => format!("{name_str}Type"),
+            };
+
+            let mut next_tag = 0_u64;
+            let mut variables = Vec::new();
+            let mut tags = Vec::new();
+            let mut union_members = Vec::new();
+            for variant in data.variants.iter() {
+                let tag = VariantAttrs::parse(&variant.attrs)?.tag.unwrap_or(next_tag);
+                next_tag = tag + 1;
+                tags.push(format!("    {type_name}_{} = {tag},\n", variant.ident));
+
+                let mut fields = FieldSpec::parse_all(&variant.fields)?;
+                variables.push(variable(&fields));
+                let member = snake_case(&variant.ident.to_string());
+                let single = matches!(variant.fields, Fields::Unnamed(_)) && fields.len() == 1;
+                if single {
+                    fields[0].name = member.clone();
+                }
+                let members = members(&fields);
+                union_members.push(match &variant.fields {
+                    Fields::Unit => quote! {},
+                    _ => quote! {
+                        let members = #members;
+                        match #single && members.len() == 1 {
+                            true => union.extend(members.into_iter().map(|(line, _)| (line, false))),
+                            false => {
+                                union.push((::std::string::String::from("struct {"), false));
+                                for line in c_lines(members, 1) {
+                                    union.push((::std::string::String::from(line.trim_end()), false));
+                                }
+                                union.push((::std::format!("}} {};", #member), false));
+                            }
+                        }
+                    },
+                });
+            }
+
+            let header = format!("typedef enum {{\n{}}} {type_name};\n", tags.concat());
+            if fieldless {
+                return Ok(quote! {
+                    impl #impl_generics CRecord for #name #ty_generics #where_clause {
+                        const C_NAME: &'static str = #c_name;
+                        const VARIABLE_LEN: bool = false;
+
+                        fn c_definition() -> ::std::string::String {
+                            ::std::string::String::from(#header)
+                        }
+                    }
+                });
+            }
+            let type_line = format!("{tag_ty} type; /* {type_name} */");
+            (
+                quote! { false #(|| #variables)* },
+                quote! {
+                    let mut out = ::std::format!("{}\n", #header);
+                    let mut union: ::std::vec::Vec<(::std::string::String, bool)> =
+                        ::std::vec::Vec::new();
+                    #(#union_members)*
+                    out.push_str(&::std::format!("typedef struct {}_s {{\n", #name_str));
+                    out.push_str(&::std::format!("    {}\n", #type_line));
+                    out.push_str("    union {\n");
+                    for line in c_lines(union, 2) {
+                        out.push_str(&line);
+                    }
+                    out.push_str("    } content;\n");
+                    out.push_str(&::std::format!("}} {};\n", #name_str));
+                    out
+                },
+            )
+        }
+        Data::Union(data) => {
+            return Err(Error::new(
+                data.union_token.span,
+                "CRecord can't be derived for unions",
+            ))
+        }
+    };
+
+    Ok(quote! {
+        impl #impl_generics CRecord for #name #ty_generics #where_clause {
+            const C_NAME: &'static str = #c_name;
+            const VARIABLE_LEN: bool = #variable;
+
+            fn c_definition() -> ::std::string::String {
+                /// Indents each member, and turns everything after the first
+                /// flexible array member into comments since C can't place
+                /// fields after it
+                fn c_lines(
+                    members: ::std::vec::Vec<(::std::string::String, bool)>,
+                    depth: usize,
+                ) -> ::std::vec::Vec<::std::string::String> {
+                    let indent = "    ".repeat(depth);
+                    let mut packed = false;
+                    let mut lines = ::std::vec::Vec::new();
+                    for (line, variable) in members {
+                        match (packed, line.split_once(" /* ")) {
+                            // C comments don't nest
+                            (true, Some((declaration, note))) => lines.push(::std::format!(
+                                "{}/* followed by: {} ({}) */\n",
+                                indent,
+                                declaration,
+                                note.trim_end_matches(" */")
+                            )),
+                            (true, None) => lines.push(::std::format!(
+                                "{}/* followed by: {} */\n",
+                                indent,
+                                line
+                            )),
+                            (false, _) => lines.push(::std::format!("{}{}\n", indent, line)),
+                        }
+                        packed |= variable;
+                    }
+                    lines
+                }
+
+                #definition
+            }
+        }
+    })
+}
+
+/// Whether any field makes the record variable length
+fn variable(fields: &[FieldSpec]) -> TokenStream {
+    let each = fields.iter().map(|field| match &field.kind {
+        Kind::Int(_) | Kind::Bool => quote!(false),
+        Kind::Bytes | Kind::Vec(_) => quote!(true),
+        Kind::Nested(ty) => quote!(<#ty as CRecord>::VARIABLE_LEN),
+    });
+    quote! { false #(|| #each)* }
+}
+
+/// An expression building `Vec<(String, bool)>` of member declarations and
+/// whether each one is variable length
+fn members(fields: &[FieldSpec]) -> TokenStream {
+    let each = fields.iter().map(|field| {
+        let name = &field.name;
+        let prefix = field.len.map(|width| {
+            let (suffix, what) = match field.kind {
+                Kind::Bytes => ("len", "bytes"),
+                _ => ("count", "elements"),
+            };
+            let terminator = match field.null_terminated {
+                true => ", including the terminator",
+                false => "",
+            };
+            let line = format!(
+                "{} {name}_{suffix}; /* {what} in {name}{terminator} */",
+                c_int(width.ty())
+            );
+            quote! { members.push((::std::string::String::from(#line), false)); }
+        });
+        let comment = match (field.len, field.null_terminated) {
+            (_, true) => " /* null terminated */",
+            (None, false) => " /* rest of the record */",
+            (Some(_), false) => "",
+        };
+        let member = match &field.kind {
+            Kind::Int(ty) => {
+                let line = format!("{} {name};", c_int(&ty.to_string()));
+                quote! { (::std::string::String::from(#line), false) }
+            }
+            Kind::Bool => {
+                let line = format!("Boolean {name};");
+                quote! { (::std::string::String::from(#line), false) }
+            }
+            Kind::Bytes => {
+                let line = format!("char {name}[];{comment}");
+                quote! { (::std::string::String::from(#line), true) }
+            }
+            Kind::Vec(ty) => {
+                let line = format!("{{}} {name}[];{comment}");
+                quote! { (::std::format!(#line, <#ty as CRecord>::C_NAME), true) }
+            }
+            Kind::Nested(ty) => {
+                let ty_name = quote!(#ty).to_string();
+                quote! {
+                    (
+                        match <#ty as CRecord>::C_NAME {
+                            #ty_name => ::std::format!("{} {};", #ty_name, #name),
+                            // fieldless enums are declared as their tag
+                            c_name => ::std::format!("{} {}; /* {} */", c_name, #name, #ty_name),
+                        },
+                        <#ty as CRecord>::VARIABLE_LEN,
+                    )
+                }
+            }
+        };
+        quote! {
+            #prefix
+            members.push(#member);
+        }
+    });
+    quote! {
+        {
+            #[allow(unused_mut)]
+            let mut members: ::std::vec::Vec<(::std::string::String, bool)> =
+                ::std::vec::Vec::new();
+            #(#each)*
+            members
+        }
+    }
+}
+
+/// The PalmOS name of a Rust integer type
+fn c_int(ty: &str) -> &'static str {
+    match ty {
+        "u8" => "UInt8",
+        "u16" => "UInt16",
+        "u32" => "UInt32",
+        "u64" => "UInt64",
+        "i8" => "Int8",
+        "i16" => "Int16",
+        "i32" => "Int32",
+        "i64" => "Int64",
+        _ => unreachable!("only called for integer types"),
+    }
+}
+
+fn snake_case(ident: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in ident.chars().enumerate() {
+        if c.is_uppercase() && i != 0 {
+            out.push('_');
+        }
+        out.extend(c.to_lowercase());
+    }
+    out
+}
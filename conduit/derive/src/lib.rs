@@ -0,0 +1,564 @@
+//! `#[derive(OnDevice)]` for the record types shared with the handheld.
+//!
+//! Fields are encoded in declaration order. Integers use the container's
+//! endianness (`#[on_device(endian = "big")]`, the default, or `"little"`),
+//! `bool` is a single byte, and any other type is encoded through its own
+//! `OnDevice` impl.
+//!
+//! Field attributes:
+//! - `len = "u8" | "u16" | "u32"`: `Vec` fields are prefixed with their
+//!   length (bytes for `Vec<u8>`, elements otherwise) in an integer of this
+//!   width. Without a prefix a `Vec` consumes the rest of the record.
+//! - `null_terminated`: a `Vec<u8>` is followed by a NUL byte, which is
+//!   counted by the length prefix and dropped when decoding.
+//!
+//! Enums take `#[on_device(discriminant = "u8" | "u16" | "u32")]` and each
+//! variant may set `#[on_device(tag = N)]`, otherwise tags count up from the
+//! previous variant starting at 0.
+//!
+//! `#[derive(CRecord)]` reads the same attributes to describe the encoding
+//! as a PalmOS C declaration.
+//!
+//! The generated impls name `OnDevice` and `CRecord` unqualified, so the
+//! traits must be in scope where the derives are used.
+
+use proc_macro2::{Span, TokenStream};
+use quote::{format_ident, quote, ToTokens};
+use syn::{
+    parse_macro_input, spanned::Spanned, Attribute, Data, DeriveInput, Error, Fields,
+    GenericArgument, Ident, LitInt, LitStr, PathArguments, Type,
+};
+
+mod c_record;
+
+#[proc_macro_derive(OnDevice, attributes(on_device))]
+pub fn derive_on_device(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+#[proc_macro_derive(CRecord, attributes(on_device))]
+pub fn derive_c_record(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    c_record::expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream> {
+    let container = ContainerAttrs::parse(&input.attrs)?;
+    let endian = container.endian.tokens();
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let (write, read) = match &input.data {
+        Data::Struct(data) => {
+            if let Some(discriminant) = container.discriminant {
+                return Err(Error::new(
+                    discriminant.span,
+                    "discriminant is only valid on enums",
+                ));
+            }
+            let fields = FieldSpec::parse_all(&data.fields)?;
+            let bindings = fields.iter().map(|f| &f.binding);
+            let pattern = destructure(&data.fields, bindings.clone());
+            let writes = fields.iter().map(|f| f.write(&endian));
+            let reads = fields.iter().map(|f| f.read(&endian));
+            let construct = destructure(&data.fields, bindings);
+            (
+                quote! {
+                    let Self #pattern = self;
+                    #(#writes)*
+                    ::std::result::Result::Ok(())
+                },
+                quote! {
+                    #(#reads)*
+                    ::std::result::Result::Ok(Self #construct)
+                },
+            )
+        }
+        Data::Enum(data) => {
+            let Some(discriminant) = container.discriminant else {
+                return Err(Error::new(
+                    Span::call_site(),
+                    "enums need #[on_device(discriminant = \"u8\" | \"u16\" | \"u32\")]",
+                ));
+            };
+            let write_tag = discriminant.width.write(&endian, quote!(tag));
+            let read_tag = discriminant.width.read(&endian);
+            let tag_ty = Ident::new(discriminant.width.ty(), Span::call_site());
+
+            let mut next_tag = 0_u64;
+            let mut write_arms = Vec::new();
+            let mut read_arms = Vec::new();
+            for variant in data.variants.iter() {
+                let tag = match VariantAttrs::parse(&variant.attrs)?.tag {
+                    Some(tag) => tag,
+                    None => next_tag,
+                };
+                next_tag = tag + 1;
+                let tag = LitInt::new(&format!("{tag}{tag_ty}"), variant.span());
+
+                let ident = &variant.ident;
+                let fields = FieldSpec::parse_all(&variant.fields)?;
+                let bindings = fields.iter().map(|f| &f.binding);
+                let pattern = destructure(&variant.fields, bindings.clone());
+                let writes = fields.iter().map(|f| f.write(&endian));
+                let reads = fields.iter().map(|f| f.read(&endian));
+                write_arms.push(quote! {
+                    Self::#ident #pattern => {
+                        let tag: #tag_ty = #tag;
+                        #write_tag
+                        #(#writes)*
+                    }
+                });
+                read_arms.push(quote! {
+                    #tag => {
+                        #(#reads)*
+                        ::std::result::Result::Ok(Self::#ident #pattern)
+                    }
+                });
+            }
+
+            let name_str = name.to_string();
+            (
+                quote! {
+                    match self {
+                        #(#write_arms)*
+                    }
+                    ::std::result::Result::Ok(())
+                },
+                quote! {
+                    let tag = { #read_tag };
+                    match tag {
+                        #(#read_arms)*
+                        _ => ::std::result::Result::Err(::std::io::Error::new(
+                            ::std::io::ErrorKind::InvalidData,
+                            ::std::format!("Invalid discriminant {} for {}", tag, #name_str),
+                        )),
+                    }
+                },
+            )
+        }
+        Data::Union(data) => {
+            return Err(Error::new(
+                data.union_token.span,
+                "OnDevice can't be derived for unions",
+            ))
+        }
+    };
+
+    Ok(quote! {
+        impl #impl_generics OnDevice for #name #ty_generics #where_clause {
+            fn write_hh(
+                &self,
+                cursor: &mut ::std::io::Cursor<::std::vec::Vec<u8>>,
+            ) -> ::std::io::Result<()> {
+                #[allow(unused_imports)]
+                use ::byteorder::{ReadBytesExt as _, WriteBytesExt as _};
+                #[allow(unused_imports)]
+                use ::std::io::{Read as _, Write as _};
+                #write
+            }
+
+            fn read_hh(cursor: &mut ::std::io::Cursor<&[u8]>) -> ::std::io::Result<Self> {
+                #[allow(unused_imports)]
+                use ::byteorder::{ReadBytesExt as _, WriteBytesExt as _};
+                #[allow(unused_imports)]
+                use ::std::io::{Read as _, Write as _};
+                #read
+            }
+        }
+    })
+}
+
+/// `{ a, b }` for named fields, `(field_0, field_1)` for tuples
+fn destructure<'a>(fields: &Fields, bindings: impl Iterator<Item = &'a Ident>) -> TokenStream {
+    match fields {
+        Fields::Named(_) => quote! { { #(#bindings),* } },
+        Fields::Unnamed(_) => quote! { ( #(#bindings),* ) },
+        Fields::Unit => TokenStream::new(),
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Endian {
+    Big,
+    Little,
+}
+
+impl Endian {
+    fn tokens(self) -> TokenStream {
+        match self {
+            Endian::Big => quote!(::byteorder::BigEndian),
+            Endian::Little => quote!(::byteorder::LittleEndian),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Width {
+    U8,
+    U16,
+    U32,
+}
+
+impl Width {
+    fn parse(lit: &LitStr) -> syn::Result<Self> {
+        match lit.value().as_str() {
+            "u8" => Ok(Width::U8),
+            "u16" => Ok(Width::U16),
+            "u32" => Ok(Width::U32),
+            _ => Err(Error::new(
+                lit.span(),
+                "expected \"u8\", \"u16\" or \"u32\"",
+            )),
+        }
+    }
+
+    fn ty(self) -> &'static str {
+        match self {
+            Width::U8 => "u8",
+            Width::U16 => "u16",
+            Width::U32 => "u32",
+        }
+    }
+
+    /// Writes `value`, which must already be of this width
+    fn write(self, endian: &TokenStream, value: TokenStream) -> TokenStream {
+        match self {
+            Width::U8 => quote! { cursor.write_u8(#value)?; },
+            Width::U16 => quote! { cursor.write_u16::<#endian>(#value)?; },
+            Width::U32 => quote! { cursor.write_u32::<#endian>(#value)?; },
+        }
+    }
+
+    fn read(self, endian: &TokenStream) -> TokenStream {
+        match self {
+            Width::U8 => quote! { cursor.read_u8()? },
+            Width::U16 => quote! { cursor.read_u16::<#endian>()? },
+            Width::U32 => quote! { cursor.read_u32::<#endian>()? },
+        }
+    }
+}
+
+struct Discriminant {
+    width: Width,
+    span: Span,
+}
+
+struct ContainerAttrs {
+    endian: Endian,
+    discriminant: Option<Discriminant>,
+}
+
+impl ContainerAttrs {
+    fn parse(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut ret = ContainerAttrs {
+            endian: Endian::Big,
+            discriminant: None,
+        };
+        for attr in attrs.iter().filter(|a| a.path().is_ident("on_device")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("endian") {
+                    let lit: LitStr = meta.value()?.parse()?;
+                    ret.endian = match lit.value().as_str() {
+                        "big" => Endian::Big,
+                        "little" => Endian::Little,
+                        _ => return Err(Error::new(lit.span(), "expected \"big\" or \"little\"")),
+                    };
+                    Ok(())
+                } else if meta.path.is_ident("discriminant") {
+                    let lit: LitStr = meta.value()?.parse()?;
+                    ret.discriminant = Some(Discriminant {
+                        width: Width::parse(&lit)?,
+                        span: lit.span(),
+                    });
+                    Ok(())
+                } else {
+                    Err(meta.error("unknown on_device container attribute"))
+                }
+            })?;
+        }
+        Ok(ret)
+    }
+}
+
+struct VariantAttrs {
+    tag: Option<u64>,
+}
+
+impl VariantAttrs {
+    fn parse(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut ret = VariantAttrs { tag: None };
+        for attr in attrs.iter().filter(|a| a.path().is_ident("on_device")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("tag") {
+                    let lit: LitInt = meta.value()?.parse()?;
+                    ret.tag = Some(lit.base10_parse()?);
+                    Ok(())
+                } else {
+                    Err(meta.error("unknown on_device variant attribute"))
+                }
+            })?;
+        }
+        Ok(ret)
+    }
+}
+
+enum Kind {
+    /// An integer read with `read_<ty>`
+    Int(Ident),
+    Bool,
+    Bytes,
+    Vec(Type),
+    Nested(Type),
+}
+
+struct FieldSpec {
+    binding: Ident,
+    name: String,
+    kind: Kind,
+    len: Option<Width>,
+    null_terminated: bool,
+}
+
+impl FieldSpec {
+    fn parse_all(fields: &Fields) -> syn::Result<Vec<Self>> {
+        fields
+            .iter()
+            .enumerate()
+            .map(|(i, field)| {
+                let binding = field
+                    .ident
+                    .clone()
+                    .unwrap_or_else(|| format_ident!("field_{}", i));
+                let mut spec = FieldSpec {
+                    name: binding.to_string(),
+                    binding,
+                    kind: Kind::of(&field.ty),
+                    len: None,
+                    null_terminated: false,
+                };
+                for attr in field
+                    .attrs
+                    .iter()
+                    .filter(|a| a.path().is_ident("on_device"))
+                {
+                    attr.parse_nested_meta(|meta| {
+                        if meta.path.is_ident("len") {
+                            spec.len = Some(Width::parse(&meta.value()?.parse()?)?);
+                            Ok(())
+                        } else if meta.path.is_ident("null_terminated") {
+                            spec.null_terminated = true;
+                            Ok(())
+                        } else {
+                            Err(meta.error("unknown on_device field attribute"))
+                        }
+                    })?;
+                }
+
+                match spec.kind {
+                    Kind::Bytes | Kind::Vec(_) => (),
+                    _ if spec.len.is_some() => {
+                        return Err(Error::new(field.span(), "len is only valid on Vec fields"))
+                    }
+                    _ => (),
+                }
+                if spec.null_terminated && !matches!(spec.kind, Kind::Bytes) {
+                    return Err(Error::new(
+                        field.span(),
+                        "null_terminated is only valid on Vec<u8> fields",
+                    ));
+                }
+                Ok(spec)
+            })
+            .collect()
+    }
+
+    fn write(&self, endian: &TokenStream) -> TokenStream {
+        let binding = &self.binding;
+        match &self.kind {
+            Kind::Int(ty) if ty == "u8" || ty == "i8" => {
+                let write = format_ident!("write_{}", ty);
+                quote! { cursor.#write(*#binding)?; }
+            }
+            Kind::Int(ty) => {
+                let write = format_ident!("write_{}", ty);
+                quote! { cursor.#write::<#endian>(*#binding)?; }
+            }
+            Kind::Bool => quote! { cursor.write_u8(u8::from(*#binding))?; },
+            Kind::Bytes => {
+                let terminator = self.null_terminated as usize;
+                let prefix = self.write_len(endian, quote!(#binding.len() + #terminator));
+                let nul = self
+                    .null_terminated
+                    .then(|| quote! { cursor.write_u8(0)?; });
+                quote! {
+                    #prefix
+                    cursor.write_all(#binding)?;
+                    #nul
+                }
+            }
+            Kind::Vec(_) => {
+                let prefix = self.write_len(endian, quote!(#binding.len()));
+                quote! {
+                    #prefix
+                    for item in #binding.iter() {
+                        OnDevice::write_hh(item, cursor)?;
+                    }
+                }
+            }
+            Kind::Nested(_) => quote! { OnDevice::write_hh(#binding, cursor)?; },
+        }
+    }
+
+    fn write_len(&self, endian: &TokenStream, len: TokenStream) -> Option<TokenStream> {
+        let width = self.len?;
+        let ty = Ident::new(width.ty(), Span::call_site());
+        let write = width.write(endian, quote!(len));
+        let message = format!("{} is too long for a {} length", self.name, width.ty());
+        Some(quote! {
+            let len = #ty::try_from(#len).map_err(|_| {
+                ::std::io::Error::new(::std::io::ErrorKind::InvalidInput, #message)
+            })?;
+            #write
+        })
+    }
+
+    fn read(&self, endian: &TokenStream) -> TokenStream {
+        let binding = &self.binding;
+        let remaining = quote! {
+            (cursor.get_ref().len() as u64).saturating_sub(cursor.position()) as usize
+        };
+        let len = self.len.map(|width| width.read(endian));
+        match &self.kind {
+            Kind::Int(ty) if ty == "u8" || ty == "i8" => {
+                let read = format_ident!("read_{}", ty);
+                quote! { let #binding = cursor.#read()?; }
+            }
+            Kind::Int(ty) => {
+                let read = format_ident!("read_{}", ty);
+                quote! { let #binding = cursor.#read::<#endian>()?; }
+            }
+            Kind::Bool => {
+                // anything else wouldn't encode back to the same byte
+                let invalid = format!("{} is {{}}, bools are 0 or 1", self.name);
+                quote! {
+                    let #binding = match cursor.read_u8()? {
+                        0 => false,
+                        1 => true,
+                        other => {
+                            return ::std::result::Result::Err(::std::io::Error::new(
+                                ::std::io::ErrorKind::InvalidData,
+                                ::std::format!(#invalid, other),
+                            ))
+                        }
+                    };
+                }
+            }
+            Kind::Bytes => {
+                let oversize = format!(
+                    "{} claims {{}} bytes but only {{}} are left in the record",
+                    self.name
+                );
+                let read = match (len, self.null_terminated) {
+                    // checked against what's left so a corrupt length can't
+                    // allocate more than the record holds
+                    (Some(len), _) => quote! {
+                        let len = #len as usize;
+                        let remaining = #remaining;
+                        if len > remaining {
+                            return ::std::result::Result::Err(::std::io::Error::new(
+                                ::std::io::ErrorKind::InvalidData,
+                                ::std::format!(#oversize, len, remaining),
+                            ));
+                        }
+                        let mut #binding = ::std::vec![0_u8; len];
+                        cursor.read_exact(&mut #binding)?;
+                    },
+                    (None, true) => quote! {
+                        let mut #binding = ::std::vec::Vec::new();
+                        loop {
+                            match cursor.read_u8()? {
+                                0 => {
+                                    #binding.push(0);
+                                    break;
+                                }
+                                byte => #binding.push(byte),
+                            }
+                        }
+                    },
+                    (None, false) => quote! {
+                        let mut #binding = ::std::vec![0_u8; #remaining];
+                        cursor.read_exact(&mut #binding)?;
+                    },
+                };
+                let message = format!("{} is not null terminated", self.name);
+                let strip = self.null_terminated.then(|| {
+                    quote! {
+                        if #binding.pop() != ::std::option::Option::Some(0) {
+                            return ::std::result::Result::Err(::std::io::Error::new(
+                                ::std::io::ErrorKind::InvalidData,
+                                #message,
+                            ));
+                        }
+                    }
+                });
+                quote! {
+                    #read
+                    #strip
+                }
+            }
+            Kind::Vec(ty) => match len {
+                Some(len) => quote! {
+                    let count = #len as usize;
+                    let mut #binding = ::std::vec::Vec::new();
+                    for _ in 0..count {
+                        #binding.push(<#ty as OnDevice>::read_hh(cursor)?);
+                    }
+                },
+                None => quote! {
+                    let mut #binding = ::std::vec::Vec::new();
+                    while #remaining > 0 {
+                        #binding.push(<#ty as OnDevice>::read_hh(cursor)?);
+                    }
+                },
+            },
+            Kind::Nested(ty) => quote! { let #binding = <#ty as OnDevice>::read_hh(cursor)?; },
+        }
+    }
+}
+
+impl Kind {
+    fn of(ty: &Type) -> Self {
+        let Type::Path(path) = ty else {
+            return Kind::Nested(ty.clone());
+        };
+        let Some(last) = path.path.segments.last() else {
+            return Kind::Nested(ty.clone());
+        };
+        let ident = last.ident.to_string();
+        match ident.as_str() {
+            "u8" | "u16" | "u32" | "u64" | "i8" | "i16" | "i32" | "i64" => {
+                Kind::Int(last.ident.clone())
+            }
+            "bool" => Kind::Bool,
+            "Vec" => match &last.arguments {
+                PathArguments::AngleBracketed(args) => match args.args.first() {
+                    Some(GenericArgument::Type(inner))
+                        if inner.to_token_stream().to_string() == "u8" =>
+                    {
+                        Kind::Bytes
+                    }
+                    Some(GenericArgument::Type(inner)) => Kind::Vec(inner.clone()),
+                    _ => Kind::Nested(ty.clone()),
+                },
+                _ => Kind::Nested(ty.clone()),
+            },
+            _ => Kind::Nested(ty.clone()),
+        }
+    }
+}
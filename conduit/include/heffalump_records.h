@@ -0,0 +1,238 @@
+/* Generated from conduit/src/heffalump_hh_types.rs, do not edit.
+ *
+ * Records are packed and big endian. Members after a flexible array
+ * are listed in comments and have to be read at runtime.
+ *
+ * The device app reports HEFFALUMP_SCHEMA_VERSION in a TootWrite of type
+ * TootWriteType_DeviceSchema on every sync, and the conduit writes
+ * HeffalumpPrefs for that version from the following sync on, or for
+ * version 0 after a sync without the report. Version 0 prefs have no schema
+ * header and start at home_timeline_len, and only version 5 and later have
+ * the extra timelines. HeffalumpNotificationsDB is only written for version 3 and
+ * later, HeffalumpConversationsDB for 4 and later. HeffalumpAuthorDB holds
+ * TootProfile records from version 8, TootAccount ones in version 7 and
+ * TootAuthor ones before. HeffalumpContentDB holds TootContentV3 records
+ * from version 11, TootContentV2 ones in versions 9 and 10 and TootContent
+ * ones before. HeffalumpPollsDB is only written for version 10 and later,
+ * HeffalumpStatusDB for 12 and later.
+ */
+
+#ifndef HEFFALUMP_RECORDS_H
+#define HEFFALUMP_RECORDS_H
+
+#include <PalmOS.h>
+
+#define HEFFALUMP_SCHEMA_VERSION 12
+#define HEFFALUMP_NO_CONTENT 0xffff
+#define HEFFALUMP_HIDDEN_VOTES 0xffffffff
+
+typedef struct SchemaHeader_s {
+    UInt16 version;
+    UInt16 features;
+} SchemaHeader;
+
+typedef struct TootContent_s {
+    UInt16 author;
+    UInt16 is_reply_to;
+    UInt16 replies_start;
+    UInt16 contents_len; /* bytes in contents */
+    char contents[];
+} TootContent;
+
+typedef struct TootContentV2_s {
+    UInt16 author;
+    UInt16 is_reply_to;
+    UInt16 replies_start;
+    Boolean sensitive;
+    UInt8 warning_len; /* bytes in warning */
+    char warning[];
+    /* followed by: UInt16 contents_len; (bytes in contents) */
+    /* followed by: char contents[]; */
+} TootContentV2;
+
+typedef struct TootContentV3_s {
+    UInt16 author;
+    UInt16 is_reply_to;
+    UInt16 replies_start;
+    UInt16 parent;
+    UInt8 depth;
+    Boolean ancestor;
+    Boolean sensitive;
+    UInt8 warning_len; /* bytes in warning */
+    char warning[];
+    /* followed by: UInt16 contents_len; (bytes in contents) */
+    /* followed by: char contents[]; */
+} TootContentV3;
+
+typedef struct TootAuthor_s {
+    UInt8 author_name_len; /* bytes in author_name */
+    char author_name[];
+} TootAuthor;
+
+typedef struct TootAccount_s {
+    UInt8 author_name_len; /* bytes in author_name, including the terminator */
+    char author_name[]; /* null terminated */
+    /* followed by: UInt8 handle_len; (bytes in handle, including the terminator) */
+    /* followed by: char handle[]; (null terminated) */
+    /* followed by: UInt8 display_name_len; (bytes in display_name, including the terminator) */
+    /* followed by: char display_name[]; (null terminated) */
+    /* followed by: UInt8 account_id_len; (bytes in account_id, including the terminator) */
+    /* followed by: char account_id[]; (null terminated) */
+} TootAccount;
+
+typedef struct TootProfile_s {
+    TootAccount account;
+    /* followed by: UInt32 followers_count; */
+    /* followed by: UInt32 following_count; */
+    /* followed by: Boolean bot; */
+    /* followed by: Boolean locked; */
+    /* followed by: Boolean following; */
+    /* followed by: UInt16 bio_len; (bytes in bio) */
+    /* followed by: char bio[]; */
+} TootProfile;
+
+typedef struct FetchLimits_s {
+    UInt16 home_timeline;
+    UInt16 self_timeline;
+    UInt16 replies;
+} FetchLimits;
+
+typedef struct DirectReply_s {
+    UInt16 message;
+    UInt16 contents_len; /* bytes in contents */
+    char contents[];
+} DirectReply;
+
+typedef struct PollVote_s {
+    UInt16 content;
+    UInt8 choices_len; /* bytes in choices */
+    char choices[];
+} PollVote;
+
+typedef enum {
+    TootWriteType_Favorite = 0,
+    TootWriteType_Follow = 1,
+    TootWriteType_Reblog = 2,
+    TootWriteType_Toot = 3,
+    TootWriteType_DeviceSchema = 4,
+    TootWriteType_FetchLimits = 5,
+    TootWriteType_DirectReply = 6,
+    TootWriteType_Bookmark = 7,
+    TootWriteType_Unbookmark = 8,
+    TootWriteType_FollowAuthor = 9,
+    TootWriteType_UnfollowAuthor = 10,
+    TootWriteType_TootV2 = 11,
+    TootWriteType_Vote = 12,
+} TootWriteType;
+
+typedef struct TootWrite_s {
+    UInt16 type; /* TootWriteType */
+    union {
+        UInt16 favorite;
+        UInt16 follow;
+        UInt16 reblog;
+        TootContent toot;
+        SchemaHeader device_schema;
+        FetchLimits fetch_limits;
+        DirectReply direct_reply;
+        UInt16 bookmark;
+        UInt16 unbookmark;
+        UInt16 follow_author;
+        UInt16 unfollow_author;
+        TootContentV2 toot_v2;
+        PollVote vote;
+    } content;
+} TootWrite;
+
+typedef struct ExtraTimeline_s {
+    UInt16 start;
+    UInt16 timeline_len;
+    UInt8 title_len; /* bytes in title */
+    char title[];
+} ExtraTimeline;
+
+typedef struct HeffalumpPrefs_s {
+    SchemaHeader schema;
+    UInt16 home_timeline_len;
+    UInt16 self_timeline_len;
+    UInt16 reply_content_len;
+    UInt8 timelines_count; /* elements in timelines */
+    ExtraTimeline timelines[];
+} HeffalumpPrefs;
+
+typedef enum {
+    NotificationKind_Mention = 0,
+    NotificationKind_Favourite = 1,
+    NotificationKind_Reblog = 2,
+    NotificationKind_Follow = 3,
+    NotificationKind_PollEnded = 4,
+} NotificationKind;
+
+typedef struct TootNotification_s {
+    UInt8 kind; /* NotificationKind */
+    UInt16 author;
+    UInt16 content;
+    UInt32 created;
+    UInt16 excerpt_len; /* bytes in excerpt */
+    char excerpt[];
+} TootNotification;
+
+typedef struct TootMessage_s {
+    UInt16 conversation;
+    UInt16 author;
+    UInt8 participants_count; /* elements in participants */
+    UInt16 participants[];
+    /* followed by: Boolean unread; */
+    /* followed by: UInt16 contents_len; (bytes in contents) */
+    /* followed by: char contents[]; */
+} TootMessage;
+
+typedef struct PollOption_s {
+    UInt32 votes_count;
+    UInt8 title_len; /* bytes in title */
+    char title[];
+} PollOption;
+
+typedef struct TootPoll_s {
+    UInt16 content;
+    UInt32 expires;
+    Boolean expired;
+    Boolean multiple;
+    Boolean voted;
+    UInt32 votes_count;
+    UInt8 options_count; /* elements in options */
+    PollOption options[];
+} TootPoll;
+
+typedef enum {
+    SyncSource_Home = 0,
+    SyncSource_SelfTimeline = 1,
+    SyncSource_Bookmarks = 2,
+    SyncSource_Favourites = 3,
+    SyncSource_List = 4,
+    SyncSource_Hashtag = 5,
+    SyncSource_LocalTimeline = 6,
+    SyncSource_FederatedTimeline = 7,
+    SyncSource_Replies = 8,
+    SyncSource_Notifications = 9,
+    SyncSource_Conversations = 10,
+    SyncSource_Following = 11,
+    SyncSource_Record = 12,
+} SyncSource;
+
+typedef struct SyncFailure_s {
+    UInt8 source; /* SyncSource */
+    Boolean cached;
+    UInt8 title_len; /* bytes in title */
+    char title[];
+    /* followed by: UInt8 error_len; (bytes in error) */
+    /* followed by: char error[]; */
+} SyncFailure;
+
+typedef struct SyncStatus_s {
+    UInt32 synced;
+    UInt8 failures_count; /* elements in failures */
+    SyncFailure failures[];
+} SyncStatus;
+
+#endif /* HEFFALUMP_RECORDS_H */
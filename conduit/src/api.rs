@@ -0,0 +1,239 @@
+//! The subset of `Megalodon` the conduit syncs through. Everything in
+//! `download` and `upload` goes through this trait so the tests can swap in
+//! the offline backend from `mock`.
+
+use async_trait::async_trait;
+use megalodon::{
+    entities::{Account, Context, Conversation, List, Notification, Poll, Relationship, Status},
+    error::Error,
+    megalodon::{
+        GetAccountStatusesInputOptions, GetBookmarksInputOptions,
+        GetConversationTimelineInputOptions, GetFavouritesInputOptions,
+        GetListTimelineInputOptions, GetNotificationsInputOptions, GetStatusContextInputOptions,
+        GetTimelineOptionsWithLocal, PostStatusInputOptions, PostStatusOutput,
+    },
+    response::Response,
+    Megalodon,
+};
+
+#[async_trait]
+pub(crate) trait MastodonApi: Send + Sync {
+    async fn verify_account_credentials(&self) -> Result<Response<Account>, Error>;
+
+    async fn get_home_timeline(
+        &self,
+        options: Option<&GetTimelineOptionsWithLocal>,
+    ) -> Result<Response<Vec<Status>>, Error>;
+
+    async fn get_account_statuses(
+        &self,
+        id: String,
+        options: Option<&GetAccountStatusesInputOptions>,
+    ) -> Result<Response<Vec<Status>>, Error>;
+
+    async fn get_status_context(
+        &self,
+        id: String,
+        options: Option<&GetStatusContextInputOptions>,
+    ) -> Result<Response<Context>, Error>;
+
+    async fn get_notifications(
+        &self,
+        options: Option<&GetNotificationsInputOptions>,
+    ) -> Result<Response<Vec<Notification>>, Error>;
+
+    async fn get_conversation_timeline(
+        &self,
+        options: Option<&GetConversationTimelineInputOptions>,
+    ) -> Result<Response<Vec<Conversation>>, Error>;
+
+    async fn get_lists(&self) -> Result<Response<Vec<List>>, Error>;
+
+    async fn get_list_timeline(
+        &self,
+        list_id: String,
+        options: Option<&GetListTimelineInputOptions>,
+    ) -> Result<Response<Vec<Status>>, Error>;
+
+    async fn get_tag_timeline(
+        &self,
+        hashtag: String,
+        options: Option<&GetTimelineOptionsWithLocal>,
+    ) -> Result<Response<Vec<Status>>, Error>;
+
+    /// The federated timeline, or the local one with `local` set
+    async fn get_public_timeline(
+        &self,
+        options: Option<&GetTimelineOptionsWithLocal>,
+    ) -> Result<Response<Vec<Status>>, Error>;
+
+    /// Paged through the `Link` header, `max_id` isn't a status id
+    async fn get_bookmarks(
+        &self,
+        options: Option<&GetBookmarksInputOptions>,
+    ) -> Result<Response<Vec<Status>>, Error>;
+
+    /// Paged through the `Link` header, `max_id` isn't a status id
+    async fn get_favourites(
+        &self,
+        options: Option<&GetFavouritesInputOptions>,
+    ) -> Result<Response<Vec<Status>>, Error>;
+
+    async fn favourite_status(&self, id: String) -> Result<Response<Status>, Error>;
+
+    async fn reblog_status(&self, id: String) -> Result<Response<Status>, Error>;
+
+    async fn bookmark_status(&self, id: String) -> Result<Response<Status>, Error>;
+
+    async fn unbookmark_status(&self, id: String) -> Result<Response<Status>, Error>;
+
+    async fn post_status(
+        &self,
+        status: String,
+        options: Option<&PostStatusInputOptions>,
+    ) -> Result<Response<PostStatusOutput>, Error>;
+
+    async fn get_relationships(
+        &self,
+        ids: Vec<String>,
+    ) -> Result<Response<Vec<Relationship>>, Error>;
+
+    async fn follow_account(&self, id: String) -> Result<Response<Relationship>, Error>;
+
+    async fn unfollow_account(&self, id: String) -> Result<Response<Relationship>, Error>;
+
+    /// `choices` are indices into the poll's options
+    async fn vote_poll(&self, id: String, choices: Vec<u32>) -> Result<Response<Poll>, Error>;
+}
+
+/// A live instance, reached through megalodon
+pub(crate) struct Remote(pub(crate) Box<dyn Megalodon + Send + Sync>);
+
+#[async_trait]
+impl MastodonApi for Remote {
+    async fn verify_account_credentials(&self) -> Result<Response<Account>, Error> {
+        self.0.verify_account_credentials().await
+    }
+
+    async fn get_home_timeline(
+        &self,
+        options: Option<&GetTimelineOptionsWithLocal>,
+    ) -> Result<Response<Vec<Status>>, Error> {
+        self.0.get_home_timeline(options).await
+    }
+
+    async fn get_account_statuses(
+        &self,
+        id: String,
+        options: Option<&GetAccountStatusesInputOptions>,
+    ) -> Result<Response<Vec<Status>>, Error> {
+        self.0.get_account_statuses(id, options).await
+    }
+
+    async fn get_status_context(
+        &self,
+        id: String,
+        options: Option<&GetStatusContextInputOptions>,
+    ) -> Result<Response<Context>, Error> {
+        self.0.get_status_context(id, options).await
+    }
+
+    async fn get_notifications(
+        &self,
+        options: Option<&GetNotificationsInputOptions>,
+    ) -> Result<Response<Vec<Notification>>, Error> {
+        self.0.get_notifications(options).await
+    }
+
+    async fn get_conversation_timeline(
+        &self,
+        options: Option<&GetConversationTimelineInputOptions>,
+    ) -> Result<Response<Vec<Conversation>>, Error> {
+        self.0.get_conversation_timeline(options).await
+    }
+
+    async fn get_lists(&self) -> Result<Response<Vec<List>>, Error> {
+        self.0.get_lists().await
+    }
+
+    async fn get_list_timeline(
+        &self,
+        list_id: String,
+        options: Option<&GetListTimelineInputOptions>,
+    ) -> Result<Response<Vec<Status>>, Error> {
+        self.0.get_list_timeline(list_id, options).await
+    }
+
+    async fn get_tag_timeline(
+        &self,
+        hashtag: String,
+        options: Option<&GetTimelineOptionsWithLocal>,
+    ) -> Result<Response<Vec<Status>>, Error> {
+        self.0.get_tag_timeline(hashtag, options).await
+    }
+
+    async fn get_public_timeline(
+        &self,
+        options: Option<&GetTimelineOptionsWithLocal>,
+    ) -> Result<Response<Vec<Status>>, Error> {
+        self.0.get_public_timeline(options).await
+    }
+
+    async fn get_bookmarks(
+        &self,
+        options: Option<&GetBookmarksInputOptions>,
+    ) -> Result<Response<Vec<Status>>, Error> {
+        self.0.get_bookmarks(options).await
+    }
+
+    async fn get_favourites(
+        &self,
+        options: Option<&GetFavouritesInputOptions>,
+    ) -> Result<Response<Vec<Status>>, Error> {
+        self.0.get_favourites(options).await
+    }
+
+    async fn favourite_status(&self, id: String) -> Result<Response<Status>, Error> {
+        self.0.favourite_status(id).await
+    }
+
+    async fn reblog_status(&self, id: String) -> Result<Response<Status>, Error> {
+        self.0.reblog_status(id).await
+    }
+
+    async fn bookmark_status(&self, id: String) -> Result<Response<Status>, Error> {
+        self.0.bookmark_status(id).await
+    }
+
+    async fn unbookmark_status(&self, id: String) -> Result<Response<Status>, Error> {
+        self.0.unbookmark_status(id).await
+    }
+
+    async fn post_status(
+        &self,
+        status: String,
+        options: Option<&PostStatusInputOptions>,
+    ) -> Result<Response<PostStatusOutput>, Error> {
+        self.0.post_status(status, options).await
+    }
+
+    async fn get_relationships(
+        &self,
+        ids: Vec<String>,
+    ) -> Result<Response<Vec<Relationship>>, Error> {
+        self.0.get_relationships(ids).await
+    }
+
+    async fn follow_account(&self, id: String) -> Result<Response<Relationship>, Error> {
+        self.0.follow_account(id, None).await
+    }
+
+    async fn unfollow_account(&self, id: String) -> Result<Response<Relationship>, Error> {
+        self.0.unfollow_account(id).await
+    }
+
+    async fn vote_poll(&self, id: String, choices: Vec<u32>) -> Result<Response<Poll>, Error> {
+        // the status id is only needed by Misskey
+        self.0.vote_poll(id, choices, None).await
+    }
+}
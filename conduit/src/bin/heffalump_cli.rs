@@ -0,0 +1,117 @@
+use std::{path::PathBuf, process::ExitCode};
+
+use heffalump_conduit::{
+    headless, headless::HeadlessSync, inspect, platform, writes_gen::WritesSpec,
+};
+use simplelog::*;
+
+const USAGE: &str = "\
+usage: heffalump_cli configure [<data-dir>]
+       heffalump_cli sync [<data-dir>] [--config <file>] [--out <dir>]
+       heffalump_cli inspect [<dir>] [--json]
+       heffalump_cli writes <spec.json|spec.toml> [--out <file>]
+
+commands:
+    configure   log in to a mastodon instance and write heffalump_config.json
+    sync        build HeffalumpContentDB.pdb and HeffalumpAuthorDB.pdb without HotSync Manager,
+                applying HeffalumpWritesDB.pdb from <data-dir> if present
+    inspect     decode the Heffalump databases in <dir> and print their thread structure
+    writes      build a HeffalumpWritesDB.pdb from a description of handheld actions
+
+<data-dir> defaults to the platform data directory";
+
+fn main() -> ExitCode {
+    TermLogger::init(
+        LevelFilter::Info,
+        Config::default(),
+        TerminalMode::Stderr,
+        ColorChoice::Auto,
+    )
+    .unwrap();
+
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let result = match args.first().map(String::as_str) {
+        Some("configure") => configure(&args[1..]),
+        Some("sync") => sync(&args[1..]),
+        Some("inspect") => inspect(&args[1..]),
+        Some("writes") => writes(&args[1..]),
+        _ => {
+            eprintln!("{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn configure(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let data_dir = data_dir(args.first())?;
+    std::fs::create_dir_all(&data_dir)?;
+    headless::configure(&HeadlessSync::new(data_dir).config)
+}
+
+fn sync(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = args.iter().peekable();
+    let data_dir = data_dir(args.next_if(|arg| !arg.starts_with("--")))?;
+    let mut sync = HeadlessSync::new(data_dir);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => sync = sync.with_config(flag_value(arg, args.next())?),
+            "--out" => sync = sync.with_out_dir(flag_value(arg, args.next())?),
+            _ => return Err(format!("unexpected argument {arg}\n{USAGE}").into()),
+        }
+    }
+    sync.run()
+}
+
+fn inspect(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = args.iter().peekable();
+    let dir = data_dir(args.next_if(|arg| !arg.starts_with("--")))?;
+    let mut json = false;
+    for arg in args {
+        match arg.as_str() {
+            "--json" => json = true,
+            _ => return Err(format!("unexpected argument {arg}\n{USAGE}").into()),
+        }
+    }
+
+    let dump = inspect::inspect_dir(&dir)?;
+    match json {
+        true => println!("{}", serde_json::to_string_pretty(&dump)?),
+        false => print!("{dump}"),
+    }
+    Ok(())
+}
+
+fn writes(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = args.iter();
+    let spec = WritesSpec::from_file(&PathBuf::from(args.next().ok_or(USAGE)?))?;
+    let mut out = PathBuf::from("HeffalumpWritesDB.pdb");
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--out" => out = flag_value(arg, args.next())?,
+            _ => return Err(format!("unexpected argument {arg}\n{USAGE}").into()),
+        }
+    }
+    std::fs::write(&out, spec.to_pdb()?)?;
+    println!("Wrote {} writes to {}", spec.writes.len(), out.display());
+    Ok(())
+}
+
+fn data_dir(arg: Option<&String>) -> Result<PathBuf, String> {
+    arg.map(PathBuf::from)
+        .or_else(platform::default_data_dir)
+        .ok_or_else(|| format!("could not determine a data directory\n{USAGE}"))
+}
+
+fn flag_value(flag: &str, value: Option<&String>) -> Result<PathBuf, String> {
+    value
+        .map(PathBuf::from)
+        .ok_or_else(|| format!("{flag} requires a value"))
+}
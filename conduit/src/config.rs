@@ -1,15 +1,280 @@
-use log::{debug, info};
+use log::{debug, info, warn};
 use megalodon::megalodon::AppInputOptions;
+use serde::{Deserialize, Serialize};
 use std::{
     io::{stdin, Error, ErrorKind::Other},
     path::Path,
 };
-use winapi::um::consoleapi;
 
-use crate::MASTODON_APP_NAME;
+use crate::{heffalump_hh_types::FetchLimits, platform, MASTODON_APP_NAME, MAX_CONTENT_RECORDS};
+
+/// Most toots per timeline. The timelines and their threads together also
+/// have to fit in the content database's u16 indices, see `validate`.
+const MAX_TIMELINE_LIMIT: u32 = 800;
+const MAX_REPLIES_LIMIT: u32 = 40;
+/// The device indents each level, deeper threads wouldn't fit its screen
+const MAX_REPLIES_DEPTH: u32 = 8;
+/// Conversations come in a single page, Mastodon caps those at 40
+const MAX_CONVERSATIONS_LIMIT: u32 = 40;
+/// Each list or hashtag is another timeline's worth of toots in the content
+/// database
+const MAX_LISTS: usize = 8;
+const MAX_HASHTAGS: usize = 8;
+
+/// `heffalump_config.json`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Config {
+    pub(crate) instance: String,
+    pub(crate) access_token: String,
+    #[serde(default)]
+    pub(crate) fetch: FetchConfig,
+}
+
+/// What to download on each sync. Unset fields keep their defaults.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct FetchConfig {
+    pub(crate) home_timeline: bool,
+    pub(crate) home_timeline_limit: u32,
+    pub(crate) self_timeline: bool,
+    pub(crate) self_timeline_limit: u32,
+    pub(crate) replies: bool,
+    /// Replies kept for each toot in the timelines, at all levels
+    pub(crate) replies_limit: u32,
+    /// Levels of replies to replies kept, 1 keeps only the direct replies
+    pub(crate) replies_depth: u32,
+    /// Replies kept to each toot of a thread
+    pub(crate) replies_breadth: u32,
+    /// Toots a reply in the timelines answers, kept closest first. Only
+    /// synced to devices on schema 11 or later.
+    pub(crate) ancestors_limit: u32,
+    /// Conversation lookups in one sync, for the direct messages first and
+    /// then the timeline toots. Toots without replies or ancestors don't
+    /// need one, the newest are looked up first.
+    pub(crate) replies_requests: u32,
+    /// Only synced to devices on schema 3 or later
+    pub(crate) notifications: bool,
+    pub(crate) notifications_limit: u32,
+    /// Direct messages, only synced to devices on schema 4 or later
+    pub(crate) conversations: bool,
+    pub(crate) conversations_limit: u32,
+    /// What the user bookmarked, as a timeline of its own. Like the other
+    /// extra timelines only synced to devices on schema 5 or later.
+    pub(crate) bookmarks: bool,
+    pub(crate) bookmarks_limit: u32,
+    /// What the user favourited
+    pub(crate) favourites: bool,
+    pub(crate) favourites_limit: u32,
+    /// Titles of Mastodon lists to sync as timelines of their own
+    pub(crate) lists: Vec<String>,
+    /// Toots fetched for each list
+    pub(crate) lists_limit: u32,
+    /// Hashtags to sync as timelines of their own, with or without the `#`
+    pub(crate) hashtags: Vec<String>,
+    /// Toots fetched for each hashtag
+    pub(crate) hashtags_limit: u32,
+    /// Everyone on the instance
+    pub(crate) local_timeline: bool,
+    pub(crate) local_timeline_limit: u32,
+    /// Everyone the instance knows about
+    pub(crate) federated_timeline: bool,
+    pub(crate) federated_timeline_limit: u32,
+}
+
+impl Default for FetchConfig {
+    fn default() -> Self {
+        Self {
+            home_timeline: true,
+            home_timeline_limit: 100,
+            self_timeline: true,
+            self_timeline_limit: 40,
+            replies: true,
+            replies_limit: 10,
+            replies_depth: 4,
+            replies_breadth: 10,
+            ancestors_limit: 5,
+            replies_requests: 60,
+            notifications: true,
+            notifications_limit: 40,
+            conversations: true,
+            conversations_limit: 20,
+            bookmarks: true,
+            bookmarks_limit: 20,
+            favourites: true,
+            favourites_limit: 20,
+            lists: Vec::new(),
+            lists_limit: 40,
+            hashtags: Vec::new(),
+            hashtags_limit: 20,
+            local_timeline: false,
+            local_timeline_limit: 40,
+            federated_timeline: false,
+            federated_timeline_limit: 40,
+        }
+    }
+}
+
+impl FetchConfig {
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        for (name, limit, max) in [
+            (
+                "home_timeline_limit",
+                self.home_timeline_limit,
+                MAX_TIMELINE_LIMIT,
+            ),
+            (
+                "self_timeline_limit",
+                self.self_timeline_limit,
+                MAX_TIMELINE_LIMIT,
+            ),
+            ("replies_limit", self.replies_limit, MAX_REPLIES_LIMIT),
+            ("replies_depth", self.replies_depth, MAX_REPLIES_DEPTH),
+            ("replies_breadth", self.replies_breadth, MAX_REPLIES_LIMIT),
+            ("ancestors_limit", self.ancestors_limit, MAX_REPLIES_LIMIT),
+            (
+                "replies_requests",
+                self.replies_requests,
+                MAX_TIMELINE_LIMIT,
+            ),
+            (
+                "notifications_limit",
+                self.notifications_limit,
+                MAX_TIMELINE_LIMIT,
+            ),
+            (
+                "conversations_limit",
+                self.conversations_limit,
+                MAX_CONVERSATIONS_LIMIT,
+            ),
+            ("bookmarks_limit", self.bookmarks_limit, MAX_TIMELINE_LIMIT),
+            (
+                "favourites_limit",
+                self.favourites_limit,
+                MAX_TIMELINE_LIMIT,
+            ),
+            ("lists_limit", self.lists_limit, MAX_TIMELINE_LIMIT),
+            ("hashtags_limit", self.hashtags_limit, MAX_TIMELINE_LIMIT),
+            (
+                "local_timeline_limit",
+                self.local_timeline_limit,
+                MAX_TIMELINE_LIMIT,
+            ),
+            (
+                "federated_timeline_limit",
+                self.federated_timeline_limit,
+                MAX_TIMELINE_LIMIT,
+            ),
+        ] {
+            if limit > max {
+                return Err(Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("fetch.{name} is {limit}, the most is {max}"),
+                ));
+            }
+        }
+        for (name, count, max) in [
+            ("lists", self.lists.len(), MAX_LISTS),
+            ("hashtags", self.hashtags.len(), MAX_HASHTAGS),
+        ] {
+            if count > max {
+                return Err(Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("fetch.{name} has {count} {name}, the most is {max}"),
+                ));
+            }
+        }
+        // every timeline toot and each thread looked up is a content record
+        let timelines = [
+            (self.home_timeline, self.home_timeline_limit),
+            (self.self_timeline, self.self_timeline_limit),
+            (self.bookmarks, self.bookmarks_limit),
+            (self.favourites, self.favourites_limit),
+            (self.local_timeline, self.local_timeline_limit),
+            (self.federated_timeline, self.federated_timeline_limit),
+        ]
+        .into_iter()
+        .filter(|&(enabled, _)| enabled)
+        .map(|(_, limit)| limit as usize)
+        .sum::<usize>()
+            + self.lists.len() * self.lists_limit as usize
+            + self.hashtags.len() * self.hashtags_limit as usize;
+        let threads = match self.replies {
+            true => {
+                timelines.min(self.replies_requests as usize)
+                    * (self.replies_limit + self.ancestors_limit) as usize
+            }
+            false => 0,
+        };
+        if timelines + threads > MAX_CONTENT_RECORDS {
+            return Err(Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "fetch limits add up to {} toots with their replies, the most is {MAX_CONTENT_RECORDS}",
+                    timelines + threads
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Applies the limits the device asked for, which win over the config
+    /// file but are still capped. The config file's limits are kept when the
+    /// device's would add up to more content records than fit.
+    pub(crate) fn with_device_limits(self, limits: Option<FetchLimits>) -> Self {
+        let Some(limits) = limits else {
+            return self;
+        };
+        let mut device = self.clone();
+        for (limit, requested, max) in [
+            (
+                &mut device.home_timeline_limit,
+                limits.home_timeline,
+                MAX_TIMELINE_LIMIT,
+            ),
+            (
+                &mut device.self_timeline_limit,
+                limits.self_timeline,
+                MAX_TIMELINE_LIMIT,
+            ),
+            (&mut device.replies_limit, limits.replies, MAX_REPLIES_LIMIT),
+        ] {
+            if requested == 0 {
+                continue;
+            }
+            if requested as u32 > max {
+                warn!("Device asked for {requested} toots, capping at {max}");
+            }
+            *limit = (requested as u32).min(max);
+        }
+        match device.validate() {
+            Ok(()) => device,
+            Err(e) => {
+                warn!("Ignoring the device's fetch limits: {e}");
+                self
+            }
+        }
+    }
+}
+
+pub(crate) fn parse_config(contents: &str) -> Result<Config, Box<dyn std::error::Error>> {
+    let config = match serde_json::from_str(contents)? {
+        // versions before 0.3 wrote the credentials as a bare pair
+        value @ serde_json::Value::Array(_) => {
+            let (instance, access_token) = serde_json::from_value(value)?;
+            Config {
+                instance,
+                access_token,
+                fetch: FetchConfig::default(),
+            }
+        }
+        value => serde_json::from_value(value)?,
+    };
+    config.fetch.validate()?;
+    Ok(config)
+}
 
 pub async fn configure(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
-    unsafe { consoleapi::AllocConsole() };
+    platform::alloc_console();
     println!("On what instance is your mastodon account? (e.g. mastodon.social, hachyderm.io)");
     let mut instance = String::new();
     stdin().read_line(&mut instance).map_err(Box::new)?;
@@ -35,13 +300,12 @@ pub async fn configure(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
             "Failed to retrieve URL for app registration",
         ))
     })?;
-    info!("Attempting to open {} for app registration.", &url);
-    open::that(url).map_err(Box::new)?; // open a browser to log in and retrieve token
+    platform::open_browser(&url).map_err(Box::new)?; // open a browser to log in and retrieve token
 
     println!("Please paste (ctrl + v) the authorization code generated for Heffalump:");
     let mut input = String::new();
     stdin().read_line(&mut input).map_err(Box::new)?;
-    unsafe { winapi::um::wincon::FreeConsole() };
+    platform::free_console();
     debug!("{}", input.trim_end().to_string());
 
     let token_data = unauthenticated
@@ -72,10 +336,120 @@ pub async fn configure(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
 
     debug!("verified authenticated client");
 
-    let to_ser = (instance, token_data.access_token);
-    let serialized = serde_json::to_string(&to_ser).map_err(Box::new)?;
+    let to_ser = Config {
+        instance,
+        access_token: token_data.access_token,
+        fetch: FetchConfig::default(),
+    };
+    let serialized = serde_json::to_string_pretty(&to_ser).map_err(Box::new)?;
     std::fs::write(path, serialized).map_err(Box::new)?;
 
     info!("Completed writing credentials");
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        config::{parse_config, FetchConfig},
+        heffalump_hh_types::FetchLimits,
+    };
+
+    #[test]
+    fn test_legacy_config() {
+        let config = parse_config(r#"["mastodon.social", "token"]"#).unwrap();
+        assert_eq!(config.instance, "mastodon.social");
+        assert_eq!(config.access_token, "token");
+        assert_eq!(config.fetch, FetchConfig::default());
+    }
+
+    #[test]
+    fn test_fetch_config() {
+        let config = parse_config(
+            r#"{
+                "instance": "mastodon.social",
+                "access_token": "token",
+                "fetch": { "home_timeline_limit": 30, "replies": false }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.fetch,
+            FetchConfig {
+                home_timeline_limit: 30,
+                replies: false,
+                ..Default::default()
+            }
+        );
+
+        let invalid = |fetch: &str| {
+            parse_config(&format!(
+                r#"{{ "instance": "a", "access_token": "b", "fetch": {fetch} }}"#
+            ))
+            .unwrap_err()
+            .to_string()
+        };
+        assert_eq!(
+            invalid(r#"{ "replies_limit": 41 }"#),
+            "fetch.replies_limit is 41, the most is 40"
+        );
+        assert_eq!(
+            invalid(r#"{ "replies_depth": 9 }"#),
+            "fetch.replies_depth is 9, the most is 8"
+        );
+        assert_eq!(
+            invalid(r#"{ "notifications_limit": 801 }"#),
+            "fetch.notifications_limit is 801, the most is 800"
+        );
+        assert_eq!(
+            invalid(r#"{ "lists": ["1", "2", "3", "4", "5", "6", "7", "8", "9"] }"#),
+            "fetch.lists has 9 lists, the most is 8"
+        );
+        assert_eq!(
+            invalid(r#"{ "local_timeline_limit": 900 }"#),
+            "fetch.local_timeline_limit is 900, the most is 800"
+        );
+        assert_eq!(
+            invalid(
+                r#"{ "replies_limit": 40, "ancestors_limit": 40, "replies_requests": 800,
+                     "lists": ["1", "2", "3", "4", "5", "6", "7", "8"], "lists_limit": 800 }"#
+            ),
+            "fetch limits add up to 70580 toots with their replies, the most is 65535"
+        );
+        assert!(invalid(r#"{ "home_limit": 10 }"#).contains("unknown field"));
+    }
+
+    #[test]
+    fn test_device_limits() {
+        let fetch = FetchConfig::default().with_device_limits(Some(FetchLimits {
+            home_timeline: 20,
+            self_timeline: 0,
+            replies: 1000,
+        }));
+        assert_eq!(fetch.home_timeline_limit, 20);
+        assert_eq!(fetch.self_timeline_limit, 40);
+        assert_eq!(fetch.replies_limit, 40);
+        assert_eq!(
+            FetchConfig::default().with_device_limits(None),
+            FetchConfig::default()
+        );
+
+        // more replies per thread would take more content records than fit
+        let config = FetchConfig {
+            home_timeline_limit: 800,
+            self_timeline_limit: 800,
+            lists: vec![String::from("1"); 8],
+            lists_limit: 800,
+            replies_requests: 800,
+            ancestors_limit: 40,
+            ..Default::default()
+        };
+        config.validate().unwrap();
+        let fetch = config.clone().with_device_limits(Some(FetchLimits {
+            home_timeline: 0,
+            self_timeline: 0,
+            replies: 40,
+        }));
+        assert_eq!(fetch, config);
+    }
+}
@@ -0,0 +1,134 @@
+//! What the device app reports about itself through `HeffalumpWritesDB`.
+//! Those records arrive after the sync's databases were built, so they're
+//! kept in `heffalump_device.json` and used from the next sync on. The schema
+//! is reported every sync, so a device app downgraded to a build without the
+//! header goes back to the original layout once it stops reporting one.
+
+use std::path::Path;
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    heffalump_hh_types::{FetchLimits, SchemaHeader, TootWrite},
+    DEVICE_FILE,
+};
+
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct DeviceReport {
+    /// `None` for device apps that predate the schema header
+    #[serde(default)]
+    pub(crate) schema: Option<SchemaHeader>,
+    #[serde(default)]
+    pub(crate) fetch_limits: Option<FetchLimits>,
+}
+
+impl DeviceReport {
+    /// The last report from the device synced into `dir`
+    pub(crate) fn read(dir: &Path) -> Self {
+        let path = dir.join(DEVICE_FILE);
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Self::default(),
+            Err(e) => {
+                warn!("Failed to read {}: {e}", path.display());
+                return Self::default();
+            }
+        };
+        serde_json::from_str(&contents).unwrap_or_else(|e| {
+            warn!("Ignoring malformed {}: {e}", path.display());
+            Self::default()
+        })
+    }
+
+    pub(crate) fn save(&self, dir: &Path) -> std::io::Result<()> {
+        info!("Device reports {:?}", self);
+        std::fs::write(dir.join(DEVICE_FILE), serde_json::to_string(self)?)
+    }
+
+    /// Moves the device's reports out of `writes`, returning whether the
+    /// report changed and the writes left for Mastodon. A schema that isn't
+    /// reported again is forgotten.
+    pub(crate) fn take_from(&mut self, writes: Vec<TootWrite>) -> (bool, Vec<TootWrite>) {
+        let previous = self.clone();
+        self.schema = None;
+        let mut remaining = Vec::new();
+        for write in writes {
+            match write {
+                TootWrite::DeviceSchema(schema) => self.schema = Some(schema),
+                TootWrite::FetchLimits(limits) => self.fetch_limits = Some(limits),
+                write => remaining.push(write),
+            }
+        }
+        (*self != previous, remaining)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use palmrs::database::record::pdb_record::RecordAttributes;
+
+    use crate::{
+        device::DeviceReport,
+        heffalump_hh_types::{FetchLimits, OnDevice, SchemaHeader, TootWrite},
+        mock::{MockMastodon, TempDir},
+        upload::apply_writes,
+    };
+
+    #[tokio::test]
+    async fn test_device_report_is_saved() {
+        let dir = TempDir::new("device");
+        assert_eq!(DeviceReport::read(dir.path()), DeviceReport::default());
+
+        let schema = SchemaHeader {
+            version: 2,
+            features: 3,
+        };
+        let limits = FetchLimits {
+            home_timeline: 20,
+            self_timeline: 0,
+            replies: 2,
+        };
+        let records = [
+            TootWrite::DeviceSchema(schema),
+            TootWrite::FetchLimits(limits),
+        ]
+        .iter()
+        .map(|write| (write.to_hh_bytes().unwrap(), RecordAttributes::default(), 1))
+        .collect();
+        let client = MockMastodon::new("me");
+        apply_writes(&client, dir.path(), records).await.unwrap();
+        let saved = DeviceReport::read(dir.path());
+
+        assert_eq!(saved.schema, Some(schema));
+        assert_eq!(saved.fetch_limits, Some(limits));
+        assert!(client.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_device_schema_expires() {
+        let dir = TempDir::new("device_expiry");
+        let limits = FetchLimits {
+            home_timeline: 20,
+            self_timeline: 0,
+            replies: 2,
+        };
+        DeviceReport {
+            schema: Some(SchemaHeader {
+                version: 11,
+                features: 0,
+            }),
+            fetch_limits: Some(limits),
+        }
+        .save(dir.path())
+        .unwrap();
+
+        // a downgraded device app that no longer reports its schema
+        let client = MockMastodon::new("me");
+        apply_writes(&client, dir.path(), Vec::new()).await.unwrap();
+        let saved = DeviceReport::read(dir.path());
+
+        assert_eq!(saved.schema, None);
+        assert_eq!(saved.fetch_limits, Some(limits));
+    }
+}
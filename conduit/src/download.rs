@@ -1,189 +1,902 @@
+use futures::{stream, StreamExt};
 use html2text::render::text_renderer::{TaggedLine, TextDecorator};
 use log::{error, info, warn};
 use megalodon::{
-    entities::{Attachment, Status},
+    entities::{
+        notification::NotificationType, Account, Attachment, Context, Notification, Status,
+        StatusVisibility,
+    },
     megalodon::{
-        GetAccountStatusesInputOptions, GetStatusContextInputOptions, GetTimelineOptionsWithLocal,
+        GetAccountStatusesInputOptions, GetBookmarksInputOptions,
+        GetConversationTimelineInputOptions, GetFavouritesInputOptions,
+        GetListTimelineInputOptions, GetNotificationsInputOptions, GetTimelineOptionsWithLocal,
     },
-    Megalodon,
 };
-use std::time::Duration;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::{
+    api::{MastodonApi, Remote},
+    heffalump_hh_types::NotificationKind,
+    paginator::{paginate, Direction},
+    rate_limit::RateLimited,
+    sync_state::HomeGap,
+    MASTODON_APP_NAME,
+};
+
+/// Messages kept per conversation, the newest ones
+const CONVERSATION_MESSAGES: usize = 20;
+/// Accounts asked about in one relationships request
+const RELATIONSHIPS_PER_REQUEST: usize = 40;
+/// Conversation lookups in flight at once
+const CONTEXT_REQUESTS_AT_ONCE: usize = 4;
+
+/// Titles of the extra timelines the user doesn't name
+pub(crate) const BOOKMARKS_TITLE: &str = "Bookmarks";
+pub(crate) const FAVOURITES_TITLE: &str = "Favourites";
+pub(crate) const LOCAL_TITLE: &str = "Local";
+pub(crate) const FEDERATED_TITLE: &str = "Federated";
+
+/// A notification the device can show, parsed like the timelines' toots
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ParsedNotification {
+    pub(crate) kind: NotificationKind,
+    pub(crate) author: ParsedAuthor,
+    /// Unix time
+    pub(crate) created: i64,
+    pub(crate) status_id: Option<String>,
+    /// The toot's text, empty for follows
+    pub(crate) excerpt: String,
+}
+
+/// Direct messages between the user and the same set of people, possibly
+/// from several Mastodon conversations
+#[derive(Debug, Clone)]
+pub(crate) struct ParsedConversation {
+    /// Everyone but the user, by `ParsedAuthor::key`
+    pub(crate) participants: BTreeMap<String, ParsedAuthor>,
+    pub(crate) unread: bool,
+    /// Oldest first
+    pub(crate) messages: Vec<ParsedToot>,
+    pub(crate) raw: Vec<Status>,
+}
+
+/// A list, hashtag or public timeline, parsed like the home timeline
+#[derive(Debug, Clone)]
+pub(crate) struct ParsedTimeline {
+    /// What the device shows it as
+    pub(crate) title: String,
+    pub(crate) contents: Vec<ParsedToot>,
+    pub(crate) raw: Vec<Status>,
+}
+
+/// How much of each timeline toot's conversation `replies` keeps
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ThreadLimits {
+    pub(crate) ancestors: usize,
+    /// Levels of replies, 1 for only the direct ones
+    pub(crate) depth: usize,
+    /// Replies to each toot
+    pub(crate) breadth: usize,
+    /// Replies at all levels together
+    pub(crate) replies: usize,
+    /// Conversations looked up in the sync, toots past it get no thread
+    pub(crate) requests: usize,
+}
+
+/// The conversation around a timeline toot. Its positions count the
+/// ancestors first, then the replies.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ParsedThread {
+    /// Oldest first, the last one is what the timeline toot replies to
+    pub(crate) ancestors: Vec<ThreadToot>,
+    /// Depth first, each reply followed by the replies to it
+    pub(crate) replies: Vec<ThreadToot>,
+}
+
+impl ParsedThread {
+    pub(crate) fn len(&self) -> usize {
+        self.ancestors.len() + self.replies.len()
+    }
+
+    /// Ancestors then replies, in position order
+    pub(crate) fn toots(&self) -> impl Iterator<Item = &ThreadToot> {
+        self.ancestors.iter().chain(self.replies.iter())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct ThreadToot {
+    pub(crate) toot: ParsedToot,
+    pub(crate) raw: Status,
+    pub(crate) reply_to: ReplyTo,
+    /// 0 for ancestors, 1 for replies to the timeline toot
+    pub(crate) depth: u8,
+}
+
+/// What a toot in a thread replies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ReplyTo {
+    /// The timeline toot the thread belongs to
+    Toot,
+    /// The toot at this position of the thread
+    Thread(usize),
+    /// Nothing that was synced
+    Unsynced,
+}
+
+/// A status as the device shows it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ParsedToot {
+    pub(crate) author: ParsedAuthor,
+    pub(crate) content: String,
+    /// The content warning, empty without one
+    pub(crate) warning: String,
+    /// Whether `content` should start out collapsed, for content warnings
+    /// and toots marked sensitive
+    pub(crate) sensitive: bool,
+    pub(crate) poll: Option<ParsedPoll>,
+}
+
+/// A poll attached to a toot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ParsedPoll {
+    /// Unix time, `None` for polls that stay open
+    pub(crate) expires: Option<i64>,
+    pub(crate) expired: bool,
+    pub(crate) multiple: bool,
+    pub(crate) voted: bool,
+    pub(crate) votes_count: u32,
+    /// Each option's title and votes, `None` while the results are hidden
+    pub(crate) options: Vec<(String, Option<u32>)>,
+}
 
-use crate::MASTODON_APP_NAME;
+/// The account a toot, notification or message is from. Usernames are only
+/// unique per instance, so authors are told apart by `handle`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct ParsedAuthor {
+    /// `user@instance`, with the instance for local accounts too
+    pub(crate) handle: String,
+    pub(crate) display_name: String,
+    pub(crate) id: String,
+    pub(crate) bio: String,
+    pub(crate) followers_count: u32,
+    pub(crate) following_count: u32,
+    pub(crate) bot: bool,
+    pub(crate) locked: bool,
+    /// Who boosted the toot onto the timeline
+    pub(crate) via: Option<Box<ParsedAuthor>>,
+}
 
-pub fn get_client(
-    mastodon_instance: String,
-    access_token: String,
-) -> Box<dyn Megalodon + Send + Sync> {
+impl ParsedAuthor {
+    fn new(account: &Account) -> Self {
+        let handle = match account.acct.contains('@') {
+            true => account.acct.clone(),
+            // local accounts' acct leaves out the instance, their url has it
+            false => match account
+                .url
+                .split("://")
+                .nth(1)
+                .and_then(|rest| rest.split('/').next())
+            {
+                Some(instance) => format!("{}@{}", account.acct, instance),
+                None => account.acct.clone(),
+            },
+        };
+        ParsedAuthor {
+            handle,
+            display_name: account.display_name.clone(),
+            id: account.id.clone(),
+            bio: to_text(&account.note),
+            followers_count: account.followers_count,
+            following_count: account.following_count,
+            bot: account.bot.unwrap_or(false),
+            locked: account.locked,
+            via: None,
+        }
+    }
+
+    /// Identifies the author in the author database, boosts by both accounts
+    pub(crate) fn key(&self) -> String {
+        match &self.via {
+            Some(via) => format!("{} via {}", self.handle, via.key()),
+            None => self.handle.clone(),
+        }
+    }
+
+    fn username(&self) -> &str {
+        self.handle.split('@').next().unwrap()
+    }
+
+    /// `@user`, or `@user@instance` when the username is one of `ambiguous`
+    pub(crate) fn shown_as(&self, ambiguous: &HashSet<&str>) -> String {
+        let name = match ambiguous.contains(self.username()) {
+            true => format!("@{}", self.handle),
+            false => format!("@{}", self.username()),
+        };
+        match &self.via {
+            Some(via) => format!("{} via {}", name, via.shown_as(ambiguous)),
+            None => name,
+        }
+    }
+}
+
+/// The usernames that more than one instance's account in `authors` (or
+/// whoever boosted them) has
+pub(crate) fn ambiguous_usernames<'a>(
+    authors: impl IntoIterator<Item = &'a ParsedAuthor>,
+) -> HashSet<&'a str> {
+    let mut handles = HashMap::new();
+    let mut ambiguous = HashSet::new();
+    let mut pending = authors.into_iter().collect::<Vec<_>>();
+    while let Some(author) = pending.pop() {
+        pending.extend(author.via.as_deref());
+        let previous = handles.insert(author.username(), &author.handle);
+        if previous.is_some_and(|other| other != &author.handle) {
+            ambiguous.insert(author.username());
+        }
+    }
+    ambiguous
+}
+
+/// The timelines `timeline` pages through
+#[derive(Debug, Clone, Copy)]
+enum Source<'a> {
+    Home,
+    /// By list id
+    List(&'a str),
+    /// Without the `#`
+    Tag(&'a str),
+    /// Only this instance's toots when `local`, otherwise the federated
+    /// timeline
+    Public {
+        local: bool,
+    },
+    Bookmarks,
+    Favourites,
+}
+
+impl Source<'_> {
+    /// Whether the next page is found through the `Link` header rather than
+    /// the oldest status id
+    fn linked(self) -> bool {
+        matches!(self, Source::Bookmarks | Source::Favourites)
+    }
+}
+
+pub fn get_client(mastodon_instance: String, access_token: String) -> Box<dyn MastodonApi> {
     let full_instance_url = format!("https://{}/", mastodon_instance);
-    megalodon::generator(
+    Box::new(RateLimited::new(Remote(megalodon::generator(
         megalodon::SNS::Mastodon,
         full_instance_url,
         Some(access_token),
         Some(String::from(MASTODON_APP_NAME)),
-    )
+    ))))
 }
 
+/// The newest `count` toots of the home timeline. Replies are only kept
+/// `with_replies`, when the device can show what they reply to.
 pub async fn feed(
-    client: &(dyn Megalodon + Send + Sync),
+    client: &dyn MastodonApi,
     count: u32,
-) -> Result<(Vec<(String, String)>, Vec<Status>), megalodon::error::Error> {
+    with_replies: bool,
+) -> Result<(Vec<ParsedToot>, Vec<Status>), megalodon::error::Error> {
+    timeline(client, Source::Home, count, with_replies).await
+}
+
+/// The newest `count` toots of each list in `titles`, in that order, or why
+/// that list failed. Titles the account has no list for are skipped, and
+/// when the lists can't be found at all none of them are downloaded.
+pub(crate) async fn lists(
+    client: &dyn MastodonApi,
+    titles: &[String],
+    count: u32,
+    with_replies: bool,
+) -> Result<Vec<(String, Result<ParsedTimeline, megalodon::error::Error>)>, megalodon::error::Error>
+{
+    let available = client
+        .get_lists()
+        .await
+        .inspect_err(|e| error!("Error while downloading lists: {}", e))?
+        .json();
+
     let mut res = Vec::new();
-    let mut prev_limit: u32 = 0;
-    while res.len() != count as usize {
-        let options: GetTimelineOptionsWithLocal = GetTimelineOptionsWithLocal {
-            only_media: None,
-            limit: Some(count - res.len() as u32),
-            max_id: res.iter().last().map(|t: &Status| t.id.clone()),
-            since_id: None,
-            min_id: None,
-            local: None,
+    for title in titles {
+        let Some(list) = available.iter().find(|l| &l.title == title) else {
+            warn!("No list called {title}, skipping it");
+            continue;
         };
+        let list = timeline(client, Source::List(&list.id), count, with_replies)
+            .await
+            .map(|(contents, raw)| {
+                info!("{} toots in list {}", raw.len(), title);
+                ParsedTimeline {
+                    title: title.clone(),
+                    contents,
+                    raw,
+                }
+            });
+        res.push((title.clone(), list));
+    }
 
-        info!(
-            "prev_limit = {} options.limit = {} res.len() = {}",
-            prev_limit,
-            options.limit.unwrap(),
-            res.len()
-        );
+    Ok(res)
+}
 
-        // If prev_limit == options.limit it means that the server is not
-        // capable of satisfying our request (requests limit reached or
-        // not enough posts to download), hence we break.
-        if options.limit.unwrap() == prev_limit {
-            break;
-        }
+/// The newest `count` toots of each hashtag in `tags` with its title, in
+/// that order, or why that hashtag failed
+pub(crate) async fn hashtags(
+    client: &dyn MastodonApi,
+    tags: &[String],
+    count: u32,
+    with_replies: bool,
+) -> Vec<(String, Result<ParsedTimeline, megalodon::error::Error>)> {
+    let mut res = Vec::new();
+    for tag in tags {
+        let tag = tag.trim_start_matches('#');
+        let title = format!("#{tag}");
+        let hashtag = timeline(client, Source::Tag(tag), count, with_replies)
+            .await
+            .map(|(contents, raw)| {
+                info!("{} toots tagged #{}", raw.len(), tag);
+                ParsedTimeline {
+                    title: title.clone(),
+                    contents,
+                    raw,
+                }
+            });
+        res.push((title, hashtag));
+    }
 
-        let server_response = client.get_home_timeline(Some(&options)).await;
-        let mut tmp = match server_response {
-            Ok(ok) => ok.json(),
-            Err(megalodon::error::Error::RequestError(r))
-                if r.status() == Some(http::StatusCode::TOO_MANY_REQUESTS) =>
-            {
-                warn!("received 429, sleeping");
-                tokio::time::sleep(Duration::from_millis(100)).await;
-                continue;
-            }
-            Err(err) => {
-                return Err(err)
-                    .inspect_err(|e| error!("Error while downloading timeline posts: {}", e))
+    res
+}
+
+/// The newest `count` toots of the local timeline, or the federated one
+pub(crate) async fn public(
+    client: &dyn MastodonApi,
+    local: bool,
+    count: u32,
+    with_replies: bool,
+) -> Result<ParsedTimeline, megalodon::error::Error> {
+    let (contents, raw) = timeline(client, Source::Public { local }, count, with_replies).await?;
+    let title = match local {
+        true => LOCAL_TITLE,
+        false => FEDERATED_TITLE,
+    };
+    Ok(ParsedTimeline {
+        title: String::from(title),
+        contents,
+        raw,
+    })
+}
+
+/// The `count` toots the user bookmarked most recently. They were picked on
+/// purpose, so replies are kept.
+pub(crate) async fn bookmarks(
+    client: &dyn MastodonApi,
+    count: u32,
+) -> Result<ParsedTimeline, megalodon::error::Error> {
+    let (contents, raw) = timeline(client, Source::Bookmarks, count, true).await?;
+    Ok(ParsedTimeline {
+        title: String::from(BOOKMARKS_TITLE),
+        contents,
+        raw,
+    })
+}
+
+/// The `count` toots the user favourited most recently, replies too
+pub(crate) async fn favourites(
+    client: &dyn MastodonApi,
+    count: u32,
+) -> Result<ParsedTimeline, megalodon::error::Error> {
+    let (contents, raw) = timeline(client, Source::Favourites, count, true).await?;
+    Ok(ParsedTimeline {
+        title: String::from(FAVOURITES_TITLE),
+        contents,
+        raw,
+    })
+}
+
+async fn timeline(
+    client: &dyn MastodonApi,
+    source: Source<'_>,
+    count: u32,
+    with_replies: bool,
+) -> Result<(Vec<ParsedToot>, Vec<Status>), megalodon::error::Error> {
+    let direction = match source.linked() {
+        true => Direction::Linked,
+        false => Direction::Older,
+    };
+    let res = paginate(
+        direction,
+        count,
+        move |page| async move {
+            let options = GetTimelineOptionsWithLocal {
+                only_media: None,
+                limit: Some(page.limit),
+                max_id: page.max_id,
+                since_id: None,
+                min_id: None,
+                local: matches!(source, Source::Public { local: true }).then_some(true),
+            };
+            match source {
+                Source::Home => client.get_home_timeline(Some(&options)).await,
+                Source::List(id) => {
+                    let options = GetListTimelineInputOptions {
+                        limit: options.limit,
+                        max_id: options.max_id.clone(),
+                        since_id: None,
+                        min_id: None,
+                    };
+                    client
+                        .get_list_timeline(id.to_string(), Some(&options))
+                        .await
+                }
+                Source::Tag(tag) => {
+                    client
+                        .get_tag_timeline(tag.to_string(), Some(&options))
+                        .await
+                }
+                Source::Public { .. } => client.get_public_timeline(Some(&options)).await,
+                Source::Bookmarks => {
+                    let options = GetBookmarksInputOptions {
+                        limit: options.limit,
+                        max_id: options.max_id.clone(),
+                        since_id: None,
+                        min_id: None,
+                    };
+                    client.get_bookmarks(Some(&options)).await
+                }
+                Source::Favourites => {
+                    let options = GetFavouritesInputOptions {
+                        limit: options.limit,
+                        max_id: options.max_id.clone(),
+                        min_id: None,
+                    };
+                    client.get_favourites(Some(&options)).await
+                }
             }
-        };
+        },
+        // without their ancestors replies make no sense in a feed
+        |p: &Status| with_replies || p.in_reply_to_account_id.is_none(),
+    )
+    .await
+    .inspect_err(|e| error!("Error while downloading timeline posts: {}", e))?;
 
-        prev_limit = options.limit.unwrap();
+    Ok((res.iter().map(parsed_toot).collect(), res))
+}
 
-        if tmp.len() == 0 {
-            break;
+/// Fetches the home timeline newer than `since_id` from the newest, then
+/// spends what's left of `count` on the `gaps` earlier syncs ran out of
+/// budget for, newest first. The new and backfilled statuses are merged with
+/// `previous` (the last sync's home timeline) newest first, then cut down to
+/// `count`. Also returns the stretches that are still missing. Replies are only kept
+/// `with_replies`, like in `feed`.
+pub(crate) async fn feed_since(
+    client: &dyn MastodonApi,
+    count: u32,
+    since_id: &str,
+    gaps: Vec<HomeGap>,
+    previous: Vec<Status>,
+    with_replies: bool,
+) -> Result<(Vec<ParsedToot>, Vec<Status>, Vec<HomeGap>), megalodon::error::Error> {
+    let stretches = std::iter::once((since_id.to_string(), None))
+        .chain(gaps.into_iter().map(|gap| (gap.after, Some(gap.before))));
+    let mut res = Vec::new();
+    let mut missing = Vec::new();
+    for (after, before) in stretches {
+        let budget = count - res.len() as u32;
+        // one over the budget tells a stretch that didn't fit from one that
+        // ran out just as it got back to `after`
+        let mut fetched = match budget {
+            0 => Vec::new(),
+            _ => paginate(
+                Direction::Since {
+                    since_id: after.clone(),
+                    max_id: before.clone(),
+                },
+                budget + 1,
+                |page| async move {
+                    let options = GetTimelineOptionsWithLocal {
+                        only_media: None,
+                        limit: Some(page.limit),
+                        max_id: page.max_id,
+                        since_id: page.since_id,
+                        min_id: None,
+                        local: None,
+                    };
+                    client.get_home_timeline(Some(&options)).await
+                },
+                |p: &Status| with_replies || p.in_reply_to_account_id.is_none(),
+            )
+            .await
+            .inspect_err(|e| error!("Error while downloading timeline posts: {}", e))?,
+        };
+        if fetched.len() as u32 > budget || budget == 0 {
+            fetched.truncate(budget as usize);
+            let before = fetched.last().map(|s| s.id.clone()).or(before);
+            missing.extend(before.map(|before| HomeGap {
+                after: after.clone(),
+                before,
+            }));
         }
-        // dont show replies in main feed
-        tmp.retain(|p| p.in_reply_to_account_id.is_none());
-        res.extend(tmp);
+        res.extend(fetched);
     }
+    info!(
+        "{} new statuses since {}, {} stretches left to backfill",
+        res.len(),
+        since_id,
+        missing.len()
+    );
 
-    Ok((res.iter().map(parsed_toot).collect(), res))
+    res.extend(previous);
+    res.sort_by(|a, b| id_cmp(&b.id, &a.id));
+    res.dedup_by(|a, b| a.id == b.id);
+    res.truncate(count as usize);
+
+    Ok((res.iter().map(parsed_toot).collect(), res, missing))
 }
 
 pub async fn self_posts(
-    client: &(dyn Megalodon + Send + Sync),
+    client: &dyn MastodonApi,
     count: u32,
-) -> Result<(Vec<(String, String)>, Vec<Status>), megalodon::error::Error> {
+) -> Result<(Vec<ParsedToot>, Vec<Status>), megalodon::error::Error> {
     let acct = client.verify_account_credentials().await?;
-    let mut res = Vec::new();
-    while res.len() != count as usize {
-        let options = GetAccountStatusesInputOptions {
-            only_media: None,
-            limit: Some(count - res.len() as u32),
-            max_id: None,
-            since_id: None,
-            pinned: None,
-            exclude_replies: None,
-            exclude_reblogs: None,
-        };
-        let tmp = client
-            .get_account_statuses(acct.json.id.clone(), Some(&options))
-            .await;
-
-        let tmp = match tmp {
-            Ok(ok) => ok.json(),
-            Err(megalodon::error::Error::RequestError(r))
-                if r.status() == Some(http::StatusCode::TOO_MANY_REQUESTS) =>
-            {
-                warn!("recieved 429, sleeping");
-                tokio::time::sleep(Duration::from_millis(100)).await;
-                continue;
-            }
-            Err(err) => {
-                return Err(err)
-                    .inspect_err(|e| error!("Error while downloading self posts: {}", e))
-            }
-        };
-
-        res.extend(tmp);
-    }
+    let id = &acct.json.id;
+    let res = paginate(
+        Direction::Older,
+        count,
+        |page| async move {
+            let options = GetAccountStatusesInputOptions {
+                only_media: None,
+                limit: Some(page.limit),
+                max_id: page.max_id,
+                since_id: None,
+                pinned: None,
+                exclude_replies: None,
+                exclude_reblogs: None,
+            };
+            client
+                .get_account_statuses(id.clone(), Some(&options))
+                .await
+        },
+        |_| true,
+    )
+    .await
+    .inspect_err(|e| error!("Error while downloading self posts: {}", e))?;
 
     Ok((res.iter().map(parsed_toot).collect(), res))
 }
 
-pub async fn replies(
-    client: &(dyn Megalodon + Send + Sync),
+/// The previous sync's copy of a timeline, parsed again for when
+/// downloading it fails
+pub(crate) fn from_cache(raw: Vec<Status>) -> (Vec<ParsedToot>, Vec<Status>) {
+    (raw.iter().map(parsed_toot).collect(), raw)
+}
+
+/// The conversation around each of `posts`, cut down to `limits`, or why
+/// its lookup failed. Only toots with replies or ancestors to keep are
+/// looked up, a few at a time.
+pub(crate) async fn replies(
+    client: &dyn MastodonApi,
     posts: impl Iterator<Item = &Status>,
-    max_replies_each: usize,
-) -> Result<Vec<(Vec<(String, String)>, Vec<Status>)>, megalodon::error::Error> {
+    limits: ThreadLimits,
+) -> Vec<Result<ParsedThread, megalodon::error::Error>> {
     info!("Getting replies");
-    let mut statuses = Vec::new();
-    let options = GetStatusContextInputOptions {
-        limit: Some(max_replies_each as u32),
+    // a boost's conversation is the boosted toot's
+    let posts = posts
+        .map(|post| post.reblog.as_deref().unwrap_or(post))
+        .collect::<Vec<_>>();
+    let wanted = posts.iter().filter(|post| has_thread(post, limits)).count();
+    if wanted > limits.requests {
+        warn!(
+            "{wanted} toots have a conversation, only looking up the first {}",
+            limits.requests
+        );
+    }
+
+    let mut budget = limits.requests;
+    stream::iter(posts)
+        .map(|post| {
+            let lookup = budget > 0 && has_thread(post, limits);
+            budget -= usize::from(lookup);
+            async move {
+                match lookup {
+                    true => client
+                        .get_status_context(post.id.clone(), None)
+                        .await
+                        .inspect_err(|e| {
+                            error!("Error while downloading the replies to {}: {}", post.id, e)
+                        })
+                        .map(|context| parsed_thread(&post.id, context.json(), limits)),
+                    false => Ok(ParsedThread::default()),
+                }
+            }
+        })
+        .buffered(CONTEXT_REQUESTS_AT_ONCE)
+        .collect()
+        .await
+}
+
+/// Whether looking up `post`'s conversation would find anything `limits`
+/// keeps
+fn has_thread(post: &Status, limits: ThreadLimits) -> bool {
+    let replies = limits.replies > 0 && limits.depth > 0 && post.replies_count > 0;
+    let ancestors = limits.ancestors > 0 && post.in_reply_to_id.is_some();
+    replies || ancestors
+}
+
+/// The thread around the toot `id` from its conversation
+fn parsed_thread(id: &str, context: Context, limits: ThreadLimits) -> ParsedThread {
+    // the closest ancestors, each replying to the one before
+    let skipped = context.ancestors.len().saturating_sub(limits.ancestors);
+    let ancestors = context
+        .ancestors
+        .into_iter()
+        .skip(skipped)
+        .enumerate()
+        .map(|(position, status)| ThreadToot {
+            toot: parsed_toot(&status),
+            raw: status,
+            reply_to: match position {
+                0 => ReplyTo::Unsynced,
+                _ => ReplyTo::Thread(position - 1),
+            },
+            depth: 0,
+        })
+        .collect::<Vec<_>>();
+
+    let mut children = HashMap::<&str, Vec<&Status>>::new();
+    for status in context.descendants.iter() {
+        if let Some(parent) = &status.in_reply_to_id {
+            children.entry(parent.as_str()).or_default().push(status);
+        }
+    }
+    let mut replies = Vec::new();
+    thread_replies(
+        id,
+        ReplyTo::Toot,
+        1,
+        &children,
+        ancestors.len(),
+        limits,
+        &mut replies,
+    );
+    ParsedThread { ancestors, replies }
+}
+
+/// Adds the replies to `id` to `out` depth first, each followed by its own
+/// replies, until one of `limits` runs out. `offset` is the position of
+/// `out`'s first toot in the thread.
+fn thread_replies(
+    id: &str,
+    reply_to: ReplyTo,
+    depth: usize,
+    children: &HashMap<&str, Vec<&Status>>,
+    offset: usize,
+    limits: ThreadLimits,
+    out: &mut Vec<ThreadToot>,
+) {
+    if depth > limits.depth {
+        return;
+    }
+    for child in children.get(id).into_iter().flatten().take(limits.breadth) {
+        if out.len() >= limits.replies {
+            return;
+        }
+        let position = offset + out.len();
+        out.push(ThreadToot {
+            toot: parsed_toot(child),
+            raw: (*child).clone(),
+            reply_to,
+            depth: depth as u8,
+        });
+        thread_replies(
+            &child.id,
+            ReplyTo::Thread(position),
+            depth + 1,
+            children,
+            offset,
+            limits,
+            out,
+        );
+    }
+}
+
+/// The newest `count` notifications of the kinds the device knows about
+pub(crate) async fn notifications(
+    client: &dyn MastodonApi,
+    count: u32,
+) -> Result<Vec<ParsedNotification>, megalodon::error::Error> {
+    let res = paginate(
+        Direction::Older,
+        count,
+        |page| async move {
+            let options = GetNotificationsInputOptions {
+                limit: Some(page.limit),
+                max_id: page.max_id,
+                ..Default::default()
+            };
+            client.get_notifications(Some(&options)).await
+        },
+        |notification| parsed_notification(notification).is_some(),
+    )
+    .await
+    .inspect_err(|e| error!("Error while downloading notifications: {}", e))?;
+
+    Ok(res.iter().filter_map(parsed_notification).collect())
+}
+
+/// The most recently active `count` conversations, merged by participants.
+/// Their earlier messages take a conversation lookup each, up to `requests`
+/// of them a few at a time, and a conversation that doesn't get one only
+/// keeps its newest message. Also returns the lookups made.
+pub(crate) async fn conversations(
+    client: &dyn MastodonApi,
+    count: u32,
+    requests: usize,
+) -> Result<(Vec<ParsedConversation>, usize), megalodon::error::Error> {
+    let options = GetConversationTimelineInputOptions {
+        limit: Some(count),
         ..Default::default()
     };
-    for post in posts {
-        let rep = loop {
-            match client
-                .get_status_context(post.id.clone(), Some(&options))
-                .await
-            {
-                Ok(ok) => break ok.json(),
-                Err(megalodon::error::Error::RequestError(r))
-                    if r.status() == Some(http::StatusCode::TOO_MANY_REQUESTS) =>
-                {
-                    warn!("recieved 429, sleeping");
-                    tokio::time::sleep(Duration::from_millis(100)).await;
-                    continue;
-                }
-                Err(err) => {
-                    return Err(err)
-                        .inspect_err(|e| error!("Error while downloading replies: {}", e))
+    let page = client
+        .get_conversation_timeline(Some(&options))
+        .await
+        .inspect_err(|e| error!("Error while downloading conversations: {}", e))?
+        .json();
+
+    let page = page
+        .into_iter()
+        .filter_map(|mut conversation| {
+            let last = conversation.last_status.take()?;
+            Some((conversation, last))
+        })
+        .collect::<Vec<_>>();
+    let lookups = page.len().min(requests);
+    if page.len() > requests {
+        warn!(
+            "{} conversations, only looking up the first {requests}",
+            page.len()
+        );
+    }
+    let threads = stream::iter(page.into_iter().enumerate())
+        .map(|(index, (conversation, last))| async move {
+            let mut raw = Vec::new();
+            // the conversation only carries its newest message
+            if index < requests {
+                match client.get_status_context(last.id.clone(), None).await {
+                    Ok(context) => raw.extend(
+                        context
+                            .json()
+                            .ancestors
+                            .into_iter()
+                            .filter(|s| matches!(s.visibility, StatusVisibility::Direct)),
+                    ),
+                    Err(e) => warn!(
+                        "Only keeping the newest message of conversation {}: {e}",
+                        conversation.id
+                    ),
                 }
             }
-        };
+            raw.push(last);
+            (conversation, raw)
+        })
+        .buffered(CONTEXT_REQUESTS_AT_ONCE)
+        .collect::<Vec<_>>()
+        .await;
 
-        let replies = rep
-            .descendants
-            .into_iter()
-            .take(max_replies_each)
-            .collect::<Vec<_>>();
-        statuses.push((replies.iter().map(parsed_toot).collect(), replies));
+    let mut res: Vec<ParsedConversation> = Vec::new();
+    for (conversation, raw) in threads {
+        let participants = conversation
+            .accounts
+            .iter()
+            .map(|account| {
+                let author = ParsedAuthor::new(account);
+                (author.key(), author)
+            })
+            .collect::<BTreeMap<_, _>>();
+        // the same people, even if a profile changed in between
+        match res
+            .iter_mut()
+            .find(|c| c.participants.keys().eq(participants.keys()))
+        {
+            Some(existing) => {
+                existing.unread |= conversation.unread;
+                existing.raw.extend(raw);
+            }
+            None => res.push(ParsedConversation {
+                participants,
+                unread: conversation.unread,
+                messages: Vec::new(),
+                raw,
+            }),
+        }
+    }
+
+    for conversation in res.iter_mut() {
+        // merged threads interleave
+        conversation.raw.sort_by(|a, b| id_cmp(&a.id, &b.id));
+        conversation.raw.dedup_by(|a, b| a.id == b.id);
+        let older = conversation.raw.len().saturating_sub(CONVERSATION_MESSAGES);
+        conversation.raw.drain(..older);
+        conversation.messages = conversation.raw.iter().map(parsed_toot).collect();
+    }
+    info!("{} conversations", res.len());
+
+    Ok((res, lookups))
+}
+
+/// Which of the accounts in `ids` the user follows
+pub(crate) async fn following(
+    client: &dyn MastodonApi,
+    ids: &[&str],
+) -> Result<HashSet<String>, megalodon::error::Error> {
+    let mut res = HashSet::new();
+    for chunk in ids.chunks(RELATIONSHIPS_PER_REQUEST) {
+        let ids = chunk.iter().map(|id| id.to_string()).collect::<Vec<_>>();
+        let relationships = client
+            .get_relationships(ids)
+            .await
+            .inspect_err(|e| error!("Error while downloading relationships: {}", e))?
+            .json();
+        res.extend(
+            relationships
+                .into_iter()
+                .filter(|r| r.following)
+                .map(|r| r.id),
+        );
     }
 
-    Ok(statuses)
+    Ok(res)
+}
+
+/// Orders status ids by age. They're numeric strings, newer ones are larger.
+pub(crate) fn id_cmp(a: &str, b: &str) -> std::cmp::Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+}
+
+fn parsed_notification(notification: &Notification) -> Option<ParsedNotification> {
+    let kind = match notification.r#type {
+        NotificationType::Mention => NotificationKind::Mention,
+        NotificationType::Favourite => NotificationKind::Favourite,
+        NotificationType::Reblog => NotificationKind::Reblog,
+        NotificationType::Follow => NotificationKind::Follow,
+        NotificationType::PollExpired => NotificationKind::PollEnded,
+        _ => return None,
+    };
+    let account = notification.account.as_ref()?;
+    let (status_id, excerpt) = match &notification.status {
+        Some(status) => (Some(status.id.clone()), parsed_toot(status).content),
+        None => (None, String::new()),
+    };
+    Some(ParsedNotification {
+        kind,
+        author: ParsedAuthor::new(account),
+        created: notification.created_at.timestamp(),
+        status_id,
+        excerpt,
+    })
+}
+
+/// Toots and bios as the device shows them
+fn to_text(html: &str) -> String {
+    html2text::from_read_with_decorator(html.as_bytes(), usize::MAX, HeffalumpDecorator)
 }
 
-fn parsed_toot(status: &megalodon::entities::Status) -> (String, String) {
-    let mut content = {
-        let unformatted = status
+fn parsed_toot(status: &megalodon::entities::Status) -> ParsedToot {
+    let mut content = to_text(
+        status
             .reblog
             .as_ref()
             .map(|r| &r.content)
-            .unwrap_or(&status.content);
-        html2text::from_read_with_decorator(unformatted.as_bytes(), usize::MAX, HeffalumpDecorator)
-    };
+            .unwrap_or(&status.content),
+    );
 
     let author = match &status.reblog {
-        Some(reblog) => format!(
-            "@{} via @{}",
-            reblog.account.acct.split('@').next().unwrap(),
-            status.account.acct.split('@').next().unwrap()
-        ),
-        None => format!("@{}", status.account.acct.split('@').next().unwrap()),
+        Some(reblog) => ParsedAuthor {
+            via: Some(Box::new(ParsedAuthor::new(&status.account))),
+            ..ParsedAuthor::new(&reblog.account)
+        },
+        None => ParsedAuthor::new(&status.account),
     };
     let mut attachments: Box<dyn Iterator<Item = &Attachment>> =
         Box::new(status.media_attachments.iter());
@@ -211,7 +924,25 @@ fn parsed_toot(status: &megalodon::entities::Status) -> (String, String) {
         }
     }
 
-    (author, content)
+    let original = status.reblog.as_deref().unwrap_or(status);
+    ParsedToot {
+        author,
+        content,
+        warning: original.spoiler_text.clone(),
+        sensitive: original.sensitive || !original.spoiler_text.is_empty(),
+        poll: original.poll.as_ref().map(|poll| ParsedPoll {
+            expires: poll.expires_at.map(|at| at.timestamp()),
+            expired: poll.expired,
+            multiple: poll.multiple,
+            voted: poll.voted.unwrap_or(false),
+            votes_count: poll.votes_count,
+            options: poll
+                .options
+                .iter()
+                .map(|option| (option.title.clone(), option.votes_count))
+                .collect(),
+        }),
+    }
 }
 
 #[derive(Clone)]
@@ -294,15 +1025,643 @@ impl TextDecorator for HeffalumpDecorator {
 
 #[cfg(test)]
 mod test {
-    use crate::download::{feed, get_client};
+    use std::collections::HashSet;
+
+    use crate::{
+        download::{
+            ambiguous_usernames, bookmarks, conversations, favourites, feed, feed_since, hashtags,
+            lists, notifications, parsed_toot, public, replies, self_posts, ParsedThread, ReplyTo,
+            ThreadLimits,
+        },
+        heffalump_hh_types::NotificationKind,
+        mock::{
+            conversation, direct, notification, poll, rate_limited, reply, status, timeline, Call,
+            Endpoint, MockMastodon,
+        },
+        sync_state::HomeGap,
+    };
+
+    const LIMITS: ThreadLimits = ThreadLimits {
+        ancestors: 5,
+        depth: 4,
+        breadth: 10,
+        replies: 3,
+        requests: 40,
+    };
+
+    fn unwrapped(threads: Vec<Result<ParsedThread, megalodon::error::Error>>) -> Vec<ParsedThread> {
+        threads.into_iter().map(Result::unwrap).collect()
+    }
 
     #[tokio::test]
     async fn test_feed() {
-        let token = env!("HEFFALUMP_ACCESS_TOKEN").to_string();
-        let instance = env!("HEFFALUMP_MASTADON_INST").to_string();
-        let client = get_client(instance, token);
-        for (author, content) in feed(client.as_ref(), 100).await.unwrap().0 {
-            println!("{}\n{}", author, content);
+        let mut home = timeline(1000, 50, "alice@mastodon.example");
+        let parent = home[0].clone();
+        home.insert(0, reply("1001", "bob", "<p>hi</p>", &parent));
+        let client = MockMastodon::new("me").with_home(home);
+
+        let (parsed, raw) = feed(&client, 100, false).await.unwrap();
+        assert_eq!(parsed.len(), 50);
+        assert!(raw.iter().all(|s| s.in_reply_to_id.is_none()));
+        assert_eq!(parsed[0].author.handle, "alice@mastodon.example");
+        assert_eq!(parsed[0].content, "post 1000\n");
+
+        // pages are capped at 40, so the second request continues from the last status
+        let calls = client.calls();
+        assert_eq!(
+            calls[1],
+            Call::HomeTimeline {
+                limit: Some(61),
+                max_id: Some(String::from("962")),
+                since_id: None,
+            }
+        );
+        drop(calls);
+
+        // unless the device can show what they reply to
+        let (parsed, _) = feed(&client, 100, true).await.unwrap();
+        assert_eq!(parsed.len(), 51);
+        assert_eq!(parsed[0].content, "hi\n");
+    }
+
+    #[tokio::test]
+    async fn test_feed_retries_after_429() {
+        let client = rate_limited(
+            MockMastodon::new("me")
+                .with_home(timeline(1000, 10, "alice"))
+                .with_too_many_requests(Endpoint::HomeTimeline, 2),
+        );
+
+        let (parsed, _) = feed(&client, 10, false).await.unwrap();
+        assert_eq!(parsed.len(), 10);
+        assert_eq!(client.inner().calls().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_feed_since() {
+        let client = MockMastodon::new("me").with_home(timeline(1100, 100, "alice"));
+        let previous = timeline(1020, 10, "alice");
+
+        // 80 new statuses, fetched from the newest with two pages of 40
+        let (parsed, raw, gaps) =
+            feed_since(&client, 100, "1020", Vec::new(), previous.clone(), false)
+                .await
+                .unwrap();
+        assert_eq!(parsed.len(), 90);
+        assert_eq!(raw[0].id, "1100");
+        assert_eq!(raw[80].id, "1020");
+        assert_eq!(raw[89].id, "1011");
+        assert!(gaps.is_empty());
+        assert_eq!(
+            client.calls()[1],
+            Call::HomeTimeline {
+                limit: Some(61),
+                max_id: Some(String::from("1061")),
+                since_id: Some(String::from("1020")),
+            }
+        );
+
+        // with a smaller budget the newest come first, and the next syncs
+        // backfill what's left
+        let client = MockMastodon::new("me").with_home(timeline(1100, 100, "alice"));
+        let (_, raw, gaps) = feed_since(&client, 30, "1020", Vec::new(), previous.clone(), false)
+            .await
+            .unwrap();
+        assert_eq!(raw.len(), 30);
+        assert_eq!(raw[0].id, "1100");
+        assert_eq!(raw[29].id, "1071");
+        assert_eq!(
+            gaps,
+            vec![HomeGap {
+                after: String::from("1020"),
+                before: String::from("1071"),
+            }]
+        );
+
+        // exactly the budget left above `since_id` isn't a gap
+        let client = MockMastodon::new("me").with_home(timeline(1100, 100, "alice"));
+        let (_, raw, no_gaps) = feed_since(&client, 30, "1070", Vec::new(), Vec::new(), false)
+            .await
+            .unwrap();
+        assert_eq!(raw.len(), 30);
+        assert_eq!(raw[29].id, "1071");
+        assert!(no_gaps.is_empty());
+
+        // 10 new and 30 from the gap, but the last sync's are newer than the
+        // gap and the gap's get cut
+        let client = MockMastodon::new("me").with_home(timeline(1110, 110, "alice"));
+        let (_, raw, partly) = feed_since(
+            &client,
+            40,
+            "1100",
+            gaps.clone(),
+            timeline(1100, 30, "alice"),
+            false,
+        )
+        .await
+        .unwrap();
+        let ids = raw.iter().map(|s| s.id.as_str()).collect::<Vec<_>>();
+        assert_eq!(ids[..3], ["1110", "1109", "1108"]);
+        assert_eq!(ids[10], "1100");
+        assert_eq!(ids[39], "1071");
+        assert_eq!(
+            partly,
+            vec![HomeGap {
+                after: String::from("1020"),
+                before: String::from("1041"),
+            }]
+        );
+
+        // with room for everything the gap is filled in order
+        let client = MockMastodon::new("me").with_home(timeline(1110, 110, "alice"));
+        let (parsed, raw, gaps) = feed_since(
+            &client,
+            100,
+            "1100",
+            gaps,
+            timeline(1100, 30, "alice"),
+            false,
+        )
+        .await
+        .unwrap();
+        assert_eq!(parsed.len(), 90);
+        assert_eq!(raw[0].id, "1110");
+        assert_eq!(raw[10].id, "1100");
+        assert_eq!(raw[40].id, "1070");
+        assert_eq!(raw[89].id, "1021");
+        assert!(raw
+            .windows(2)
+            .all(|w| id_cmp(&w[0].id, &w[1].id) == std::cmp::Ordering::Greater));
+        assert!(gaps.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_self_posts() {
+        let client = MockMastodon::new("me").with_account_statuses(timeline(500, 40, "me"));
+
+        let (parsed, raw) = self_posts(&client, 40).await.unwrap();
+        assert_eq!(parsed.len(), 40);
+        assert_eq!(raw[0].id, "500");
+
+        // the second page continues from the first, and an account with
+        // fewer toots than asked for stops at the empty page after them
+        let client = MockMastodon::new("me").with_account_statuses(timeline(500, 50, "me"));
+        let (_, raw) = self_posts(&client, 80).await.unwrap();
+        assert_eq!(raw.len(), 50);
+        assert_eq!(raw[49].id, "451");
+        assert_eq!(
+            client.calls()[1..],
+            [
+                Call::AccountStatuses {
+                    limit: Some(80),
+                    max_id: None,
+                },
+                Call::AccountStatuses {
+                    limit: Some(40),
+                    max_id: Some(String::from("461")),
+                },
+                Call::AccountStatuses {
+                    limit: Some(30),
+                    max_id: Some(String::from("451")),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replies() {
+        let mut posts = timeline(1000, 2, "alice");
+        posts[0].replies_count = 5;
+        let descendants = (0..5)
+            .map(|i| reply(&(2000 + i).to_string(), "bob", "<p>reply</p>", &posts[0]))
+            .collect();
+        let client = rate_limited(
+            MockMastodon::new("me")
+                .with_replies("1000", descendants)
+                .with_too_many_requests(Endpoint::StatusContext, 1),
+        );
+
+        let res = unwrapped(replies(&client, posts.iter(), LIMITS).await);
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].replies.len(), 3);
+        assert_eq!(res[0].replies[0].toot.author.handle, "bob@mastodon.example");
+        assert_eq!(res[0].replies[0].toot.content, "reply\n");
+        assert!(res[0]
+            .replies
+            .iter()
+            .all(|r| r.reply_to == ReplyTo::Toot && r.depth == 1));
+        assert_eq!(res[1].len(), 0);
+        // the retry, and nothing for the toot without replies
+        assert_eq!(
+            *client.inner().calls(),
+            vec![
+                Call::StatusContext(String::from("1000")),
+                Call::StatusContext(String::from("1000"))
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replies_budget() {
+        let mut posts = timeline(1000, 5, "alice");
+        for post in posts.iter_mut().skip(1) {
+            post.replies_count = 1;
+        }
+        // a boost is looked up as the boosted toot
+        let mut boosted = status("400", "bob", "<p>boosted</p>");
+        boosted.replies_count = 1;
+        posts[2].reblog = Some(Box::new(boosted.clone()));
+        let client = MockMastodon::new("me")
+            .with_replies("999", vec![reply("2000", "bob", "<p>hi</p>", &posts[1])])
+            .with_replies("400", vec![reply("2001", "carol", "<p>hey</p>", &boosted)]);
+
+        let limits = ThreadLimits {
+            requests: 2,
+            ..LIMITS
+        };
+        let res = unwrapped(replies(&client, posts.iter(), limits).await);
+        assert_eq!(
+            *client.calls(),
+            vec![
+                Call::StatusContext(String::from("999")),
+                Call::StatusContext(String::from("400"))
+            ]
+        );
+        let lens = res.iter().map(ParsedThread::len).collect::<Vec<_>>();
+        assert_eq!(lens, vec![0, 1, 1, 0, 0]);
+        assert_eq!(res[2].replies[0].raw.id, "2001");
+    }
+
+    #[tokio::test]
+    async fn test_replies_fail_alone() {
+        let mut posts = timeline(1000, 2, "alice");
+        for post in posts.iter_mut() {
+            post.replies_count = 1;
         }
+        let client = MockMastodon::new("me")
+            .with_replies("1000", vec![reply("2000", "bob", "<p>hi</p>", &posts[0])])
+            .with_replies("999", vec![reply("2001", "bob", "<p>hey</p>", &posts[1])])
+            .with_too_many_requests(Endpoint::StatusContext, 1);
+
+        // lookups run at once, either may be the one turned away
+        let res = replies(&client, posts.iter(), LIMITS).await;
+        assert_eq!(res.iter().filter(|thread| thread.is_err()).count(), 1);
+        let found = res.iter().flatten().collect::<Vec<_>>();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_reply_trees() {
+        let root = status("900", "dave", "<p>root</p>");
+        let middle = reply("950", "erin", "<p>middle</p>", &root);
+        let mut post = reply("1000", "alice", "<p>question</p>", &middle);
+        post.replies_count = 2;
+        let first = reply("2000", "bob", "<p>first</p>", &post);
+        let second = reply("2001", "carol", "<p>second</p>", &post);
+        let nested = reply("2002", "alice", "<p>to bob</p>", &first);
+        let deeper = reply("2003", "bob", "<p>to alice</p>", &nested);
+        let client = MockMastodon::new("me")
+            .with_ancestors("1000", vec![root, middle])
+            // replies to the same toot keep the order the API lists them in
+            .with_replies("1000", vec![second, deeper, first, nested]);
+
+        let limits = ThreadLimits {
+            ancestors: 1,
+            depth: 2,
+            ..LIMITS
+        };
+        let res = unwrapped(replies(&client, std::iter::once(&post), limits).await);
+        let thread = &res[0];
+        // only the closest ancestor, which isn't a reply to anything synced
+        assert_eq!(thread.ancestors.len(), 1);
+        assert_eq!(thread.ancestors[0].raw.id, "950");
+        assert_eq!(thread.ancestors[0].reply_to, ReplyTo::Unsynced);
+        let tree = thread
+            .replies
+            .iter()
+            .map(|r| (r.raw.id.as_str(), r.reply_to, r.depth))
+            .collect::<Vec<_>>();
+        // 2003 is too deep, and positions count the ancestor
+        assert_eq!(
+            tree,
+            vec![
+                ("2001", ReplyTo::Toot, 1),
+                ("2000", ReplyTo::Toot, 1),
+                ("2002", ReplyTo::Thread(2), 2),
+            ]
+        );
+
+        let limits = ThreadLimits {
+            ancestors: 0,
+            breadth: 1,
+            ..LIMITS
+        };
+        let res = unwrapped(replies(&client, std::iter::once(&post), limits).await);
+        let ids = res[0]
+            .toots()
+            .map(|r| r.raw.id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec!["2001"]);
+    }
+
+    #[tokio::test]
+    async fn test_notifications() {
+        let mine = status("500", "me", "<p>my toot</p>");
+        let mut all = (0..60)
+            .map(|i| {
+                let id = (3100 - i).to_string();
+                notification(&id, "favourite", "bob@elsewhere.example", Some(&mine))
+            })
+            .collect::<Vec<_>>();
+        all.insert(0, notification("3200", "follow", "carol", None));
+        all.insert(1, notification("3150", "follow_request", "dave", None));
+        let client = rate_limited(
+            MockMastodon::new("me")
+                .with_notifications(all)
+                .with_too_many_requests(Endpoint::Notifications, 1),
+        );
+
+        let res = notifications(&client, 50).await.unwrap();
+        assert_eq!(res.len(), 50);
+        assert_eq!(res[0].kind, NotificationKind::Follow);
+        assert_eq!(res[0].author.handle, "carol@mastodon.example");
+        assert_eq!(res[0].status_id, None);
+        // follow requests aren't shown on the device
+        assert_eq!(res[1].kind, NotificationKind::Favourite);
+        assert_eq!(res[1].author.handle, "bob@elsewhere.example");
+        assert_eq!(res[1].status_id.as_deref(), Some("500"));
+        assert_eq!(res[1].excerpt, "my toot\n");
+        assert_eq!(res[1].created, 1_704_153_600);
+
+        // the second page picks up after the oldest notification of the first,
+        // skipped ones included
+        assert_eq!(
+            client.inner().calls()[2],
+            Call::Notifications {
+                limit: Some(1),
+                max_id: Some(String::from("3053")),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lists() {
+        let client = rate_limited(
+            MockMastodon::new("me")
+                .with_list("7", "team", timeline(900, 50, "dave"))
+                .with_list("8", "news", timeline(800, 3, "erin"))
+                .with_too_many_requests(Endpoint::ListTimeline, 1),
+        );
+        let titles = ["news", "missing", "team"].map(String::from);
+
+        let res = lists(&client, &titles, 45, false)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(_, list)| list.unwrap())
+            .collect::<Vec<_>>();
+        // in the configured order, without the list the account doesn't have
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].title, "news");
+        assert_eq!(res[0].raw.len(), 3);
+        assert_eq!(res[1].title, "team");
+        assert_eq!(res[1].contents.len(), 45);
+        assert_eq!(res[1].contents[0].author.handle, "dave@mastodon.example");
+        assert_eq!(res[1].contents[0].content, "post 900\n");
+        // paged like the home timeline
+        assert_eq!(
+            client.inner().calls().last(),
+            Some(&Call::ListTimeline {
+                id: String::from("7"),
+                limit: Some(5),
+                max_id: Some(String::from("861")),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_hashtags_and_public() {
+        let client = rate_limited(
+            MockMastodon::new("me")
+                .with_tag("palm", timeline(700, 50, "frank"))
+                .with_public(vec![
+                    status("650", "gina@elsewhere.example", "<p>far</p>"),
+                    status("640", "hank", "<p>near</p>"),
+                ])
+                .with_too_many_requests(Endpoint::TagTimeline, 1),
+        );
+
+        let res = hashtags(&client, &[String::from("#palm")], 45, false).await;
+        assert_eq!(res[0].0, "#palm");
+        let palm = res[0].1.as_ref().unwrap();
+        assert_eq!(palm.title, "#palm");
+        assert_eq!(palm.raw.len(), 45);
+        assert_eq!(
+            client.inner().calls().last(),
+            Some(&Call::TagTimeline {
+                tag: String::from("palm"),
+                limit: Some(5),
+                max_id: Some(String::from("661")),
+            })
+        );
+
+        let local = public(&client, true, 10, false).await.unwrap();
+        assert_eq!(local.title, "Local");
+        assert_eq!(local.contents.len(), 1);
+        assert_eq!(local.contents[0].author.handle, "hank@mastodon.example");
+        assert_eq!(local.contents[0].content, "near\n");
+        let federated = public(&client, false, 10, false).await.unwrap();
+        assert_eq!(federated.title, "Federated");
+        assert_eq!(federated.raw.len(), 2);
+        assert!(client.inner().calls().contains(&Call::PublicTimeline {
+            local: Some(true),
+            limit: Some(10),
+            max_id: None,
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_saved_timelines() {
+        let saved = timeline(900, 50, "alice");
+        let liked = vec![reply("950", "bob", "<p>nice</p>", &saved[0])];
+        let client = rate_limited(
+            MockMastodon::new("me")
+                .with_bookmarks(saved)
+                .with_favourites(liked)
+                .with_too_many_requests(Endpoint::Bookmarks, 1),
+        );
+
+        let res = bookmarks(&client, 45).await.unwrap();
+        assert_eq!(res.title, "Bookmarks");
+        assert_eq!(res.raw.len(), 45);
+        // paged with the cursor from the Link header, not the oldest status id
+        assert_eq!(
+            client.inner().calls().last(),
+            Some(&Call::Bookmarks {
+                limit: Some(5),
+                max_id: Some(String::from("40")),
+            })
+        );
+
+        let res = favourites(&client, 10).await.unwrap();
+        // replies are kept, and without a next link there's nothing more to ask for
+        assert_eq!(res.contents.len(), 1);
+        assert_eq!(res.contents[0].content, "nice\n");
+        assert_eq!(
+            client.inner().calls().last(),
+            Some(&Call::Favourites {
+                limit: Some(10),
+                max_id: None,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_conversations() {
+        let first = direct("700", "bob", "<p>@me one</p>", &["me"]);
+        let second = direct("710", "bob", "<p>@me two</p>", &["me"]);
+        let elsewhere = direct("720", "bob@elsewhere.example", "<p>@me three</p>", &["me"]);
+        let public = status("650", "bob", "<p>not a dm</p>");
+        // bob gained a follower between the two conversations
+        let mut bob_before = conversation("1", &["bob"], &first);
+        bob_before.accounts[0].followers_count += 1;
+        let client = rate_limited(
+            MockMastodon::new("me")
+                .with_ancestors(
+                    "700",
+                    vec![public, direct("690", "me", "<p>@bob zero</p>", &["bob"])],
+                )
+                .with_ancestors("710", vec![first.clone()])
+                .with_conversations(vec![
+                    conversation("3", &["bob@elsewhere.example"], &elsewhere),
+                    conversation("2", &["bob"], &second),
+                    bob_before,
+                ])
+                .with_too_many_requests(Endpoint::Conversations, 1),
+        );
+
+        let (res, lookups) = conversations(&client, 20, 60).await.unwrap();
+        assert_eq!(lookups, 3);
+        // both threads with the local @bob are merged, the other @bob is
+        // someone else
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].raw.len(), 1);
+        let participants = res[1]
+            .participants
+            .values()
+            .map(|p| p.handle.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(participants, vec!["bob@mastodon.example"]);
+        let ids = res[1].raw.iter().map(|s| s.id.as_str()).collect::<Vec<_>>();
+        assert_eq!(ids, vec!["690", "700", "710"]);
+        assert_eq!(res[1].messages[0].author.handle, "me@mastodon.example");
+        assert_eq!(res[1].messages[0].content, "@bob zero\n");
+        assert_eq!(
+            client.inner().calls()[1],
+            Call::Conversations { limit: Some(20) }
+        );
+
+        // past the budget, or when the lookup fails, only the newest message
+        // is kept
+        let dm = |id, acct| direct(id, acct, "<p>@me hi</p>", &["me"]);
+        let client = MockMastodon::new("me")
+            .with_ancestors("810", vec![dm("800", "dave")])
+            .with_ancestors("820", vec![dm("801", "bob")])
+            .with_ancestors("830", vec![dm("802", "carol")])
+            .with_conversations(vec![
+                conversation("4", &["carol"], &dm("830", "carol")),
+                conversation("5", &["bob"], &dm("820", "bob")),
+                conversation("6", &["dave"], &dm("810", "dave")),
+            ])
+            .with_too_many_requests(Endpoint::StatusContext, 1);
+        let (res, lookups) = conversations(&client, 20, 2).await.unwrap();
+        assert_eq!(lookups, 2);
+        let lookups = client
+            .calls()
+            .iter()
+            .filter(|call| matches!(call, Call::StatusContext(_)))
+            .count();
+        assert_eq!(lookups, 2);
+        let mut kept = res.iter().map(|c| c.raw.len()).collect::<Vec<_>>();
+        kept.sort();
+        assert_eq!(kept, vec![1, 1, 2]);
+    }
+
+    #[test]
+    fn test_parsed_toot_reblog() {
+        let mut boost = status("2", "bob@elsewhere.example", "");
+        boost.reblog = Some(Box::new(status("1", "alice", "<p>original</p>")));
+        let toot = parsed_toot(&boost);
+        assert_eq!(
+            toot.author.key(),
+            "alice@mastodon.example via bob@elsewhere.example"
+        );
+        assert_eq!(toot.author.shown_as(&HashSet::new()), "@alice via @bob");
+        assert_eq!(toot.content, "original\n");
+    }
+
+    #[test]
+    fn test_parsed_toot_content_warning() {
+        let mut warned = status("1", "alice", "<p>spoilers</p>");
+        warned.spoiler_text = String::from("book ending");
+        let toot = parsed_toot(&warned);
+        assert_eq!(toot.warning, "book ending");
+        assert!(toot.sensitive);
+        assert_eq!(toot.content, "spoilers\n");
+
+        // a boost collapses like the toot it boosts
+        let mut boost = status("2", "bob", "");
+        boost.reblog = Some(Box::new(warned));
+        assert!(parsed_toot(&boost).sensitive);
+
+        let mut sensitive = status("3", "alice", "<p>pictures</p>");
+        sensitive.sensitive = true;
+        let toot = parsed_toot(&sensitive);
+        assert!(toot.warning.is_empty() && toot.sensitive);
+        assert!(!parsed_toot(&status("4", "alice", "")).sensitive);
+    }
+
+    #[test]
+    fn test_parsed_toot_poll() {
+        let mut polled = status("1", "alice", "<p>tabs or spaces?</p>");
+        polled.poll = Some(poll("7", &[("tabs", Some(3)), ("spaces", Some(1))]));
+        let mut boost = status("2", "bob", "");
+        boost.reblog = Some(Box::new(polled));
+        let poll = parsed_toot(&boost).poll.unwrap();
+        assert_eq!(poll.expires, Some(1_704_240_000));
+        assert!(!poll.expired && !poll.multiple && !poll.voted);
+        assert_eq!(poll.votes_count, 4);
+        assert_eq!(
+            poll.options,
+            vec![
+                (String::from("tabs"), Some(3)),
+                (String::from("spaces"), Some(1))
+            ]
+        );
+        assert_eq!(parsed_toot(&status("3", "alice", "")).poll, None);
+    }
+
+    #[test]
+    fn test_ambiguous_usernames() {
+        let author = |acct| parsed_toot(&status("1", acct, "")).author;
+        let mut boost = author("alice@hachyderm.io");
+        boost.via = Some(Box::new(author("bob")));
+        let authors = [
+            author("alice"),
+            author("alice"),
+            boost,
+            author("bob"),
+            author("carol@hachyderm.io"),
+        ];
+
+        let ambiguous = ambiguous_usernames(&authors);
+        assert_eq!(ambiguous, HashSet::from(["alice"]));
+        assert_eq!(authors[0].shown_as(&ambiguous), "@alice@mastodon.example");
+        assert_eq!(
+            authors[2].shown_as(&ambiguous),
+            "@alice@hachyderm.io via @bob"
+        );
+        assert_eq!(authors[4].shown_as(&ambiguous), "@carol");
     }
 }
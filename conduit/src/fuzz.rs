@@ -0,0 +1,63 @@
+//! Entry points for the cargo-fuzz targets in `conduit/fuzz`, also run by
+//! the property tests. A decoder has to either reject its input or accept it
+//! and encode it back to exactly the same bytes.
+
+use crate::heffalump_hh_types::{
+    HeffalumpPrefs, OnDevice, SyncStatus, TootAccount, TootAuthor, TootContent, TootContentV2,
+    TootContentV3, TootMessage, TootNotification, TootPoll, TootProfile, TootWrite,
+};
+
+fn round_trip<T: OnDevice + std::fmt::Debug>(data: &[u8]) {
+    if let Ok(value) = T::from_hh_bytes(data) {
+        let encoded = value.to_hh_bytes().expect("decoded records always encode");
+        assert_eq!(encoded, data, "{value:?} encodes differently");
+    }
+}
+
+pub fn toot_content(data: &[u8]) {
+    round_trip::<TootContent>(data)
+}
+
+pub fn toot_content_v2(data: &[u8]) {
+    round_trip::<TootContentV2>(data)
+}
+
+pub fn toot_content_v3(data: &[u8]) {
+    round_trip::<TootContentV3>(data)
+}
+
+pub fn toot_author(data: &[u8]) {
+    round_trip::<TootAuthor>(data)
+}
+
+pub fn toot_write(data: &[u8]) {
+    round_trip::<TootWrite>(data)
+}
+
+pub fn prefs(data: &[u8]) {
+    round_trip::<HeffalumpPrefs>(data)
+}
+
+pub fn toot_notification(data: &[u8]) {
+    round_trip::<TootNotification>(data)
+}
+
+pub fn toot_message(data: &[u8]) {
+    round_trip::<TootMessage>(data)
+}
+
+pub fn toot_account(data: &[u8]) {
+    round_trip::<TootAccount>(data)
+}
+
+pub fn toot_profile(data: &[u8]) {
+    round_trip::<TootProfile>(data)
+}
+
+pub fn toot_poll(data: &[u8]) {
+    round_trip::<TootPoll>(data)
+}
+
+pub fn sync_status(data: &[u8]) {
+    round_trip::<SyncStatus>(data)
+}
@@ -0,0 +1,181 @@
+//! Runs the conduit's sync logic without HotSync Manager, reading the
+//! handheld's writes from and emitting the generated databases to disk.
+
+use std::path::{Path, PathBuf};
+
+use log::info;
+use palmrs::database::{record::pdb_record::RecordAttributes, PalmDatabase, PdbDatabase};
+
+use crate::{
+    api::MastodonApi, config::FetchConfig, create_dbs, device::DeviceReport, download::get_client,
+    read_config, schema::negotiate, upload::apply_writes, DB_NAME_AUTHOR, DB_NAME_CONTENT,
+    DB_NAME_CONVERSATIONS, DB_NAME_NOTIFICATIONS, DB_NAME_POLLS, DB_NAME_STATUS, DB_NAME_WRITES,
+};
+
+pub struct HeadlessSync {
+    /// Directory holding the timeline caches, equivalent to the HotSync user directory
+    pub data_dir: PathBuf,
+    /// Path to a `heffalump_config.json`
+    pub config: PathBuf,
+    /// Directory the generated databases are written to
+    pub out_dir: PathBuf,
+}
+
+impl HeadlessSync {
+    pub fn new(data_dir: impl Into<PathBuf>) -> Self {
+        let data_dir = data_dir.into();
+        Self {
+            config: data_dir.join(crate::CONFIG_FILE),
+            out_dir: data_dir.clone(),
+            data_dir,
+        }
+    }
+
+    pub fn with_config(mut self, config: impl Into<PathBuf>) -> Self {
+        self.config = config.into();
+        self
+    }
+
+    pub fn with_out_dir(mut self, out_dir: impl Into<PathBuf>) -> Self {
+        self.out_dir = out_dir.into();
+        self
+    }
+
+    /// Builds the databases for the handheld, then applies any writes found
+    /// in `HeffalumpWritesDB.pdb` in the data directory, in the same order as
+    /// a HotSync would. Applied writes are renamed to
+    /// `HeffalumpWritesDB.pdb.applied` so the next run doesn't repeat them.
+    pub fn run(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+
+        let config = read_config(&self.config)?;
+        let client = get_client(config.instance, config.access_token);
+        runtime.block_on(self.sync(client.as_ref(), config.fetch))
+    }
+
+    async fn sync(
+        &self,
+        client: &dyn MastodonApi,
+        fetch: FetchConfig,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let device = DeviceReport::read(&self.data_dir);
+        let schema = negotiate(device.schema)?;
+        let fetch = fetch.with_device_limits(device.fetch_limits);
+
+        let dbs = create_dbs(client, schema, &fetch, Some(&self.data_dir))
+            .await
+            .map_err(|_| "Failed to create databases")?;
+        info!("{:?}", &dbs.prefs);
+
+        std::fs::create_dir_all(&self.out_dir)?;
+        write_pdb(&self.out_dir, DB_NAME_AUTHOR, &dbs.author)?;
+        write_pdb(&self.out_dir, DB_NAME_CONTENT, &dbs.content)?;
+        if let Some(notifications_db) = &dbs.notifications {
+            write_pdb(&self.out_dir, DB_NAME_NOTIFICATIONS, notifications_db)?;
+        }
+        if let Some(conversations_db) = &dbs.conversations {
+            write_pdb(&self.out_dir, DB_NAME_CONVERSATIONS, conversations_db)?;
+        }
+        if let Some(polls_db) = &dbs.polls {
+            write_pdb(&self.out_dir, DB_NAME_POLLS, polls_db)?;
+        }
+        if let Some(status_db) = &dbs.status {
+            write_pdb(&self.out_dir, DB_NAME_STATUS, status_db)?;
+        }
+
+        let writes_path = pdb_path(&self.data_dir, DB_NAME_WRITES);
+        match std::fs::read(&writes_path) {
+            Ok(bytes) => {
+                info!("Applying writes from {}", writes_path.display());
+                let writes = PalmDatabase::<PdbDatabase>::from_bytes(&bytes)?;
+                apply_writes(client, &self.data_dir, database_records(&writes)).await?;
+                let applied = writes_path.with_extension("pdb.applied");
+                std::fs::rename(&writes_path, &applied)?;
+                info!("Moved the applied writes to {}", applied.display());
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                info!("No writes found at {}", writes_path.display());
+            }
+            Err(e) => return Err(Box::new(e)),
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs the interactive first-sync setup, writing the credentials to `config`
+pub fn configure(config: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?
+        .block_on(crate::config::configure(config))
+}
+
+pub(crate) fn pdb_path(dir: &Path, db_name: &str) -> PathBuf {
+    dir.join(format!("{db_name}.pdb"))
+}
+
+/// The records of `db` in the shape handed to `ConduitDBSink::Dynamic`
+pub(crate) fn database_records(
+    db: &PalmDatabase<PdbDatabase>,
+) -> Vec<(Vec<u8>, RecordAttributes, u32)> {
+    db.list_records_resources()
+        .into_iter()
+        .map(|(header, data)| (data, header.attributes, header.unique_id))
+        .collect()
+}
+
+fn write_pdb(
+    dir: &Path,
+    db_name: &str,
+    db: &PalmDatabase<PdbDatabase>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let path = pdb_path(dir, db_name);
+    std::fs::write(&path, db.to_bytes()?)?;
+    info!("Wrote {}", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        config::FetchConfig,
+        headless::{pdb_path, HeadlessSync},
+        heffalump_hh_types::HeffalumpPrefs,
+        mock::{timeline, Call, MockMastodon, TempDir},
+        writes_gen::WritesSpec,
+        DB_NAME_WRITES, MASTODON_CACHE_NEW,
+    };
+
+    #[tokio::test]
+    async fn test_writes_applied_once() {
+        let dir = TempDir::new("headless");
+        let source = timeline(100, 13, "alice");
+        std::fs::write(
+            dir.path().join(MASTODON_CACHE_NEW),
+            serde_json::to_string(&(HeffalumpPrefs::default(), &source)).unwrap(),
+        )
+        .unwrap();
+        let spec: WritesSpec =
+            serde_json::from_str(r#"{ "writes": [{ "favorite": 3 }] }"#).unwrap();
+        std::fs::write(pdb_path(dir.path(), DB_NAME_WRITES), spec.to_pdb().unwrap()).unwrap();
+
+        let client = MockMastodon::new("me").with_home(source);
+        let sync = HeadlessSync::new(dir.path());
+        sync.sync(&client, FetchConfig::default()).await.unwrap();
+        sync.sync(&client, FetchConfig::default()).await.unwrap();
+
+        let favourites = client
+            .calls()
+            .iter()
+            .filter(|call| matches!(call, Call::Favourite(_)))
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_eq!(favourites, vec![Call::Favourite(String::from("97"))]);
+        let writes = pdb_path(dir.path(), DB_NAME_WRITES);
+        assert!(!writes.exists());
+        assert!(writes.with_extension("pdb.applied").exists());
+    }
+}
@@ -1,197 +1,524 @@
-// typedef struct TootContent_s {
-//     UInt16  author;
-//     UInt16  is_reply_to;
-//     UInt16  replies_start;
-//     UInt16  content_len;
-//     char    toot_content[];
-// } TootContent;
-
-// typedef struct TootAuthor_s {
-//     UInt8 author_name_len;
-//     char  author_name[];
-// } TootAuthor;
-
-// enum TootWriteType {
-//     Favorite = 0,
-//     Follow = 1,
-//     Reblog = 2,
-//     Toot = 3,
-//     // to ensure the values chosen ar
-//     DoNotUse = 0xFF
-// }
-
-// union TootWriteContent {
-//     UInt16 favorite;
-//     UInt16 reblog;
-//     UInt16 follow;
-//     TootContent toot;
-// } ;
-
-// struct TootWrite {
-//     UInt8 type;
-//     TootWriteContent content;
-// }
-
-// typedef struct HeffalumpPrefs_s {
-//     UInt16          timeline_content_start;
-//     UInt16          self_content_start;
-//     UInt16          reply_content_start;
-//     UInt16          reply_content_end;
-// } HeffalumpPrefs;
-
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
-use log::debug;
-use std::io::{Cursor, Read, Write};
+use heffalump_conduit_derive::{CRecord, OnDevice};
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+
+/// Format version of the records this conduit writes. Bump it when a record
+/// changes shape, and only write the new shape to devices reporting it.
+///
+/// 1. Schema header in the prefs, `TootWrite::DeviceSchema`
+/// 2. `TootWrite::FetchLimits`
+/// 3. `HeffalumpNotificationsDB`
+/// 4. `HeffalumpConversationsDB`, `TootWrite::DirectReply`
+/// 5. Extra timelines (lists, hashtags, local, federated) in the prefs
+/// 6. `TootWrite::Bookmark`, `TootWrite::Unbookmark`
+/// 7. `TootAccount` instead of `TootAuthor` in `HeffalumpAuthorDB`
+/// 8. `TootProfile` in `HeffalumpAuthorDB`, `TootWrite::FollowAuthor`,
+///    `TootWrite::UnfollowAuthor`
+/// 9. `TootContentV2` in `HeffalumpContentDB`, `TootWrite::TootV2`
+/// 10. `HeffalumpPollsDB`, `TootWrite::Vote`
+/// 11. `TootContentV3` in `HeffalumpContentDB`
+/// 12. `HeffalumpStatusDB`
+pub(crate) const SCHEMA_VERSION: u16 = 12;
+/// Oldest device schema the conduit can still write for
+pub(crate) const MIN_SCHEMA_VERSION: u16 = 0;
+/// Optional parts of the format this conduit can write, one bit each
+pub(crate) const SUPPORTED_FEATURES: u16 = 0;
+
+/// Heads the prefs block, and is what the device app reports about itself
+/// through `TootWrite::DeviceSchema`
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, OnDevice, CRecord)]
+pub(crate) struct SchemaHeader {
+    pub(crate) version: u16,
+    pub(crate) features: u16,
+}
 
-#[derive(Default, Debug, Clone, serde::Serialize, serde::Deserialize)]
+/// Written with `set_preferences`. Devices on schema 0 predate the header and
+/// only get the timeline lengths, `timelines` is only written from schema 5.
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize, CRecord)]
 pub(crate) struct HeffalumpPrefs {
+    #[serde(default)]
+    pub(crate) schema: SchemaHeader,
     pub(crate) home_timeline_len: u16,
     pub(crate) self_timeline_len: u16,
     pub(crate) reply_content_len: u16,
+    #[serde(default)]
+    #[on_device(len = "u8")]
+    pub(crate) timelines: Vec<ExtraTimeline>,
+}
+
+/// A Mastodon list, hashtag, or the local or federated timeline, as the
+/// `timeline_len` content records from `start`. They follow the self
+/// timeline, one after another.
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize, OnDevice, CRecord)]
+pub(crate) struct ExtraTimeline {
+    pub(crate) start: u16,
+    pub(crate) timeline_len: u16,
+    #[on_device(len = "u8")]
+    pub(crate) title: Vec<u8>,
+}
+
+/// The whole prefs block before schema version 1
+#[derive(OnDevice)]
+struct LegacyPrefs {
+    home_timeline_len: u16,
+    self_timeline_len: u16,
+    reply_content_len: u16,
+}
+
+const LEGACY_PREFS_LEN: u64 = 6;
+
+/// Follows the legacy prefs from schema version 5
+#[derive(OnDevice)]
+struct PrefsTimelines {
+    #[on_device(len = "u8")]
+    timelines: Vec<ExtraTimeline>,
 }
 
+impl OnDevice for HeffalumpPrefs {
+    fn write_hh(&self, cursor: &mut Cursor<Vec<u8>>) -> std::io::Result<()> {
+        if self.schema.version != 0 {
+            self.schema.write_hh(cursor)?;
+        }
+        LegacyPrefs {
+            home_timeline_len: self.home_timeline_len,
+            self_timeline_len: self.self_timeline_len,
+            reply_content_len: self.reply_content_len,
+        }
+        .write_hh(cursor)?;
+        match self.schema.version >= 5 {
+            true => PrefsTimelines {
+                timelines: self.timelines.clone(),
+            }
+            .write_hh(cursor),
+            false => Ok(()),
+        }
+    }
+
+    fn read_hh(cursor: &mut Cursor<&[u8]>) -> std::io::Result<Self> {
+        let remaining = (cursor.get_ref().len() as u64).saturating_sub(cursor.position());
+        let schema = match remaining {
+            LEGACY_PREFS_LEN => SchemaHeader::default(),
+            _ => SchemaHeader::read_hh(cursor)?,
+        };
+        if remaining != LEGACY_PREFS_LEN && schema.version == 0 {
+            // version 0 is the layout without a header
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Prefs have a schema header with version 0",
+            ));
+        }
+        let legacy = LegacyPrefs::read_hh(cursor)?;
+        let timelines = match schema.version >= 5 {
+            true => PrefsTimelines::read_hh(cursor)?.timelines,
+            false => Vec::new(),
+        };
+        Ok(HeffalumpPrefs {
+            schema,
+            home_timeline_len: legacy.home_timeline_len,
+            self_timeline_len: legacy.self_timeline_len,
+            reply_content_len: legacy.reply_content_len,
+            timelines,
+        })
+    }
+}
+
+/// Records shared with the handheld. Implement with `#[derive(OnDevice)]`,
+/// see `heffalump_conduit_derive` for the available attributes.
 pub trait OnDevice: Sized {
-    fn to_hh_bytes(&self) -> std::io::Result<Vec<u8>>;
-    fn from_hh_bytes(bytes: &[u8]) -> std::io::Result<Self>;
+    fn write_hh(&self, cursor: &mut Cursor<Vec<u8>>) -> std::io::Result<()>;
+    fn read_hh(cursor: &mut Cursor<&[u8]>) -> std::io::Result<Self>;
+
+    fn to_hh_bytes(&self) -> std::io::Result<Vec<u8>> {
+        let mut cursor = Cursor::new(Vec::new());
+        self.write_hh(&mut cursor)?;
+        Ok(cursor.into_inner())
+    }
+
+    /// Decodes a whole record, rejecting truncated records and trailing bytes
+    fn from_hh_bytes(bytes: &[u8]) -> std::io::Result<Self> {
+        let name = std::any::type_name::<Self>()
+            .rsplit("::")
+            .next()
+            .unwrap_or_default();
+        let mut cursor = Cursor::new(bytes);
+        let value = Self::read_hh(&mut cursor).map_err(|e| match e.kind() {
+            std::io::ErrorKind::UnexpectedEof => std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{name} record ends early, it's only {} bytes", bytes.len()),
+            ),
+            _ => e,
+        })?;
+        match bytes.len() as u64 - cursor.position() {
+            0 => Ok(value),
+            trailing => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{trailing} trailing bytes after a {name} record"),
+            )),
+        }
+    }
+}
+
+/// Lists of author or content indices, e.g. `Vec<u16>` fields
+impl OnDevice for u16 {
+    fn write_hh(&self, cursor: &mut Cursor<Vec<u8>>) -> std::io::Result<()> {
+        cursor.write_u16::<BigEndian>(*self)
+    }
+
+    fn read_hh(cursor: &mut Cursor<&[u8]>) -> std::io::Result<Self> {
+        cursor.read_u16::<BigEndian>()
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, OnDevice, CRecord)]
 pub(crate) struct TootContent {
     pub(crate) author: u16,
     pub(crate) is_reply_to: u16,
     pub(crate) replies_start: u16,
-    // pub(crate) content_len: u16, not used in rust, needed in c
+    #[on_device(len = "u16")]
+    pub(crate) contents: Vec<u8>,
+}
+
+/// A content record from schema 9, `TootContent` with the toot's content
+/// warning
+#[derive(Debug, Clone, PartialEq, Eq, OnDevice, CRecord)]
+pub(crate) struct TootContentV2 {
+    pub(crate) author: u16,
+    pub(crate) is_reply_to: u16,
+    pub(crate) replies_start: u16,
+    /// Show `warning` with `contents` collapsed until the reader opens it
+    pub(crate) sensitive: bool,
+    /// Empty without a content warning
+    #[on_device(len = "u8")]
+    pub(crate) warning: Vec<u8>,
+    #[on_device(len = "u16")]
+    pub(crate) contents: Vec<u8>,
+}
+
+/// A content record from schema 11, `TootContentV2` with its place in the
+/// conversation. `is_reply_to` and `replies_start` still group each timeline
+/// toot's thread.
+#[derive(Debug, Clone, PartialEq, Eq, OnDevice, CRecord)]
+pub(crate) struct TootContentV3 {
+    pub(crate) author: u16,
+    pub(crate) is_reply_to: u16,
+    pub(crate) replies_start: u16,
+    /// Index of the toot this one replies to, `NO_CONTENT` when it isn't a
+    /// reply or that toot wasn't synced
+    pub(crate) parent: u16,
+    /// 1 for replies to the timeline toot, 2 for replies to those and so on.
+    /// 0 for the timeline toots and their ancestors.
+    pub(crate) depth: u8,
+    /// One of the toots a timeline toot replies to, shown above it
+    pub(crate) ancestor: bool,
+    pub(crate) sensitive: bool,
+    #[on_device(len = "u8")]
+    pub(crate) warning: Vec<u8>,
+    #[on_device(len = "u16")]
     pub(crate) contents: Vec<u8>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, OnDevice, CRecord)]
 pub(crate) struct TootAuthor {
-    // pub(crate) author_name_len: u16, not used in rust, needed in c
+    #[on_device(len = "u8")]
+    pub(crate) author_name: Vec<u8>,
+}
+
+/// An author record from schema 7, with who `author_name` stands for
+#[derive(Debug, Clone, PartialEq, Eq, OnDevice, CRecord)]
+pub(crate) struct TootAccount {
+    /// `@user`, with the instance when another author shares the username
+    #[on_device(len = "u8", null_terminated)]
     pub(crate) author_name: Vec<u8>,
+    /// `@user@instance`
+    #[on_device(len = "u8", null_terminated)]
+    pub(crate) handle: Vec<u8>,
+    #[on_device(len = "u8", null_terminated)]
+    pub(crate) display_name: Vec<u8>,
+    /// The instance's id for the account
+    #[on_device(len = "u8", null_terminated)]
+    pub(crate) account_id: Vec<u8>,
 }
 
-#[derive(Debug, Clone)]
+/// An author record from schema 8, for the device's profile screen. Starts
+/// like a `TootAccount`.
+#[derive(Debug, Clone, PartialEq, Eq, OnDevice, CRecord)]
+pub(crate) struct TootProfile {
+    pub(crate) account: TootAccount,
+    pub(crate) followers_count: u32,
+    pub(crate) following_count: u32,
+    pub(crate) bot: bool,
+    /// Follows have to be approved
+    pub(crate) locked: bool,
+    /// Whether the user follows them
+    pub(crate) following: bool,
+    #[on_device(len = "u16")]
+    pub(crate) bio: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, OnDevice, CRecord)]
+#[on_device(discriminant = "u16")]
 pub(crate) enum TootWrite {
     Favorite(u16),
+    /// Follows the author of the content record, or of what it boosts
     Follow(u16),
     Reblog(u16),
     Toot(TootContent),
+    /// The schema the device app was built against, sent on every sync
+    DeviceSchema(SchemaHeader),
+    /// Only sent once the prefs report schema 2 or later
+    FetchLimits(FetchLimits),
+    DirectReply(DirectReply),
+    /// Only sent once the prefs report schema 6 or later
+    Bookmark(u16),
+    Unbookmark(u16),
+    /// Index into the author database, only sent once the prefs report
+    /// schema 8 or later
+    FollowAuthor(u16),
+    UnfollowAuthor(u16),
+    /// A toot with a content warning, only sent once the prefs report schema
+    /// 9 or later
+    TootV2(TootContentV2),
+    /// Only sent once the prefs report schema 10 or later
+    Vote(PollVote),
 }
 
-impl TootWrite {
-    fn c_enum_val(&self) -> u16 {
-        match self {
-            TootWrite::Favorite(_) => 0,
-            TootWrite::Follow(_) => 1,
-            TootWrite::Reblog(_) => 2,
-            TootWrite::Toot(_) => 3,
-        }
-    }
+/// The options chosen in a poll on the handheld
+#[derive(Debug, Clone, PartialEq, Eq, OnDevice, CRecord)]
+pub(crate) struct PollVote {
+    /// Index into the content database of the toot with the poll
+    pub(crate) content: u16,
+    /// Indices into the poll's options
+    #[on_device(len = "u8")]
+    pub(crate) choices: Vec<u8>,
 }
 
-impl OnDevice for TootContent {
-    fn to_hh_bytes(&self) -> std::io::Result<Vec<u8>> {
-        let mut cursor = Cursor::new(Vec::new());
-        cursor.write_u16::<BigEndian>(self.author)?;
-        cursor.write_u16::<BigEndian>(self.is_reply_to)?;
-        cursor.write_u16::<BigEndian>(self.replies_start)?;
-        cursor.write_u16::<BigEndian>(self.contents.len() as u16)?;
-        cursor.write_all(&self.contents)?;
+/// A reply to a message in `HeffalumpConversationsDB`, posted with direct
+/// visibility to everyone in the conversation
+#[derive(Debug, Clone, PartialEq, Eq, OnDevice, CRecord)]
+pub(crate) struct DirectReply {
+    /// Index into the conversations database
+    pub(crate) message: u16,
+    #[on_device(len = "u16")]
+    pub(crate) contents: Vec<u8>,
+}
 
-        Ok(cursor.into_inner())
-    }
+/// How many toots the device wants, overriding `heffalump_config.json`.
+/// 0 keeps the configured value.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, OnDevice, CRecord)]
+pub(crate) struct FetchLimits {
+    pub(crate) home_timeline: u16,
+    pub(crate) self_timeline: u16,
+    pub(crate) replies: u16,
+}
 
-    fn from_hh_bytes(bytes: &[u8]) -> std::io::Result<Self> {
-        let mut cursor = Cursor::new(bytes);
-        let author = cursor.read_u16::<BigEndian>()?;
-        let is_reply_to = cursor.read_u16::<BigEndian>()?;
-        let replies_start = cursor.read_u16::<BigEndian>()?;
-        let content_len = cursor.read_u16::<BigEndian>()? as usize;
-
-        if content_len == 0 {
-            debug!("Size of content is: {} bytes", content_len);
-            debug!("content is: \n{:?} ", bytes);
-        }
-        let mut content_buf = vec![0_u8; content_len];
-        cursor.read_exact(&mut content_buf)?;
-        Ok(Self {
-            author,
-            is_reply_to,
-            replies_start,
-            contents: content_buf,
-        })
-    }
+/// Who interacted with the user, one record per notification in
+/// `HeffalumpNotificationsDB`, newest first
+#[derive(Debug, Clone, PartialEq, Eq, OnDevice, CRecord)]
+pub(crate) struct TootNotification {
+    pub(crate) kind: NotificationKind,
+    /// Index into the author database
+    pub(crate) author: u16,
+    /// Index into the content database, `NO_CONTENT` for follows and toots
+    /// that weren't synced
+    pub(crate) content: u16,
+    /// Seconds since 1904
+    pub(crate) created: u32,
+    /// The start of the toot, for when `content` is `NO_CONTENT`
+    #[on_device(len = "u16")]
+    pub(crate) excerpt: Vec<u8>,
 }
 
-impl OnDevice for TootAuthor {
-    fn to_hh_bytes(&self) -> std::io::Result<Vec<u8>> {
-        let mut cursor = Cursor::new(Vec::new());
-        cursor.write_u8(self.author_name.len() as u8)?;
-        cursor.write_all(&self.author_name)?;
+/// One direct message, written to `HeffalumpConversationsDB` with the secret
+/// attribute. Messages between the same people share a `conversation` and
+/// are consecutive, oldest first.
+#[derive(Debug, Clone, PartialEq, Eq, OnDevice, CRecord)]
+pub(crate) struct TootMessage {
+    pub(crate) conversation: u16,
+    /// Index into the author database
+    pub(crate) author: u16,
+    /// Everyone in the conversation but the user, as author indices
+    #[on_device(len = "u8")]
+    pub(crate) participants: Vec<u16>,
+    pub(crate) unread: bool,
+    #[on_device(len = "u16")]
+    pub(crate) contents: Vec<u8>,
+}
 
-        Ok(cursor.into_inner())
-    }
+/// `TootNotification::content` when there is no content record to point at
+pub(crate) const NO_CONTENT: u16 = u16::MAX;
 
-    fn from_hh_bytes(bytes: &[u8]) -> std::io::Result<Self> {
-        let mut cursor = Cursor::new(bytes);
-        let mut content_buf = vec![0_u8; cursor.read_u8()? as usize];
-        cursor.read_exact(&mut content_buf)?;
-        Ok(Self {
-            author_name: content_buf,
-        })
-    }
+/// A poll on a synced toot, one record per poll in `HeffalumpPollsDB` in
+/// content database order
+#[derive(Debug, Clone, PartialEq, Eq, OnDevice, CRecord)]
+pub(crate) struct TootPoll {
+    /// Index into the content database
+    pub(crate) content: u16,
+    /// Seconds since 1904, 0 for polls that stay open
+    pub(crate) expires: u32,
+    pub(crate) expired: bool,
+    /// Whether more than one option can be chosen
+    pub(crate) multiple: bool,
+    /// Whether the user has voted
+    pub(crate) voted: bool,
+    pub(crate) votes_count: u32,
+    #[on_device(len = "u8")]
+    pub(crate) options: Vec<PollOption>,
 }
 
-impl OnDevice for TootWrite {
-    fn to_hh_bytes(&self) -> std::io::Result<Vec<u8>> {
-        let mut cursor = Cursor::new(Vec::new());
-        cursor.write_u16::<BigEndian>(self.c_enum_val())?;
-        match self {
-            TootWrite::Favorite(val) => cursor.write_u16::<BigEndian>(*val)?,
-            TootWrite::Follow(val) => cursor.write_u16::<BigEndian>(*val)?,
-            TootWrite::Reblog(val) => cursor.write_u16::<BigEndian>(*val)?,
-            TootWrite::Toot(toot) => cursor.write_all(toot.clone().to_hh_bytes()?.as_ref())?,
-        }
+#[derive(Debug, Clone, PartialEq, Eq, OnDevice, CRecord)]
+pub(crate) struct PollOption {
+    /// `HIDDEN_VOTES` while the poll hides its results
+    pub(crate) votes_count: u32,
+    #[on_device(len = "u8")]
+    pub(crate) title: Vec<u8>,
+}
 
-        Ok(cursor.into_inner())
-    }
+/// `PollOption::votes_count` before a poll with hidden results ends
+pub(crate) const HIDDEN_VOTES: u32 = u32::MAX;
 
-    fn from_hh_bytes(bytes: &[u8]) -> std::io::Result<Self> {
-        let mut cursor = Cursor::new(bytes);
-        let discrim = cursor.read_u16::<BigEndian>()?;
-        match discrim {
-            0 => Ok(Self::Favorite(cursor.read_u16::<BigEndian>()?)),
-            1 => Ok(Self::Follow(cursor.read_u16::<BigEndian>()?)),
-            2 => Ok(Self::Reblog(cursor.read_u16::<BigEndian>()?)),
-            3 => Ok(Self::Toot(TootContent::from_hh_bytes(
-                cursor.get_ref()[(cursor.position() as usize)..].as_ref(),
-            )?)),
-            _ => Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "Invalid discriminant",
-            )),
-        }
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Eq, OnDevice, CRecord)]
+#[on_device(discriminant = "u8")]
+pub(crate) enum NotificationKind {
+    Mention,
+    Favourite,
+    Reblog,
+    Follow,
+    PollEnded,
 }
 
-impl OnDevice for HeffalumpPrefs {
-    fn to_hh_bytes(&self) -> std::io::Result<Vec<u8>> {
-        let mut cursor = Cursor::new(Vec::new());
-        cursor.write_u16::<BigEndian>(self.home_timeline_len)?;
-        cursor.write_u16::<BigEndian>(self.self_timeline_len)?;
-        cursor.write_u16::<BigEndian>(self.reply_content_len)?;
-        Ok(cursor.into_inner())
+/// How the last sync went, the only record in `HeffalumpStatusDB`
+#[derive(Debug, Clone, PartialEq, Eq, OnDevice, CRecord)]
+pub(crate) struct SyncStatus {
+    /// Seconds since 1904
+    pub(crate) synced: u32,
+    /// Empty when everything was downloaded
+    #[on_device(len = "u8")]
+    pub(crate) failures: Vec<SyncFailure>,
+}
+
+/// Something the sync couldn't download, the rest was shipped without it
+#[derive(Debug, Clone, PartialEq, Eq, OnDevice, CRecord)]
+pub(crate) struct SyncFailure {
+    pub(crate) source: SyncSource,
+    /// Whether the previous sync's copy was shipped in its place, otherwise
+    /// the source is empty until the next sync
+    pub(crate) cached: bool,
+    /// Which of the extra timelines, or the database of a record cut short.
+    /// Empty for the other sources.
+    #[on_device(len = "u8")]
+    pub(crate) title: Vec<u8>,
+    /// What went wrong, for showing to the user
+    #[on_device(len = "u8")]
+    pub(crate) error: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, OnDevice, CRecord)]
+#[on_device(discriminant = "u8")]
+pub(crate) enum SyncSource {
+    Home,
+    SelfTimeline,
+    /// The extra timelines, the failure's title says which
+    Bookmarks,
+    Favourites,
+    List,
+    Hashtag,
+    LocalTimeline,
+    FederatedTimeline,
+    /// A toot's conversation, a failure for each one that couldn't be
+    /// looked up
+    Replies,
+    Notifications,
+    Conversations,
+    /// Which authors the user follows
+    Following,
+    /// A record too long for its fields, shipped cut short. The failure's
+    /// title names its database.
+    Record,
+}
+
+/// The C declaration of a record, for the device app. Implement with
+/// `#[derive(CRecord)]` next to `OnDevice`.
+pub trait CRecord {
+    /// The C type members of this type are declared with
+    const C_NAME: &'static str;
+    /// Whether the record ends in (or contains) variable length data
+    const VARIABLE_LEN: bool;
+
+    fn c_definition() -> String;
+}
+
+impl CRecord for u16 {
+    const C_NAME: &'static str = "UInt16";
+    const VARIABLE_LEN: bool = false;
+
+    fn c_definition() -> String {
+        String::new()
     }
+}
 
-    fn from_hh_bytes(bytes: &[u8]) -> std::io::Result<Self> {
-        let mut cursor = Cursor::new(bytes);
+const HEADER_PREAMBLE: &str = r#"/* Generated from conduit/src/heffalump_hh_types.rs, do not edit.
+ *
+ * Records are packed and big endian. Members after a flexible array
+ * are listed in comments and have to be read at runtime.
+ *
+ * The device app reports HEFFALUMP_SCHEMA_VERSION in a TootWrite of type
+ * TootWriteType_DeviceSchema on every sync, and the conduit writes
+ * HeffalumpPrefs for that version from the following sync on, or for
+ * version 0 after a sync without the report. Version 0 prefs have no schema
+ * header and start at home_timeline_len, and only version 5 and later have
+ * the extra timelines. HeffalumpNotificationsDB is only written for version 3 and
+ * later, HeffalumpConversationsDB for 4 and later. HeffalumpAuthorDB holds
+ * TootProfile records from version 8, TootAccount ones in version 7 and
+ * TootAuthor ones before. HeffalumpContentDB holds TootContentV3 records
+ * from version 11, TootContentV2 ones in versions 9 and 10 and TootContent
+ * ones before. HeffalumpPollsDB is only written for version 10 and later,
+ * HeffalumpStatusDB for 12 and later.
+ */
 
-        Ok(HeffalumpPrefs {
-            home_timeline_len: cursor.read_u16::<BigEndian>()?,
-            self_timeline_len: cursor.read_u16::<BigEndian>()?,
-            reply_content_len: cursor.read_u16::<BigEndian>()?,
-        })
+#ifndef HEFFALUMP_RECORDS_H
+#define HEFFALUMP_RECORDS_H
+
+#include <PalmOS.h>
+"#;
+
+/// `heffalump_records.h`, the records as the device app declares them.
+/// Regenerate the checked in copy with `HEFFALUMP_BLESS=1 cargo test`.
+pub(crate) fn c_header() -> String {
+    let mut out = String::from(HEADER_PREAMBLE);
+    out.push_str(&format!(
+        "\n#define HEFFALUMP_SCHEMA_VERSION {SCHEMA_VERSION}\n\
+         #define HEFFALUMP_NO_CONTENT {NO_CONTENT:#06x}\n\
+         #define HEFFALUMP_HIDDEN_VOTES {HIDDEN_VOTES:#010x}\n"
+    ));
+    for definition in [
+        SchemaHeader::c_definition(),
+        TootContent::c_definition(),
+        TootContentV2::c_definition(),
+        TootContentV3::c_definition(),
+        TootAuthor::c_definition(),
+        TootAccount::c_definition(),
+        TootProfile::c_definition(),
+        FetchLimits::c_definition(),
+        DirectReply::c_definition(),
+        PollVote::c_definition(),
+        TootWrite::c_definition(),
+        ExtraTimeline::c_definition(),
+        HeffalumpPrefs::c_definition(),
+        NotificationKind::c_definition(),
+        TootNotification::c_definition(),
+        TootMessage::c_definition(),
+        PollOption::c_definition(),
+        TootPoll::c_definition(),
+        SyncSource::c_definition(),
+        SyncFailure::c_definition(),
+        SyncStatus::c_definition(),
+    ] {
+        out.push('\n');
+        out.push_str(&definition);
     }
+    out.push_str("\n#endif /* HEFFALUMP_RECORDS_H */\n");
+    out
 }
 
 impl TryFrom<Vec<u8>> for HeffalumpPrefs {
@@ -209,3 +536,793 @@ impl TryInto<Vec<u8>> for HeffalumpPrefs {
         self.to_hh_bytes()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use proptest::{collection::vec, prelude::*};
+
+    use super::{
+        c_header, CRecord, DirectReply, ExtraTimeline, FetchLimits, HeffalumpPrefs,
+        NotificationKind, OnDevice, PollOption, PollVote, SchemaHeader, SyncFailure, SyncSource,
+        SyncStatus, TootAccount, TootAuthor, TootContent, TootContentV2, TootContentV3,
+        TootMessage, TootNotification, TootPoll, TootProfile, TootWrite, HIDDEN_VOTES, NO_CONTENT,
+    };
+    use crate::fuzz;
+
+    #[test]
+    fn test_toot_content_layout() {
+        let content = TootContent {
+            author: 1,
+            is_reply_to: 2,
+            replies_start: 0x0304,
+            contents: b"hi".to_vec(),
+        };
+        let bytes = content.to_hh_bytes().unwrap();
+        assert_eq!(bytes, [0, 1, 0, 2, 3, 4, 0, 2, b'h', b'i']);
+        assert_eq!(TootContent::from_hh_bytes(&bytes).unwrap(), content);
+    }
+
+    #[test]
+    fn test_toot_content_v2_layout() {
+        let content = TootContentV2 {
+            author: 1,
+            is_reply_to: 2,
+            replies_start: 0x0304,
+            sensitive: true,
+            warning: b"cw".to_vec(),
+            contents: b"hi".to_vec(),
+        };
+        let bytes = content.to_hh_bytes().unwrap();
+        assert_eq!(
+            bytes,
+            [0, 1, 0, 2, 3, 4, 1, 2, b'c', b'w', 0, 2, b'h', b'i']
+        );
+        assert_eq!(TootContentV2::from_hh_bytes(&bytes).unwrap(), content);
+        assert!(TootContent::from_hh_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_toot_content_v3_layout() {
+        let content = TootContentV3 {
+            author: 1,
+            is_reply_to: 2,
+            replies_start: 0,
+            parent: NO_CONTENT,
+            depth: 3,
+            ancestor: false,
+            sensitive: true,
+            warning: b"cw".to_vec(),
+            contents: b"hi".to_vec(),
+        };
+        let bytes = content.to_hh_bytes().unwrap();
+        assert_eq!(
+            bytes,
+            [0, 1, 0, 2, 0, 0, 0xff, 0xff, 3, 0, 1, 2, b'c', b'w', 0, 2, b'h', b'i']
+        );
+        assert_eq!(TootContentV3::from_hh_bytes(&bytes).unwrap(), content);
+        assert!(TootContentV2::from_hh_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_toot_author_layout() {
+        let author = TootAuthor {
+            author_name: b"@me\0".to_vec(),
+        };
+        let bytes = author.to_hh_bytes().unwrap();
+        assert_eq!(bytes, [4, b'@', b'm', b'e', 0]);
+        assert_eq!(TootAuthor::from_hh_bytes(&bytes).unwrap(), author);
+    }
+
+    #[test]
+    fn test_toot_account_layout() {
+        let account = TootAccount {
+            author_name: b"@me".to_vec(),
+            handle: b"@me@a.b".to_vec(),
+            display_name: Vec::new(),
+            account_id: b"12".to_vec(),
+        };
+        let bytes = account.to_hh_bytes().unwrap();
+        assert_eq!(
+            bytes,
+            [
+                4, b'@', b'm', b'e', 0, 8, b'@', b'm', b'e', b'@', b'a', b'.', b'b', 0, 1, 0, 3,
+                b'1', b'2', 0
+            ]
+        );
+        assert_eq!(TootAccount::from_hh_bytes(&bytes).unwrap(), account);
+        // the two author records can't be mistaken for each other
+        assert!(TootAuthor::from_hh_bytes(&bytes).is_err());
+        assert!(TootAccount::from_hh_bytes(&[4, b'@', b'm', b'e', 0]).is_err());
+    }
+
+    #[test]
+    fn test_toot_profile_layout() {
+        let account = TootAccount {
+            author_name: b"@me".to_vec(),
+            handle: b"@me@a.b".to_vec(),
+            display_name: Vec::new(),
+            account_id: b"12".to_vec(),
+        };
+        let profile = TootProfile {
+            account: account.clone(),
+            followers_count: 0x0102,
+            following_count: 3,
+            bot: false,
+            locked: true,
+            following: true,
+            bio: b"hi".to_vec(),
+        };
+        let bytes = profile.to_hh_bytes().unwrap();
+        let prefix = account.to_hh_bytes().unwrap();
+        assert_eq!(bytes[..prefix.len()], prefix);
+        assert_eq!(
+            bytes[prefix.len()..],
+            [0, 0, 1, 2, 0, 0, 0, 3, 0, 1, 1, 0, 2, b'h', b'i']
+        );
+        assert_eq!(TootProfile::from_hh_bytes(&bytes).unwrap(), profile);
+        assert!(TootAccount::from_hh_bytes(&bytes).is_err());
+        assert!(TootProfile::from_hh_bytes(&prefix).is_err());
+        assert!(TootProfile::c_definition().contains("/* followed by: UInt32 followers_count; */"));
+    }
+
+    #[test]
+    fn test_toot_write_layout() {
+        assert_eq!(TootWrite::Reblog(7).to_hh_bytes().unwrap(), [0, 2, 0, 7]);
+        let toot = TootWrite::Toot(TootContent {
+            author: 0,
+            is_reply_to: 1,
+            replies_start: 0,
+            contents: b"a".to_vec(),
+        });
+        let bytes = toot.to_hh_bytes().unwrap();
+        assert_eq!(bytes, [0, 3, 0, 0, 0, 1, 0, 0, 0, 1, b'a']);
+        assert_eq!(TootWrite::from_hh_bytes(&bytes).unwrap(), toot);
+        assert!(TootWrite::from_hh_bytes(&[0, 13, 0, 0]).is_err());
+        assert_eq!(
+            TootWrite::from_hh_bytes(&[0, 4, 0, 1, 0, 0]).unwrap(),
+            TootWrite::DeviceSchema(SchemaHeader {
+                version: 1,
+                features: 0
+            })
+        );
+        assert_eq!(
+            TootWrite::FetchLimits(FetchLimits {
+                home_timeline: 50,
+                self_timeline: 0,
+                replies: 5,
+            })
+            .to_hh_bytes()
+            .unwrap(),
+            [0, 5, 0, 50, 0, 0, 0, 5]
+        );
+        assert_eq!(
+            TootWrite::DirectReply(DirectReply {
+                message: 2,
+                contents: b"ok".to_vec(),
+            })
+            .to_hh_bytes()
+            .unwrap(),
+            [0, 6, 0, 2, 0, 2, b'o', b'k']
+        );
+        assert_eq!(
+            TootWrite::from_hh_bytes(&[0, 8, 0, 3]).unwrap(),
+            TootWrite::Unbookmark(3)
+        );
+        assert_eq!(
+            TootWrite::FollowAuthor(4).to_hh_bytes().unwrap(),
+            [0, 9, 0, 4]
+        );
+        assert_eq!(
+            TootWrite::from_hh_bytes(&[0, 12, 0, 3, 2, 0, 1]).unwrap(),
+            TootWrite::Vote(PollVote {
+                content: 3,
+                choices: vec![0, 1],
+            })
+        );
+    }
+
+    #[test]
+    fn test_toot_poll_layout() {
+        let poll = TootPoll {
+            content: 2,
+            expires: 0x0102_0304,
+            expired: false,
+            multiple: true,
+            voted: false,
+            votes_count: 5,
+            options: vec![
+                PollOption {
+                    votes_count: 5,
+                    title: b"yes".to_vec(),
+                },
+                PollOption {
+                    votes_count: HIDDEN_VOTES,
+                    title: b"no".to_vec(),
+                },
+            ],
+        };
+        let bytes = poll.to_hh_bytes().unwrap();
+        assert_eq!(
+            bytes,
+            [
+                0, 2, 1, 2, 3, 4, 0, 1, 0, 0, 0, 0, 5, 2, 0, 0, 0, 5, 3, b'y', b'e', b's', 0xff,
+                0xff, 0xff, 0xff, 2, b'n', b'o'
+            ]
+        );
+        assert_eq!(TootPoll::from_hh_bytes(&bytes).unwrap(), poll);
+        // claims a third option
+        let mut truncated = bytes.clone();
+        truncated[13] = 3;
+        assert!(TootPoll::from_hh_bytes(&truncated).is_err());
+        assert!(TootPoll::c_definition().contains("    PollOption options[];\n"));
+    }
+
+    #[test]
+    fn test_sync_status_layout() {
+        let status = SyncStatus {
+            synced: 0x0102_0304,
+            failures: vec![SyncFailure {
+                source: SyncSource::List,
+                cached: true,
+                title: b"team".to_vec(),
+                error: b"busy".to_vec(),
+            }],
+        };
+        let bytes = status.to_hh_bytes().unwrap();
+        assert_eq!(
+            bytes,
+            [1, 2, 3, 4, 1, 4, 1, 4, b't', b'e', b'a', b'm', 4, b'b', b'u', b's', b'y']
+        );
+        assert_eq!(SyncStatus::from_hh_bytes(&bytes).unwrap(), status);
+        // an unknown source
+        assert!(SyncStatus::from_hh_bytes(&[1, 2, 3, 4, 1, 13, 0, 0, 0]).is_err());
+        assert!(SyncStatus::c_definition().contains("    SyncFailure failures[];\n"));
+    }
+
+    #[test]
+    fn test_toot_message_layout() {
+        let message = TootMessage {
+            conversation: 1,
+            author: 2,
+            participants: vec![2, 0x0304],
+            unread: true,
+            contents: b"hi".to_vec(),
+        };
+        let bytes = message.to_hh_bytes().unwrap();
+        assert_eq!(bytes, [0, 1, 0, 2, 2, 0, 2, 3, 4, 1, 0, 2, b'h', b'i']);
+        assert_eq!(TootMessage::from_hh_bytes(&bytes).unwrap(), message);
+        // claims three participants
+        assert!(TootMessage::from_hh_bytes(&[0, 1, 0, 2, 3, 0, 2, 3, 4, 1, 0, 0]).is_err());
+        assert!(TootMessage::c_definition().contains("    UInt16 participants[];\n"));
+    }
+
+    #[test]
+    fn test_prefs_layout() {
+        let mut prefs = HeffalumpPrefs {
+            schema: SchemaHeader::default(),
+            home_timeline_len: 1,
+            self_timeline_len: 2,
+            reply_content_len: 3,
+            timelines: Vec::new(),
+        };
+        let legacy = prefs.to_hh_bytes().unwrap();
+        assert_eq!(legacy, [0, 1, 0, 2, 0, 3]);
+        assert_eq!(HeffalumpPrefs::from_hh_bytes(&legacy).unwrap(), prefs);
+
+        prefs.schema = SchemaHeader {
+            version: 1,
+            features: 0x8001,
+        };
+        let bytes = prefs.to_hh_bytes().unwrap();
+        assert_eq!(bytes, [0, 1, 0x80, 1, 0, 1, 0, 2, 0, 3]);
+        assert_eq!(HeffalumpPrefs::from_hh_bytes(&bytes).unwrap(), prefs);
+        assert!(HeffalumpPrefs::from_hh_bytes(&bytes[..8]).is_err());
+
+        // the extra timelines are only written to devices that know about them
+        prefs.timelines = vec![ExtraTimeline {
+            start: 3,
+            timeline_len: 2,
+            title: b"team".to_vec(),
+        }];
+        assert_eq!(prefs.to_hh_bytes().unwrap(), bytes);
+        prefs.schema.version = 5;
+        let bytes = prefs.to_hh_bytes().unwrap();
+        assert_eq!(bytes[10..], [1, 0, 3, 0, 2, 4, b't', b'e', b'a', b'm']);
+        assert_eq!(HeffalumpPrefs::from_hh_bytes(&bytes).unwrap(), prefs);
+        assert!(HeffalumpPrefs::from_hh_bytes(&bytes[..10]).is_err());
+    }
+
+    #[test]
+    fn test_toot_notification_layout() {
+        let notification = TootNotification {
+            kind: NotificationKind::Reblog,
+            author: 3,
+            content: NO_CONTENT,
+            created: 0x0102_0304,
+            excerpt: b"hi".to_vec(),
+        };
+        let bytes = notification.to_hh_bytes().unwrap();
+        assert_eq!(bytes, [2, 0, 3, 0xff, 0xff, 1, 2, 3, 4, 0, 2, b'h', b'i']);
+        assert_eq!(
+            TootNotification::from_hh_bytes(&bytes).unwrap(),
+            notification
+        );
+        assert!(TootNotification::from_hh_bytes(&[5, 0, 3, 0, 0, 1, 2, 3, 4, 0, 0]).is_err());
+
+        assert_eq!(
+            (NotificationKind::C_NAME, NotificationKind::VARIABLE_LEN),
+            ("UInt8", false)
+        );
+        assert!(
+            TootNotification::c_definition().contains("    UInt8 kind; /* NotificationKind */\n")
+        );
+    }
+
+    #[test]
+    fn test_strict_decoding() {
+        let err = |bytes: &[u8]| TootContent::from_hh_bytes(bytes).unwrap_err().to_string();
+        assert_eq!(
+            err(&[0, 1, 0, 2, 0, 0, 0, 2, b'h', b'i', 0]),
+            "1 trailing bytes after a TootContent record"
+        );
+        assert_eq!(
+            err(&[0, 1, 0, 2, 0, 0, 0xff, 0xff, b'h']),
+            "contents claims 65535 bytes but only 1 are left in the record"
+        );
+        assert_eq!(
+            err(&[0, 1, 0]),
+            "TootContent record ends early, it's only 3 bytes"
+        );
+        assert_eq!(
+            TootMessage::from_hh_bytes(&[0, 1, 0, 2, 0, 2, 0, 0])
+                .unwrap_err()
+                .to_string(),
+            "unread is 2, bools are 0 or 1"
+        );
+        assert!(TootAuthor::from_hh_bytes(&[5, b'@']).is_err());
+        assert!(TootWrite::from_hh_bytes(&[0, 0, 0, 1, 0]).is_err());
+        // a header claiming the headerless layout
+        assert!(HeffalumpPrefs::from_hh_bytes(&[0, 0, 0, 0, 0, 1, 0, 2, 0, 3]).is_err());
+    }
+
+    fn toot_content() -> impl Strategy<Value = TootContent> {
+        (
+            any::<u16>(),
+            any::<u16>(),
+            any::<u16>(),
+            vec(any::<u8>(), 0..300),
+        )
+            .prop_map(
+                |(author, is_reply_to, replies_start, contents)| TootContent {
+                    author,
+                    is_reply_to,
+                    replies_start,
+                    contents,
+                },
+            )
+    }
+
+    fn schema_header() -> impl Strategy<Value = SchemaHeader> {
+        (any::<u16>(), any::<u16>())
+            .prop_map(|(version, features)| SchemaHeader { version, features })
+    }
+
+    fn toot_write() -> impl Strategy<Value = TootWrite> {
+        prop_oneof![
+            any::<u16>().prop_map(TootWrite::Favorite),
+            any::<u16>().prop_map(TootWrite::Follow),
+            any::<u16>().prop_map(TootWrite::Reblog),
+            toot_content().prop_map(TootWrite::Toot),
+            schema_header().prop_map(TootWrite::DeviceSchema),
+            (any::<u16>(), any::<u16>(), any::<u16>()).prop_map(
+                |(home_timeline, self_timeline, replies)| TootWrite::FetchLimits(FetchLimits {
+                    home_timeline,
+                    self_timeline,
+                    replies,
+                })
+            ),
+            (any::<u16>(), vec(any::<u8>(), 0..300)).prop_map(|(message, contents)| {
+                TootWrite::DirectReply(DirectReply { message, contents })
+            }),
+            any::<u16>().prop_map(TootWrite::Bookmark),
+            any::<u16>().prop_map(TootWrite::Unbookmark),
+            any::<u16>().prop_map(TootWrite::FollowAuthor),
+            any::<u16>().prop_map(TootWrite::UnfollowAuthor),
+            toot_content_v2().prop_map(TootWrite::TootV2),
+            (any::<u16>(), vec(any::<u8>(), 0..=255))
+                .prop_map(|(content, choices)| TootWrite::Vote(PollVote { content, choices })),
+        ]
+    }
+
+    fn toot_poll() -> impl Strategy<Value = TootPoll> {
+        let option = (any::<u32>(), vec(any::<u8>(), 0..=255))
+            .prop_map(|(votes_count, title)| PollOption { votes_count, title });
+        (
+            any::<u16>(),
+            any::<u32>(),
+            any::<(bool, bool, bool)>(),
+            any::<u32>(),
+            vec(option, 0..=8),
+        )
+            .prop_map(
+                |(content, expires, (expired, multiple, voted), votes_count, options)| TootPoll {
+                    content,
+                    expires,
+                    expired,
+                    multiple,
+                    voted,
+                    votes_count,
+                    options,
+                },
+            )
+    }
+
+    fn toot_content_v2() -> impl Strategy<Value = TootContentV2> {
+        (
+            any::<u16>(),
+            any::<u16>(),
+            any::<u16>(),
+            any::<bool>(),
+            vec(any::<u8>(), 0..=255),
+            vec(any::<u8>(), 0..300),
+        )
+            .prop_map(
+                |(author, is_reply_to, replies_start, sensitive, warning, contents)| {
+                    TootContentV2 {
+                        author,
+                        is_reply_to,
+                        replies_start,
+                        sensitive,
+                        warning,
+                        contents,
+                    }
+                },
+            )
+    }
+
+    fn toot_content_v3() -> impl Strategy<Value = TootContentV3> {
+        (
+            (any::<u16>(), any::<u16>(), any::<u16>(), any::<u16>()),
+            (any::<u8>(), any::<bool>(), any::<bool>()),
+            vec(any::<u8>(), 0..=255),
+            vec(any::<u8>(), 0..300),
+        )
+            .prop_map(
+                |(
+                    (author, is_reply_to, replies_start, parent),
+                    (depth, ancestor, sensitive),
+                    warning,
+                    contents,
+                )| TootContentV3 {
+                    author,
+                    is_reply_to,
+                    replies_start,
+                    parent,
+                    depth,
+                    ancestor,
+                    sensitive,
+                    warning,
+                    contents,
+                },
+            )
+    }
+
+    fn toot_notification() -> impl Strategy<Value = TootNotification> {
+        let kind = prop_oneof![
+            Just(NotificationKind::Mention),
+            Just(NotificationKind::Favourite),
+            Just(NotificationKind::Reblog),
+            Just(NotificationKind::Follow),
+            Just(NotificationKind::PollEnded),
+        ];
+        (
+            kind,
+            any::<u16>(),
+            any::<u16>(),
+            any::<u32>(),
+            vec(any::<u8>(), 0..300),
+        )
+            .prop_map(
+                |(kind, author, content, created, excerpt)| TootNotification {
+                    kind,
+                    author,
+                    content,
+                    created,
+                    excerpt,
+                },
+            )
+    }
+
+    fn toot_message() -> impl Strategy<Value = TootMessage> {
+        (
+            any::<u16>(),
+            any::<u16>(),
+            vec(any::<u16>(), 0..=255),
+            any::<bool>(),
+            vec(any::<u8>(), 0..300),
+        )
+            .prop_map(|(conversation, author, participants, unread, contents)| {
+                TootMessage {
+                    conversation,
+                    author,
+                    participants,
+                    unread,
+                    contents,
+                }
+            })
+    }
+
+    fn toot_account() -> impl Strategy<Value = TootAccount> {
+        (
+            vec(any::<u8>(), 0..255),
+            vec(any::<u8>(), 0..255),
+            vec(any::<u8>(), 0..255),
+            vec(any::<u8>(), 0..255),
+        )
+            .prop_map(
+                |(author_name, handle, display_name, account_id)| TootAccount {
+                    author_name,
+                    handle,
+                    display_name,
+                    account_id,
+                },
+            )
+    }
+
+    fn toot_profile() -> impl Strategy<Value = TootProfile> {
+        (
+            toot_account(),
+            any::<u32>(),
+            any::<u32>(),
+            any::<(bool, bool, bool)>(),
+            vec(any::<u8>(), 0..600),
+        )
+            .prop_map(
+                |(account, followers_count, following_count, (bot, locked, following), bio)| {
+                    TootProfile {
+                        account,
+                        followers_count,
+                        following_count,
+                        bot,
+                        locked,
+                        following,
+                        bio,
+                    }
+                },
+            )
+    }
+
+    fn sync_status() -> impl Strategy<Value = SyncStatus> {
+        let source = prop_oneof![
+            Just(SyncSource::Home),
+            Just(SyncSource::SelfTimeline),
+            Just(SyncSource::Bookmarks),
+            Just(SyncSource::Favourites),
+            Just(SyncSource::List),
+            Just(SyncSource::Hashtag),
+            Just(SyncSource::LocalTimeline),
+            Just(SyncSource::FederatedTimeline),
+            Just(SyncSource::Replies),
+            Just(SyncSource::Notifications),
+            Just(SyncSource::Conversations),
+            Just(SyncSource::Following),
+            Just(SyncSource::Record),
+        ];
+        let failure = (
+            source,
+            any::<bool>(),
+            vec(any::<u8>(), 0..=255),
+            vec(any::<u8>(), 0..=255),
+        )
+            .prop_map(|(source, cached, title, error)| SyncFailure {
+                source,
+                cached,
+                title,
+                error,
+            });
+        (any::<u32>(), vec(failure, 0..=7))
+            .prop_map(|(synced, failures)| SyncStatus { synced, failures })
+    }
+
+    fn prefs() -> impl Strategy<Value = HeffalumpPrefs> {
+        // version 0 has no header to carry the features
+        let schema = prop_oneof![
+            Just(SchemaHeader::default()),
+            schema_header().prop_filter("version 0 is headerless", |s| s.version != 0),
+        ];
+        let timeline = (any::<u16>(), any::<u16>(), vec(any::<u8>(), 0..=255)).prop_map(
+            |(start, timeline_len, title)| ExtraTimeline {
+                start,
+                timeline_len,
+                title,
+            },
+        );
+        (
+            schema,
+            any::<u16>(),
+            any::<u16>(),
+            any::<u16>(),
+            vec(timeline, 0..8),
+        )
+            .prop_map(
+                |(schema, home_timeline_len, self_timeline_len, reply_content_len, timelines)| {
+                    HeffalumpPrefs {
+                        schema,
+                        home_timeline_len,
+                        self_timeline_len,
+                        reply_content_len,
+                        // older layouts drop them
+                        timelines: match schema.version >= 5 {
+                            true => timelines,
+                            false => Vec::new(),
+                        },
+                    }
+                },
+            )
+    }
+
+    proptest! {
+        #[test]
+        fn test_toot_content_round_trip(content in toot_content()) {
+            let bytes = content.to_hh_bytes().unwrap();
+            prop_assert_eq!(TootContent::from_hh_bytes(&bytes).unwrap(), content);
+        }
+
+        #[test]
+        fn test_toot_content_v2_round_trip(content in toot_content_v2()) {
+            let bytes = content.to_hh_bytes().unwrap();
+            prop_assert_eq!(TootContentV2::from_hh_bytes(&bytes).unwrap(), content);
+        }
+
+        #[test]
+        fn test_toot_content_v3_round_trip(content in toot_content_v3()) {
+            let bytes = content.to_hh_bytes().unwrap();
+            prop_assert_eq!(TootContentV3::from_hh_bytes(&bytes).unwrap(), content);
+        }
+
+        #[test]
+        fn test_toot_author_round_trip(author_name in vec(any::<u8>(), 0..=255)) {
+            let author = TootAuthor { author_name };
+            let bytes = author.to_hh_bytes().unwrap();
+            prop_assert_eq!(TootAuthor::from_hh_bytes(&bytes).unwrap(), author);
+        }
+
+        #[test]
+        fn test_toot_account_round_trip(account in toot_account()) {
+            let bytes = account.to_hh_bytes().unwrap();
+            prop_assert_eq!(TootAccount::from_hh_bytes(&bytes).unwrap(), account);
+        }
+
+        #[test]
+        fn test_toot_profile_round_trip(profile in toot_profile()) {
+            let bytes = profile.to_hh_bytes().unwrap();
+            prop_assert_eq!(TootProfile::from_hh_bytes(&bytes).unwrap(), profile);
+        }
+
+        #[test]
+        fn test_toot_write_round_trip(write in toot_write()) {
+            let bytes = write.to_hh_bytes().unwrap();
+            prop_assert_eq!(TootWrite::from_hh_bytes(&bytes).unwrap(), write);
+        }
+
+        #[test]
+        fn test_toot_notification_round_trip(notification in toot_notification()) {
+            let bytes = notification.to_hh_bytes().unwrap();
+            prop_assert_eq!(TootNotification::from_hh_bytes(&bytes).unwrap(), notification);
+        }
+
+        #[test]
+        fn test_toot_message_round_trip(message in toot_message()) {
+            let bytes = message.to_hh_bytes().unwrap();
+            prop_assert_eq!(TootMessage::from_hh_bytes(&bytes).unwrap(), message);
+        }
+
+        #[test]
+        fn test_toot_poll_round_trip(poll in toot_poll()) {
+            let bytes = poll.to_hh_bytes().unwrap();
+            prop_assert_eq!(TootPoll::from_hh_bytes(&bytes).unwrap(), poll);
+        }
+
+        #[test]
+        fn test_sync_status_round_trip(status in sync_status()) {
+            let bytes = status.to_hh_bytes().unwrap();
+            prop_assert_eq!(SyncStatus::from_hh_bytes(&bytes).unwrap(), status);
+        }
+
+        #[test]
+        fn test_prefs_round_trip(prefs in prefs()) {
+            let bytes = prefs.to_hh_bytes().unwrap();
+            prop_assert_eq!(HeffalumpPrefs::from_hh_bytes(&bytes).unwrap(), prefs);
+        }
+
+        #[test]
+        fn test_decoders_on_arbitrary_bytes(bytes in vec(any::<u8>(), 0..64)) {
+            fuzz::toot_content(&bytes);
+            fuzz::toot_author(&bytes);
+            fuzz::toot_write(&bytes);
+            fuzz::prefs(&bytes);
+            fuzz::toot_notification(&bytes);
+            fuzz::toot_message(&bytes);
+            fuzz::toot_account(&bytes);
+            fuzz::toot_profile(&bytes);
+            fuzz::toot_content_v2(&bytes);
+            fuzz::toot_poll(&bytes);
+            fuzz::toot_content_v3(&bytes);
+            fuzz::sync_status(&bytes);
+        }
+    }
+
+    #[test]
+    fn test_derive_attributes() {
+        #[derive(Debug, PartialEq, OnDevice, CRecord)]
+        #[on_device(endian = "little")]
+        struct Entry {
+            id: u32,
+            flag: bool,
+            #[on_device(null_terminated)]
+            name: Vec<u8>,
+        }
+
+        #[derive(Debug, PartialEq, OnDevice, CRecord)]
+        #[on_device(discriminant = "u8")]
+        enum Record {
+            Empty,
+            #[on_device(tag = 0x10)]
+            Entries {
+                #[on_device(len = "u16")]
+                entries: Vec<Entry>,
+                #[on_device(len = "u8", null_terminated)]
+                label: Vec<u8>,
+            },
+            Rest(Vec<u8>),
+        }
+
+        let record = Record::Entries {
+            entries: vec![Entry {
+                id: 0x0102,
+                flag: true,
+                name: b"ab".to_vec(),
+            }],
+            label: b"x".to_vec(),
+        };
+        let bytes = record.to_hh_bytes().unwrap();
+        assert_eq!(
+            bytes,
+            [0x10, 0, 1, 2, 1, 0, 0, 1, b'a', b'b', 0, 2, b'x', 0]
+        );
+        assert_eq!(Record::from_hh_bytes(&bytes).unwrap(), record);
+
+        assert_eq!(Record::Empty.to_hh_bytes().unwrap(), [0]);
+        assert_eq!(
+            Record::from_hh_bytes(&[0x11, 1, 2]).unwrap(),
+            Record::Rest(vec![1, 2])
+        );
+        // label's terminator is missing
+        assert!(Record::from_hh_bytes(&[0x10, 0, 0, 1, b'x']).is_err());
+
+        assert_eq!((Record::C_NAME, Record::VARIABLE_LEN), ("Record", true));
+        let definition = Record::c_definition();
+        assert!(definition.contains("    RecordType_Entries = 16,\n"));
+        assert!(definition.contains("    UInt8 type; /* RecordType */\n"));
+        assert!(definition.contains("            Entry entries[];\n"));
+        assert!(definition.contains("/* followed by: char label[]; (null terminated) */"));
+        assert!(definition.contains("        char rest[]; /* rest of the record */\n"));
+    }
+
+    #[test]
+    fn test_c_header_matches() {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("include")
+            .join("heffalump_records.h");
+        let header = c_header();
+        if std::env::var_os("HEFFALUMP_BLESS").is_some() {
+            std::fs::write(&path, &header).unwrap();
+        }
+        let checked_in = std::fs::read_to_string(&path).unwrap_or_default();
+        assert!(
+            checked_in == header,
+            "{} is out of date, rerun with HEFFALUMP_BLESS=1:\n{header}",
+            path.display()
+        );
+    }
+}
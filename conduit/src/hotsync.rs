@@ -0,0 +1,163 @@
+use std::{
+    ffi::{c_long, c_uchar, c_void, CString},
+    path::{Path, PathBuf},
+};
+
+use hotsync_conduit_rs::{CSyncProperties, ConduitBuilder, ConduitDBSource, PreferenceType};
+use log::{error, info};
+use simplelog::*;
+
+use crate::{
+    config, create_dbs, device::DeviceReport, download::get_client,
+    heffalump_hh_types::HeffalumpPrefs, log_err, read_config, schema::negotiate,
+    upload::apply_writes, CONFIG_FILE, CONVERSATIONS_DB_TYPE, DB_NAME_AUTHOR, DB_NAME_CONTENT,
+    DB_NAME_CONVERSATIONS, DB_NAME_NOTIFICATIONS, DB_NAME_POLLS, DB_NAME_STATUS, DB_NAME_WRITES,
+    NOTIFICATIONS_DB_TYPE, POLLS_DB_TYPE, STATUS_DB_TYPE,
+};
+
+const CREATOR: [c_uchar; 4] = [b'H', b'E', b'F', b'f'];
+
+#[no_mangle]
+/// # Safety
+///
+/// these pointers are initialized by HS Manager, and this function should only ever be called by it
+pub unsafe extern "cdecl" fn OpenConduit(
+    _: *const c_void,
+    sync_props: *const CSyncProperties,
+) -> c_long {
+    match std::panic::catch_unwind(|| conduit(sync_props)) {
+        Ok(res) => res,
+        Err(_) => {
+            error!(
+                "Caught Panic: {}",
+                std::backtrace::Backtrace::force_capture()
+            );
+            -1
+        }
+    }
+}
+
+unsafe fn conduit(sync_props: *const CSyncProperties) -> c_long {
+    let Some(path) = (unsafe { path_from_sync_props(sync_props) }) else {
+        return -1;
+    };
+    initialize_logger(&path);
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let config_path = {
+        let mut owned = path.clone().to_owned();
+        owned.push(CONFIG_FILE);
+        owned
+    };
+
+    if let Err(std::io::ErrorKind::NotFound) = std::fs::metadata(&config_path).map_err(|e| e.kind())
+    {
+        if runtime
+            .block_on(config::configure(&config_path))
+            .map_err(log_err)
+            .is_err()
+        {
+            return -1;
+        }
+    }
+
+    let Ok(config) = read_config(&config_path) else {
+        return -1;
+    };
+
+    let device = DeviceReport::read(&path);
+    let Ok(schema) = negotiate(device.schema).map_err(log_err) else {
+        return -1;
+    };
+    let fetch = config.fetch.with_device_limits(device.fetch_limits);
+
+    let client = get_client(config.instance, config.access_token);
+    let Ok(dbs) = runtime.block_on(create_dbs(client.as_ref(), schema, &fetch, Some(&path))) else {
+        return -1;
+    };
+    info!("{:?}", &dbs.prefs);
+
+    let mut conduit = ConduitBuilder::<HeffalumpPrefs>::new_with_name_creator(
+        CString::new("heffalump_conduit").unwrap(),
+        CREATOR,
+    )
+    .download_db_and(
+        CString::new(DB_NAME_WRITES).unwrap(),
+        hotsync_conduit_rs::ConduitDBSink::Dynamic(Box::new(move |from_hh| {
+            runtime.block_on(apply_writes(client.as_ref(), &path, from_hh))
+        })),
+    )
+    .overwrite_db(ConduitDBSource::Static(
+        CString::new(DB_NAME_AUTHOR).unwrap(),
+        [b'A', b'u', b't', b'h'],
+        dbs.author,
+    ))
+    .overwrite_db(ConduitDBSource::Static(
+        CString::new(DB_NAME_CONTENT).unwrap(),
+        [b'T', b'o', b'o', b't'],
+        dbs.content,
+    ));
+    if let Some(notifications_db) = dbs.notifications {
+        conduit = conduit.overwrite_db(ConduitDBSource::Static(
+            CString::new(DB_NAME_NOTIFICATIONS).unwrap(),
+            NOTIFICATIONS_DB_TYPE,
+            notifications_db,
+        ));
+    }
+    if let Some(conversations_db) = dbs.conversations {
+        conduit = conduit.overwrite_db(ConduitDBSource::Static(
+            CString::new(DB_NAME_CONVERSATIONS).unwrap(),
+            CONVERSATIONS_DB_TYPE,
+            conversations_db,
+        ));
+    }
+    if let Some(polls_db) = dbs.polls {
+        conduit = conduit.overwrite_db(ConduitDBSource::Static(
+            CString::new(DB_NAME_POLLS).unwrap(),
+            POLLS_DB_TYPE,
+            polls_db,
+        ));
+    }
+    if let Some(status_db) = dbs.status {
+        conduit = conduit.overwrite_db(ConduitDBSource::Static(
+            CString::new(DB_NAME_STATUS).unwrap(),
+            STATUS_DB_TYPE,
+            status_db,
+        ));
+    }
+    let conduit = conduit
+        .set_preferences(PreferenceType::Static(0, dbs.prefs))
+        .build();
+
+    match conduit.sync() {
+        Ok(_) => 0,
+        Err(_) => -1,
+    }
+}
+
+unsafe fn path_from_sync_props(props: *const CSyncProperties) -> Option<PathBuf> {
+    // SAFETY this is initialized by HS Manager
+    // this is the only way to retrieve the path for the application's
+    // HotSync-created directory
+    match unsafe { props.as_ref() } {
+        Some(sync_props) => sync_props.get_dir_path(),
+        None => None,
+    }
+}
+
+fn initialize_logger(at: &Path) {
+    let mut log_path = at.to_owned();
+    log_path.push("heffalump.log");
+    CombinedLogger::init(vec![WriteLogger::new(
+        LevelFilter::Info,
+        Config::default(),
+        std::fs::File::create(log_path).unwrap(),
+    )])
+    .unwrap();
+
+    info!("Logger Initialized");
+}
@@ -0,0 +1,1046 @@
+//! Decodes the databases shipped to (and received from) the handheld back
+//! into a readable thread structure.
+
+use std::{collections::HashMap, path::Path};
+
+use encoding::{all::ISO_8859_1, DecoderTrap, Encoding};
+use palmrs::database::{PalmDatabase, PdbDatabase};
+use serde::Serialize;
+
+use crate::{
+    headless::{database_records, pdb_path},
+    heffalump_hh_types::{
+        NotificationKind, OnDevice, SyncSource, SyncStatus, TootAccount, TootAuthor, TootContent,
+        TootContentV2, TootContentV3, TootMessage, TootNotification, TootPoll, TootProfile,
+        TootWrite, HIDDEN_VOTES, NO_CONTENT,
+    },
+    pdb::PALM_EPOCH_OFFSET,
+    DB_NAME_AUTHOR, DB_NAME_CONTENT, DB_NAME_CONVERSATIONS, DB_NAME_NOTIFICATIONS, DB_NAME_POLLS,
+    DB_NAME_STATUS, DB_NAME_WRITES,
+};
+
+#[derive(Debug, Default, Serialize)]
+pub struct Dump {
+    pub authors: Vec<String>,
+    /// Top level toots with their replies nested beneath them
+    pub threads: Vec<Toot>,
+    pub notifications: Vec<Notification>,
+    pub conversations: Vec<Conversation>,
+    pub polls: Vec<Poll>,
+    /// How the sync that wrote the databases went
+    pub sync: Option<LastSync>,
+    pub writes: Vec<Action>,
+    /// Records that failed to decode or reference something that doesn't exist
+    pub problems: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Toot {
+    pub index: usize,
+    pub author: String,
+    /// The content warning, `None` unless the toot is marked sensitive
+    pub warning: Option<String>,
+    pub content: String,
+    /// What a timeline toot replies to, oldest first
+    pub ancestors: Vec<Toot>,
+    /// Each reply with the replies to it nested beneath
+    pub replies: Vec<Toot>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Notification {
+    pub kind: String,
+    pub author: String,
+    /// Unix time
+    pub created: i64,
+    /// The synced toot, if there is one
+    pub target: Option<String>,
+    pub excerpt: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Poll {
+    pub index: u16,
+    pub target: Option<String>,
+    /// Unix time, `None` for polls that stay open
+    pub expires: Option<i64>,
+    pub expired: bool,
+    pub multiple: bool,
+    pub voted: bool,
+    pub votes_count: u32,
+    /// Each option's title and votes, `None` while the results are hidden
+    pub options: Vec<(String, Option<u32>)>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LastSync {
+    /// Unix time
+    pub synced: i64,
+    pub failures: Vec<Failure>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Failure {
+    pub source: String,
+    /// Whether the previous sync's copy was shipped instead
+    pub cached: bool,
+    pub error: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Conversation {
+    pub participants: Vec<String>,
+    pub unread: bool,
+    /// Oldest first
+    pub messages: Vec<Toot>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Action {
+    Favorite {
+        index: u16,
+        target: Option<String>,
+    },
+    Follow {
+        index: u16,
+        target: Option<String>,
+    },
+    Reblog {
+        index: u16,
+        target: Option<String>,
+    },
+    Toot {
+        in_reply_to: Option<u16>,
+        target: Option<String>,
+        /// Only sent from a `TootV2` write
+        warning: Option<String>,
+        content: String,
+    },
+    DeviceSchema {
+        version: u16,
+        features: u16,
+    },
+    FetchLimits {
+        home_timeline: u16,
+        self_timeline: u16,
+        replies: u16,
+    },
+    DirectReply {
+        message: u16,
+        target: Option<String>,
+        content: String,
+    },
+    Bookmark {
+        index: u16,
+        target: Option<String>,
+    },
+    Unbookmark {
+        index: u16,
+        target: Option<String>,
+    },
+    FollowAuthor {
+        index: u16,
+        author: Option<String>,
+    },
+    UnfollowAuthor {
+        index: u16,
+        author: Option<String>,
+    },
+    Vote {
+        index: u16,
+        target: Option<String>,
+        /// Positions in the toot's poll
+        choices: Vec<u8>,
+    },
+}
+
+/// Reads whichever of the Heffalump databases exist in `dir`
+pub fn inspect_dir(dir: &Path) -> Result<Dump, Box<dyn std::error::Error>> {
+    let read = |name| -> Result<_, Box<dyn std::error::Error>> {
+        match std::fs::read(pdb_path(dir, name)) {
+            Ok(bytes) => Ok(Some(PalmDatabase::<PdbDatabase>::from_bytes(&bytes)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Box::new(e)),
+        }
+    };
+
+    Ok(inspect(
+        read(DB_NAME_AUTHOR)?.as_ref(),
+        read(DB_NAME_CONTENT)?.as_ref(),
+        read(DB_NAME_NOTIFICATIONS)?.as_ref(),
+        read(DB_NAME_CONVERSATIONS)?.as_ref(),
+        read(DB_NAME_POLLS)?.as_ref(),
+        read(DB_NAME_STATUS)?.as_ref(),
+        read(DB_NAME_WRITES)?.as_ref(),
+    ))
+}
+
+pub(crate) fn inspect(
+    author_db: Option<&PalmDatabase<PdbDatabase>>,
+    content_db: Option<&PalmDatabase<PdbDatabase>>,
+    notifications_db: Option<&PalmDatabase<PdbDatabase>>,
+    conversations_db: Option<&PalmDatabase<PdbDatabase>>,
+    polls_db: Option<&PalmDatabase<PdbDatabase>>,
+    status_db: Option<&PalmDatabase<PdbDatabase>>,
+    writes_db: Option<&PalmDatabase<PdbDatabase>>,
+) -> Dump {
+    let mut dump = Dump::default();
+
+    let records = |db: Option<&PalmDatabase<PdbDatabase>>| {
+        db.map(database_records)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(bytes, _, _)| bytes)
+            .enumerate()
+    };
+
+    for (index, bytes) in records(author_db) {
+        // TootAccount in schema 7 and TootProfile from 8, none of them
+        // decodes as another
+        let author_name = TootAuthor::from_hh_bytes(&bytes)
+            .map(|author| author.author_name)
+            .or_else(|e| {
+                TootAccount::from_hh_bytes(&bytes)
+                    .or_else(|_| TootProfile::from_hh_bytes(&bytes).map(|p| p.account))
+                    .map(|account| account.author_name)
+                    .map_err(|_| e)
+            });
+        match author_name {
+            Ok(author_name) => dump.authors.push(from_latin_1(&author_name)),
+            Err(e) => {
+                dump.problems.push(format!("author {index}: {e}"));
+                dump.authors.push(format!("<undecodable author {index}>"));
+            }
+        }
+    }
+
+    // a single record can decode in more than one layout, so the whole
+    // database is read in the oldest layout every record fits
+    let content_records = records(content_db).collect::<Vec<_>>();
+    let layouts: [fn(&[u8]) -> std::io::Result<TootContentV3>; 3] =
+        [content_v1, content_v2, TootContentV3::from_hh_bytes];
+    let layout = layouts
+        .iter()
+        .find(|decode| {
+            content_records
+                .iter()
+                .all(|(_, bytes)| decode(bytes).is_ok())
+        })
+        .unwrap_or(&layouts[0]);
+    let mut contents = Vec::new();
+    for (index, bytes) in content_records {
+        match layout(&bytes) {
+            Ok(content) => contents.push(Some(content)),
+            Err(e) => {
+                dump.problems.push(format!("content {index}: {e}"));
+                contents.push(None);
+            }
+        }
+    }
+
+    // is_reply_to is 0 both for top level toots and replies to the first
+    // toot, so the reply section is found from the earliest replies_start
+    let replies_from = contents
+        .iter()
+        .flatten()
+        .map(|c| c.replies_start as usize)
+        .filter(|start| *start != 0)
+        .min()
+        .unwrap_or(contents.len());
+
+    for (index, content) in contents.iter().enumerate().take(replies_from) {
+        if let Some(content) = content {
+            let toot = resolve(&mut dump, index, content);
+            dump.threads.push(toot);
+        }
+    }
+
+    let mut replies = Vec::new();
+    for (index, content) in contents.iter().enumerate().skip(replies_from) {
+        let Some(content) = content else {
+            continue;
+        };
+        let reply = resolve(&mut dump, index, content);
+        let parent = content.is_reply_to as usize;
+        let in_range = contents
+            .get(parent)
+            .and_then(Option::as_ref)
+            .is_some_and(|p| p.replies_start != 0 && p.replies_start as usize <= index);
+        match dump.threads.iter().any(|t| t.index == parent) && in_range {
+            true => replies.push((index, reply, content)),
+            false => dump.problems.push(format!(
+                "content {index}: reply to {parent} which has no replies at this index"
+            )),
+        }
+    }
+
+    // replies nest under the reply they answer, which comes before them, so
+    // the trees are put together from the last record up
+    let thread_of = replies
+        .iter()
+        .map(|(index, _, content)| (*index, content.is_reply_to))
+        .collect::<HashMap<_, _>>();
+    let mut ancestors = HashMap::<usize, Vec<Toot>>::new();
+    let mut nested = HashMap::<usize, Vec<Toot>>::new();
+    let mut top = HashMap::<usize, Vec<Toot>>::new();
+    for (index, mut reply, content) in replies.into_iter().rev() {
+        reply.replies = nested.remove(&index).unwrap_or_default();
+        reply.replies.reverse();
+        let thread = content.is_reply_to as usize;
+        let parent = content.parent as usize;
+        let siblings = if content.ancestor {
+            ancestors.entry(thread)
+        } else if parent < index && thread_of.get(&parent) == Some(&content.is_reply_to) {
+            nested.entry(parent)
+        } else {
+            if content.parent != NO_CONTENT && parent != thread {
+                dump.problems.push(format!(
+                    "content {index}: reply to {parent} which isn't earlier in its thread"
+                ));
+            }
+            top.entry(thread)
+        };
+        siblings.or_default().push(reply);
+    }
+    for thread in dump.threads.iter_mut() {
+        for (toots, from) in [
+            (&mut thread.ancestors, &mut ancestors),
+            (&mut thread.replies, &mut top),
+        ] {
+            *toots = from.remove(&thread.index).unwrap_or_default();
+            toots.reverse();
+        }
+    }
+
+    let target = |index: u16| {
+        contents
+            .get(index as usize)
+            .and_then(Option::as_ref)
+            .map(|c| summary(&from_latin_1(&c.contents)))
+    };
+
+    for (index, bytes) in records(notifications_db) {
+        let notification = match TootNotification::from_hh_bytes(&bytes) {
+            Ok(notification) => notification,
+            Err(e) => {
+                dump.problems.push(format!("notification {index}: {e}"));
+                continue;
+            }
+        };
+        let author = author(
+            &mut dump,
+            &format!("notification {index}"),
+            notification.author,
+        );
+        let target = match notification.content {
+            NO_CONTENT => None,
+            content => {
+                let target = target(content);
+                if target.is_none() {
+                    dump.problems.push(format!(
+                        "notification {index}: content {content} does not exist"
+                    ));
+                }
+                target
+            }
+        };
+        dump.notifications.push(Notification {
+            kind: match notification.kind {
+                NotificationKind::Mention => "mention",
+                NotificationKind::Favourite => "favourite",
+                NotificationKind::Reblog => "reblog",
+                NotificationKind::Follow => "follow",
+                NotificationKind::PollEnded => "poll ended",
+            }
+            .to_string(),
+            author,
+            created: notification.created as i64 - PALM_EPOCH_OFFSET as i64,
+            target,
+            excerpt: from_latin_1(&notification.excerpt),
+        });
+    }
+
+    for (index, bytes) in records(polls_db) {
+        let poll = match TootPoll::from_hh_bytes(&bytes) {
+            Ok(poll) => poll,
+            Err(e) => {
+                dump.problems.push(format!("poll {index}: {e}"));
+                continue;
+            }
+        };
+        let target = target(poll.content);
+        if target.is_none() {
+            dump.problems.push(format!(
+                "poll {index}: content {} does not exist",
+                poll.content
+            ));
+        }
+        dump.polls.push(Poll {
+            index: poll.content,
+            target,
+            expires: match poll.expires {
+                0 => None,
+                expires => Some(expires as i64 - PALM_EPOCH_OFFSET as i64),
+            },
+            expired: poll.expired,
+            multiple: poll.multiple,
+            voted: poll.voted,
+            votes_count: poll.votes_count,
+            options: poll
+                .options
+                .iter()
+                .map(|option| {
+                    let votes = match option.votes_count {
+                        HIDDEN_VOTES => None,
+                        votes => Some(votes),
+                    };
+                    (from_latin_1(&option.title), votes)
+                })
+                .collect(),
+        });
+    }
+
+    for (index, bytes) in records(status_db) {
+        if index > 0 {
+            dump.problems
+                .push(format!("status {index}: only one is read"));
+            continue;
+        }
+        let status = match SyncStatus::from_hh_bytes(&bytes) {
+            Ok(status) => status,
+            Err(e) => {
+                dump.problems.push(format!("status {index}: {e}"));
+                continue;
+            }
+        };
+        dump.sync = Some(LastSync {
+            synced: status.synced as i64 - PALM_EPOCH_OFFSET as i64,
+            failures: status
+                .failures
+                .iter()
+                .map(|failure| {
+                    let source = match failure.source {
+                        SyncSource::Home => "home",
+                        SyncSource::SelfTimeline => "self",
+                        SyncSource::Bookmarks => "bookmarks",
+                        SyncSource::Favourites => "favourites",
+                        SyncSource::List => "list",
+                        SyncSource::Hashtag => "hashtag",
+                        SyncSource::LocalTimeline => "local",
+                        SyncSource::FederatedTimeline => "federated",
+                        SyncSource::Replies => "replies",
+                        SyncSource::Notifications => "notifications",
+                        SyncSource::Conversations => "conversations",
+                        SyncSource::Following => "following",
+                        SyncSource::Record => "record",
+                    };
+                    Failure {
+                        source: match failure.title.is_empty() {
+                            true => source.to_string(),
+                            false => format!("{source} {}", from_latin_1(&failure.title)),
+                        },
+                        cached: failure.cached,
+                        error: from_latin_1(&failure.error),
+                    }
+                })
+                .collect(),
+        });
+    }
+
+    let mut messages = Vec::new();
+    for (index, bytes) in records(conversations_db) {
+        let message = match TootMessage::from_hh_bytes(&bytes) {
+            Ok(message) => message,
+            Err(e) => {
+                dump.problems.push(format!("message {index}: {e}"));
+                messages.push(None);
+                continue;
+            }
+        };
+        let participants = message
+            .participants
+            .iter()
+            .map(|p| author(&mut dump, &format!("message {index}"), *p))
+            .collect::<Vec<_>>();
+        let toot = Toot {
+            index,
+            author: author(&mut dump, &format!("message {index}"), message.author),
+            warning: None,
+            content: from_latin_1(&message.contents),
+            ancestors: Vec::new(),
+            replies: Vec::new(),
+        };
+        messages.push(Some(summary(&toot.content)));
+        // messages of a conversation are consecutive
+        let conversation = message.conversation as usize;
+        if conversation == dump.conversations.len() {
+            dump.conversations.push(Conversation {
+                participants,
+                unread: message.unread,
+                messages: Vec::new(),
+            });
+        }
+        match dump.conversations.len() == conversation + 1 {
+            true => dump.conversations[conversation].messages.push(toot),
+            false => dump.problems.push(format!(
+                "message {index}: conversation {conversation} is out of order"
+            )),
+        }
+    }
+
+    for (index, bytes) in records(writes_db) {
+        let write = match TootWrite::from_hh_bytes(&bytes) {
+            Ok(write) => write,
+            Err(e) => {
+                dump.problems.push(format!("write {index}: {e}"));
+                continue;
+            }
+        };
+        dump.writes.push(match write {
+            TootWrite::Favorite(index) => Action::Favorite {
+                index,
+                target: target(index),
+            },
+            TootWrite::Follow(index) => Action::Follow {
+                index,
+                target: target(index),
+            },
+            TootWrite::Reblog(index) => Action::Reblog {
+                index,
+                target: target(index),
+            },
+            TootWrite::Toot(toot) => {
+                // 1-based so that 0 can mean "not a reply"
+                let in_reply_to = toot.is_reply_to.checked_sub(1);
+                Action::Toot {
+                    in_reply_to,
+                    target: in_reply_to.and_then(target),
+                    warning: None,
+                    content: from_latin_1(&toot.contents),
+                }
+            }
+            TootWrite::TootV2(toot) => {
+                let in_reply_to = toot.is_reply_to.checked_sub(1);
+                Action::Toot {
+                    in_reply_to,
+                    target: in_reply_to.and_then(target),
+                    warning: toot.sensitive.then(|| from_latin_1(&toot.warning)),
+                    content: from_latin_1(&toot.contents),
+                }
+            }
+            TootWrite::DeviceSchema(schema) => Action::DeviceSchema {
+                version: schema.version,
+                features: schema.features,
+            },
+            TootWrite::FetchLimits(limits) => Action::FetchLimits {
+                home_timeline: limits.home_timeline,
+                self_timeline: limits.self_timeline,
+                replies: limits.replies,
+            },
+            TootWrite::DirectReply(reply) => Action::DirectReply {
+                message: reply.message,
+                target: messages.get(reply.message as usize).cloned().flatten(),
+                content: from_latin_1(&reply.contents),
+            },
+            TootWrite::Bookmark(index) => Action::Bookmark {
+                index,
+                target: target(index),
+            },
+            TootWrite::Unbookmark(index) => Action::Unbookmark {
+                index,
+                target: target(index),
+            },
+            TootWrite::FollowAuthor(index) => Action::FollowAuthor {
+                index,
+                author: dump.authors.get(index as usize).cloned(),
+            },
+            TootWrite::UnfollowAuthor(index) => Action::UnfollowAuthor {
+                index,
+                author: dump.authors.get(index as usize).cloned(),
+            },
+            TootWrite::Vote(vote) => Action::Vote {
+                index: vote.content,
+                target: target(vote.content),
+                choices: vote.choices,
+            },
+        });
+    }
+
+    dump
+}
+
+/// A content record from before schema 9
+fn content_v1(bytes: &[u8]) -> std::io::Result<TootContentV3> {
+    let content = TootContent::from_hh_bytes(bytes)?;
+    Ok(TootContentV3 {
+        author: content.author,
+        is_reply_to: content.is_reply_to,
+        replies_start: content.replies_start,
+        parent: NO_CONTENT,
+        depth: 0,
+        ancestor: false,
+        sensitive: false,
+        warning: Vec::new(),
+        contents: content.contents,
+    })
+}
+
+/// A content record from schemas 9 and 10
+fn content_v2(bytes: &[u8]) -> std::io::Result<TootContentV3> {
+    let content = TootContentV2::from_hh_bytes(bytes)?;
+    Ok(TootContentV3 {
+        author: content.author,
+        is_reply_to: content.is_reply_to,
+        replies_start: content.replies_start,
+        parent: NO_CONTENT,
+        depth: 0,
+        ancestor: false,
+        sensitive: content.sensitive,
+        warning: content.warning,
+        contents: content.contents,
+    })
+}
+
+fn resolve(dump: &mut Dump, index: usize, content: &TootContentV3) -> Toot {
+    Toot {
+        index,
+        author: author(dump, &format!("content {index}"), content.author),
+        warning: content.sensitive.then(|| from_latin_1(&content.warning)),
+        content: from_latin_1(&content.contents),
+        ancestors: Vec::new(),
+        replies: Vec::new(),
+    }
+}
+
+/// The author at `index`, noting a problem with `record` if there's none
+fn author(dump: &mut Dump, record: &str, index: u16) -> String {
+    match dump.authors.get(index as usize) {
+        Some(author) => author.clone(),
+        None => {
+            dump.problems
+                .push(format!("{record}: author {index} does not exist"));
+            format!("<missing author {index}>")
+        }
+    }
+}
+
+impl std::fmt::Display for Dump {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "authors ({}):", self.authors.len())?;
+        for (index, author) in self.authors.iter().enumerate() {
+            writeln!(f, "  [{index}] {author}")?;
+        }
+
+        writeln!(f, "\nthreads ({}):", self.threads.len())?;
+        for thread in self.threads.iter() {
+            for ancestor in thread.ancestors.iter() {
+                write_toot(f, ancestor, 1, "^ ")?;
+            }
+            write_toot(f, thread, 1, "")?;
+            write_replies(f, &thread.replies, 3)?;
+        }
+
+        if !self.notifications.is_empty() {
+            writeln!(f, "\nnotifications ({}):", self.notifications.len())?;
+            for notification in self.notifications.iter() {
+                let toot = match (&notification.target, notification.excerpt.is_empty()) {
+                    (Some(target), _) => format!(": {target}"),
+                    (None, false) => format!(": {}", summary(&notification.excerpt)),
+                    (None, true) => String::new(),
+                };
+                writeln!(
+                    f,
+                    "  {} from {}{toot}",
+                    notification.kind, notification.author
+                )?;
+            }
+        }
+
+        if !self.conversations.is_empty() {
+            writeln!(f, "\nconversations ({}):", self.conversations.len())?;
+            for conversation in self.conversations.iter() {
+                let unread = match conversation.unread {
+                    true => " (unread)",
+                    false => "",
+                };
+                writeln!(f, "  with {}{unread}", conversation.participants.join(", "))?;
+                for message in conversation.messages.iter() {
+                    write_toot(f, message, 2, "")?;
+                }
+            }
+        }
+
+        if !self.polls.is_empty() {
+            writeln!(f, "\npolls ({}):", self.polls.len())?;
+            for poll in self.polls.iter() {
+                let state = match (poll.expired, poll.voted) {
+                    (true, _) => " (ended)",
+                    (false, true) => " (voted)",
+                    (false, false) => "",
+                };
+                writeln!(
+                    f,
+                    "  on #{}: {}{state}",
+                    poll.index,
+                    or_missing(&poll.target)
+                )?;
+                for (title, votes) in poll.options.iter() {
+                    match votes {
+                        Some(votes) => writeln!(f, "    {title}: {votes}")?,
+                        None => writeln!(f, "    {title}")?,
+                    }
+                }
+            }
+        }
+
+        if let Some(sync) = &self.sync {
+            writeln!(f, "\nfailed sources ({}):", sync.failures.len())?;
+            for failure in sync.failures.iter() {
+                let shipped = match failure.cached {
+                    true => "previous copy",
+                    false => "empty",
+                };
+                writeln!(f, "  {} ({shipped}): {}", failure.source, failure.error)?;
+            }
+        }
+
+        writeln!(f, "\nwrites ({}):", self.writes.len())?;
+        for write in self.writes.iter() {
+            match write {
+                Action::Favorite { index, target } => {
+                    writeln!(f, "  favorite #{index}: {}", or_missing(target))?
+                }
+                Action::Follow { index, target } => {
+                    writeln!(f, "  follow #{index}: {}", or_missing(target))?
+                }
+                Action::Reblog { index, target } => {
+                    writeln!(f, "  reblog #{index}: {}", or_missing(target))?
+                }
+                Action::Toot {
+                    in_reply_to,
+                    target,
+                    warning,
+                    content,
+                } => {
+                    writeln!(f, "  toot: {}", summary(content))?;
+                    if let Some(warning) = warning {
+                        writeln!(f, "    CW: {}", summary(warning))?;
+                    }
+                    if let Some(index) = in_reply_to {
+                        writeln!(f, "    in reply to #{index}: {}", or_missing(target))?;
+                    }
+                }
+                Action::DeviceSchema { version, features } => {
+                    writeln!(f, "  device schema {version}, features {features:#06x}")?
+                }
+                Action::FetchLimits {
+                    home_timeline,
+                    self_timeline,
+                    replies,
+                } => writeln!(
+                    f,
+                    "  fetch limits: home {home_timeline}, self {self_timeline}, replies {replies}"
+                )?,
+                Action::DirectReply {
+                    message,
+                    target,
+                    content,
+                } => {
+                    writeln!(f, "  direct reply: {}", summary(content))?;
+                    writeln!(f, "    to message #{message}: {}", or_missing(target))?;
+                }
+                Action::Bookmark { index, target } => {
+                    writeln!(f, "  bookmark #{index}: {}", or_missing(target))?
+                }
+                Action::Unbookmark { index, target } => {
+                    writeln!(f, "  unbookmark #{index}: {}", or_missing(target))?
+                }
+                Action::FollowAuthor { index, author } => writeln!(
+                    f,
+                    "  follow author [{index}]: {}",
+                    author.as_deref().unwrap_or("<missing author>")
+                )?,
+                Action::UnfollowAuthor { index, author } => writeln!(
+                    f,
+                    "  unfollow author [{index}]: {}",
+                    author.as_deref().unwrap_or("<missing author>")
+                )?,
+                Action::Vote {
+                    index,
+                    target,
+                    choices,
+                } => {
+                    writeln!(f, "  vote #{index}: {}", or_missing(target))?;
+                    let poll = self.polls.iter().find(|p| p.index == *index);
+                    for choice in choices {
+                        let title = poll
+                            .and_then(|p| p.options.get(*choice as usize))
+                            .map_or("<missing option>", |(title, _)| title.as_str());
+                        writeln!(f, "    [{choice}] {title}")?;
+                    }
+                }
+            }
+        }
+
+        if !self.problems.is_empty() {
+            writeln!(f, "\nproblems ({}):", self.problems.len())?;
+            for problem in self.problems.iter() {
+                writeln!(f, "  {problem}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes `toot`, `marker` goes before its index
+fn write_toot(
+    f: &mut std::fmt::Formatter<'_>,
+    toot: &Toot,
+    indent: usize,
+    marker: &str,
+) -> std::fmt::Result {
+    let pad = "  ".repeat(indent);
+    writeln!(f, "{pad}{marker}#{} {}", toot.index, toot.author)?;
+    if let Some(warning) = &toot.warning {
+        writeln!(f, "{pad}  CW: {warning}")?;
+    }
+    for line in toot.content.lines() {
+        writeln!(f, "{pad}  {line}")?;
+    }
+    Ok(())
+}
+
+/// Writes `replies` and the replies to them, a level further in each time
+fn write_replies(
+    f: &mut std::fmt::Formatter<'_>,
+    replies: &[Toot],
+    indent: usize,
+) -> std::fmt::Result {
+    for reply in replies {
+        write_toot(f, reply, indent, "")?;
+        write_replies(f, &reply.replies, indent + 1)?;
+    }
+    Ok(())
+}
+
+fn from_latin_1(bytes: &[u8]) -> String {
+    let trimmed = bytes.strip_suffix(&[0]).unwrap_or(bytes);
+    ISO_8859_1
+        .decode(trimmed, DecoderTrap::Replace)
+        .expect("Replacing undecodable chars, this shouldn't be reachable")
+}
+
+fn or_missing(target: &Option<String>) -> &str {
+    target.as_deref().unwrap_or("<missing toot>")
+}
+
+/// The first line of `content`, cut to fit on a terminal line
+fn summary(content: &str) -> String {
+    let first_line = content.lines().next().unwrap_or_default();
+    match first_line.char_indices().nth(60) {
+        Some((cut, _)) => format!("{}...", &first_line[..cut]),
+        None => first_line.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use palmrs::database::{PalmDatabase, PdbDatabase};
+
+    use crate::{
+        config::FetchConfig,
+        create_dbs,
+        heffalump_hh_types::SchemaHeader,
+        inspect::inspect,
+        mock::{conversation, direct, notification, poll, reply, status, timeline, MockMastodon},
+        writes_gen::WritesSpec,
+    };
+
+    #[tokio::test]
+    async fn test_inspect_threads() {
+        let mut home = timeline(1000, 3, "alice");
+        home[1].replies_count = 1;
+        let mine = timeline(500, 40, "me");
+        let client = MockMastodon::new("me")
+            .with_home(home.clone())
+            .with_account_statuses(mine.clone())
+            .with_replies("999", vec![reply("2000", "bob", "<p>hey</p>", &home[1])])
+            .with_notifications(vec![
+                notification("11", "favourite", "bob", Some(&mine[0])),
+                notification("10", "follow", "carol", None),
+            ])
+            .with_conversations(vec![conversation(
+                "1",
+                &["bob"],
+                &direct("1005", "bob", "<p>@me psst</p>", &["me"]),
+            )]);
+        let schema = SchemaHeader {
+            version: 4,
+            features: 0,
+        };
+        let dbs = create_dbs(&client, schema, &FetchConfig::default(), None)
+            .await
+            .unwrap();
+
+        let dump = inspect(
+            Some(&dbs.author),
+            Some(&dbs.content),
+            dbs.notifications.as_ref(),
+            dbs.conversations.as_ref(),
+            None,
+            None,
+            None,
+        );
+        assert!(dump.problems.is_empty(), "{:?}", dump.problems);
+        assert_eq!(dump.authors, vec!["@alice", "@bob", "@carol", "@me"]);
+        assert_eq!(dump.threads.len(), 43);
+        assert_eq!(dump.threads[1].author, "@alice");
+        assert_eq!(dump.threads[1].replies.len(), 1);
+        assert_eq!(dump.threads[1].replies[0].index, 43);
+        assert_eq!(dump.threads[1].replies[0].author, "@bob");
+        assert_eq!(dump.threads[1].replies[0].content, "hey\n");
+
+        assert_eq!(dump.notifications.len(), 2);
+        assert_eq!(dump.notifications[0].kind, "favourite");
+        assert_eq!(dump.notifications[0].author, "@bob");
+        assert_eq!(dump.notifications[0].target.as_deref(), Some("post 500"));
+        assert_eq!(dump.notifications[0].created, 1_704_153_600);
+        assert_eq!(dump.notifications[1].target, None);
+        assert!(dump.to_string().contains(
+            "notifications (2):\n  favourite from @bob: post 500\n  follow from @carol\n"
+        ));
+
+        assert_eq!(dump.conversations.len(), 1);
+        assert_eq!(dump.conversations[0].participants, vec!["@bob"]);
+        assert_eq!(dump.conversations[0].messages[0].author, "@bob");
+        assert_eq!(dump.conversations[0].messages[0].content, "@me psst\n");
+    }
+
+    #[tokio::test]
+    async fn test_inspect_content_warnings() {
+        let mut home = timeline(1000, 2, "alice");
+        home[0].spoiler_text = String::from("ending");
+        let client = MockMastodon::new("me").with_home(home);
+        for (version, warning) in [(9, Some("ending")), (8, None)] {
+            let schema = SchemaHeader {
+                version,
+                features: 0,
+            };
+            let dbs = create_dbs(&client, schema, &FetchConfig::default(), None)
+                .await
+                .unwrap();
+            let dump = inspect(
+                Some(&dbs.author),
+                Some(&dbs.content),
+                None,
+                None,
+                None,
+                None,
+                None,
+            );
+            assert!(dump.problems.is_empty(), "{:?}", dump.problems);
+            assert_eq!(dump.threads[0].warning.as_deref(), warning);
+            assert_eq!(dump.threads[1].warning, None);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_inspect_reply_trees() {
+        let start = status("50", "carol", "<p>start</p>");
+        let mut mine = reply("999", "me", "<p>post 999</p>", &start);
+        mine.replies_count = 2;
+        let first = reply("2000", "bob", "<p>first</p>", &mine);
+        let client = MockMastodon::new("me")
+            .with_home(timeline(1000, 1, "alice"))
+            .with_account_statuses(vec![mine.clone()])
+            .with_ancestors("999", vec![start])
+            .with_replies(
+                "999",
+                vec![
+                    first.clone(),
+                    reply("2001", "carol", "<p>nested</p>", &first),
+                    reply("2002", "bob", "<p>second</p>", &mine),
+                ],
+            );
+        let schema = SchemaHeader {
+            version: 11,
+            features: 0,
+        };
+        let dbs = create_dbs(&client, schema, &FetchConfig::default(), None)
+            .await
+            .unwrap();
+
+        let dump = inspect(
+            Some(&dbs.author),
+            Some(&dbs.content),
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        assert!(dump.problems.is_empty(), "{:?}", dump.problems);
+        assert_eq!(dump.threads.len(), 2);
+        let thread = &dump.threads[1];
+        assert_eq!(thread.ancestors.len(), 1);
+        assert_eq!(thread.ancestors[0].index, 2);
+        assert_eq!(thread.ancestors[0].content, "start\n");
+        assert_eq!(thread.replies.len(), 2);
+        assert_eq!(thread.replies[0].index, 3);
+        assert_eq!(thread.replies[0].replies.len(), 1);
+        assert_eq!(thread.replies[0].replies[0].index, 4);
+        assert_eq!(thread.replies[0].replies[0].author, "@carol");
+        assert_eq!(thread.replies[1].index, 5);
+        assert!(thread.replies[1].replies.is_empty());
+        assert!(dump.to_string().contains(
+            "  ^ #2 @carol\n    start\n  #1 @me\n    post 999\n      \
+             #3 @bob\n        first\n        #4 @carol\n          nested\n      \
+             #5 @bob\n"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_inspect_polls() {
+        let mut home = timeline(1000, 2, "alice");
+        home[1].poll = Some(poll("7", &[("tea", Some(2)), ("coffee", None)]));
+        let client = MockMastodon::new("me").with_home(home);
+        let schema = SchemaHeader {
+            version: 10,
+            features: 0,
+        };
+        let dbs = create_dbs(&client, schema, &FetchConfig::default(), None)
+            .await
+            .unwrap();
+        let spec: WritesSpec =
+            serde_json::from_str(r#"{ "writes": [{ "vote": { "toot": 1, "choices": [1] } }] }"#)
+                .unwrap();
+        let writes = PalmDatabase::<PdbDatabase>::from_bytes(&spec.to_pdb().unwrap()).unwrap();
+
+        let dump = inspect(
+            Some(&dbs.author),
+            Some(&dbs.content),
+            None,
+            None,
+            dbs.polls.as_ref(),
+            None,
+            Some(&writes),
+        );
+        assert!(dump.problems.is_empty(), "{:?}", dump.problems);
+        assert_eq!(dump.polls.len(), 1);
+        assert_eq!(dump.polls[0].index, 1);
+        assert_eq!(dump.polls[0].target.as_deref(), Some("post 999"));
+        assert_eq!(dump.polls[0].expires, Some(1_704_240_000));
+        assert_eq!(
+            dump.polls[0].options,
+            vec![
+                (String::from("tea"), Some(2)),
+                (String::from("coffee"), None)
+            ]
+        );
+        assert!(dump.to_string().contains(
+            "polls (1):\n  on #1: post 999\n    tea: 2\n    coffee\n\n\
+             writes (1):\n  vote #1: post 999\n    [1] coffee\n"
+        ));
+    }
+}
@@ -1,174 +1,116 @@
 use std::{
-    collections::BTreeMap,
-    ffi::{c_long, c_uchar, c_void, CString},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     fmt::Display,
-    path::{Path, PathBuf},
+    path::Path,
 };
 
-use hotsync_conduit_rs::{CSyncProperties, ConduitBuilder, ConduitDBSource, PreferenceType};
-use log::{error, info, trace};
-use megalodon::Megalodon;
+use log::{error, warn};
 use palmrs::database::{record::pdb_record::RecordAttributes, PalmDatabase, PdbDatabase};
-use simplelog::*;
 
+mod api;
 mod config;
+mod device;
 mod download;
+#[cfg(any(test, fuzzing))]
+#[doc(hidden)]
+pub mod fuzz;
+pub mod headless;
 mod heffalump_hh_types;
+#[cfg(windows)]
+mod hotsync;
+pub mod inspect;
+#[cfg(test)]
+mod mock;
+mod paginator;
+mod pdb;
+pub mod platform;
+mod rate_limit;
+mod schema;
+mod sync_state;
 mod upload;
+pub mod writes_gen;
 
-use download::{feed, get_client, replies, self_posts};
-use heffalump_hh_types::{HeffalumpPrefs, OnDevice, TootAuthor, TootContent};
-use tokio::try_join;
-use upload::*;
+use api::MastodonApi;
+use config::{parse_config, Config, FetchConfig};
+use download::{
+    ambiguous_usernames, bookmarks, conversations, favourites, feed, feed_since, following,
+    from_cache, hashtags, lists, notifications, public, replies, self_posts, ParsedAuthor,
+    ParsedTimeline, ParsedToot, ReplyTo, ThreadLimits, BOOKMARKS_TITLE, FAVOURITES_TITLE,
+    FEDERATED_TITLE, LOCAL_TITLE,
+};
+use heffalump_hh_types::{
+    ExtraTimeline, HeffalumpPrefs, OnDevice, PollOption, SchemaHeader, SyncFailure, SyncSource,
+    SyncStatus, TootAccount, TootAuthor, TootContent, TootContentV2, TootContentV3, TootMessage,
+    TootNotification, TootPoll, TootProfile, HIDDEN_VOTES, NO_CONTENT,
+};
+#[cfg(windows)]
+pub use hotsync::OpenConduit;
+use megalodon::entities::StatusVisibility;
+use pdb::{PdbBuilder, PALM_EPOCH_OFFSET};
+use sync_state::{PreviousSync, SyncState};
+use tokio::join;
 
-const CREATOR: [c_uchar; 4] = [b'H', b'E', b'F', b'f'];
 const MASTODON_APP_NAME: &str = "Heffalump 0.3 (PalmOS)";
 const AUTHOR_DB: &[u8] = include_bytes!("../include/HeffalumpAuthorDB.pdb");
 const CONTENT_DB: &[u8] = include_bytes!("../include/HeffalumpContentDB.pdb");
 const MASTODON_CACHE_OLD: &str = "heffalump_mastodon_timeline_old.json";
 const MASTODON_CACHE_NEW: &str = "heffalump_mastodon_timeline.json";
+const CONVERSATIONS_CACHE_OLD: &str = "heffalump_mastodon_conversations_old.json";
+const CONVERSATIONS_CACHE_NEW: &str = "heffalump_mastodon_conversations.json";
+const AUTHORS_CACHE_OLD: &str = "heffalump_mastodon_authors_old.json";
+const AUTHORS_CACHE_NEW: &str = "heffalump_mastodon_authors.json";
 const CONFIG_FILE: &str = "heffalump_config.json";
+const DEVICE_FILE: &str = "heffalump_device.json";
+const SYNC_STATE_FILE: &str = "heffalump_sync_state.json";
 
 const DB_NAME_CONTENT: &str = "HeffalumpContentDB";
 const DB_NAME_AUTHOR: &str = "HeffalumpAuthorDB";
 const DB_NAME_WRITES: &str = "HeffalumpWritesDB";
+const DB_NAME_NOTIFICATIONS: &str = "HeffalumpNotificationsDB";
+const NOTIFICATIONS_DB_TYPE: [u8; 4] = [b'N', b't', b'f', b'y'];
+const DB_NAME_CONVERSATIONS: &str = "HeffalumpConversationsDB";
+const CONVERSATIONS_DB_TYPE: [u8; 4] = [b'C', b'o', b'n', b'v'];
+const DB_NAME_POLLS: &str = "HeffalumpPollsDB";
+const POLLS_DB_TYPE: [u8; 4] = [b'P', b'o', b'l', b'l'];
+const DB_NAME_STATUS: &str = "HeffalumpStatusDB";
+const STATUS_DB_TYPE: [u8; 4] = [b'S', b't', b'a', b't'];
+const CREATOR: [u8; 4] = [b'H', b'E', b'F', b'f'];
+/// dmRecAttrSecret, hidden when the device hides private records
+const ATTR_SECRET: u8 = 0x10;
+/// Bytes of the toot kept with a notification
+const NOTIFICATION_EXCERPT_LEN: usize = 100;
+/// Bytes of an author's bio kept for their profile
+const PROFILE_BIO_LEN: usize = 500;
+/// Content records the u16 indices can point at, `NO_CONTENT` is taken
+const MAX_CONTENT_RECORDS: usize = NO_CONTENT as usize;
 
-#[no_mangle]
-/// # Safety
-///
-/// these pointers are initialized by HS Manager, and this function should only ever be called by it
-pub unsafe extern "cdecl" fn OpenConduit(
-    _: *const c_void,
-    sync_props: *const CSyncProperties,
-) -> c_long {
-    match std::panic::catch_unwind(|| conduit(sync_props)) {
-        Ok(res) => res,
-        Err(_) => {
-            error!(
-                "Caught Panic: {}",
-                std::backtrace::Backtrace::force_capture()
-            );
-            -1
-        }
-    }
+/// Everything a sync ships to the handheld
+pub(crate) struct Databases {
+    pub(crate) author: PalmDatabase<PdbDatabase>,
+    pub(crate) content: PalmDatabase<PdbDatabase>,
+    /// Only built for devices on schema 3 or later
+    pub(crate) notifications: Option<PalmDatabase<PdbDatabase>>,
+    /// Only built for devices on schema 4 or later
+    pub(crate) conversations: Option<PalmDatabase<PdbDatabase>>,
+    /// Only built for devices on schema 10 or later
+    pub(crate) polls: Option<PalmDatabase<PdbDatabase>>,
+    /// Only built for devices on schema 12 or later
+    pub(crate) status: Option<PalmDatabase<PdbDatabase>>,
+    pub(crate) prefs: HeffalumpPrefs,
 }
 
-unsafe fn conduit(sync_props: *const CSyncProperties) -> c_long {
-    let Some(path) = (unsafe { path_from_sync_props(sync_props) }) else {
-        return -1;
-    };
-    initialize_logger(&path);
-
-    let runtime = tokio::runtime::Builder::new_current_thread()
-        .enable_all()
-        .build()
-        .unwrap();
-
-    let config_path = {
-        let mut owned = path.clone().to_owned();
-        owned.push(CONFIG_FILE);
-        owned
-    };
-
-    if let Err(std::io::ErrorKind::NotFound) = std::fs::metadata(&config_path).map_err(|e| e.kind())
-    {
-        if runtime
-            .block_on(config::configure(&config_path))
-            .map_err(log_err)
-            .is_err()
-        {
-            return -1;
-        }
-    }
-
-    let Ok(config) = std::fs::read_to_string(config_path).map_err(log_err) else {
-        return -1;
-    };
-
-    let Ok((mastodon_inst, mastodon_access)) = serde_json::from_str(&config).map_err(log_err)
-    else {
-        return -1;
-    };
-
-    let client = get_client(mastodon_inst, mastodon_access);
-    let Ok((author_db, content_db, prefs)) =
-        runtime.block_on(create_dbs(client.as_ref(), Some(&path)))
-    else {
-        return -1;
-    };
-    info!("{:?}", &prefs);
-
-    let conduit = ConduitBuilder::<HeffalumpPrefs>::new_with_name_creator(
-        CString::new("heffalump_conduit").unwrap(),
-        CREATOR,
-    )
-    .download_db_and(
-        CString::new(DB_NAME_WRITES).unwrap(),
-        hotsync_conduit_rs::ConduitDBSink::Dynamic(Box::new(move |from_hh| {
-            let parsed = match parse_writes(from_hh) {
-                Ok(v) => v,
-                Err(e) => return Err(Box::new(e)),
-            };
-            trace!("parsed writes");
-            let mut path = path.clone();
-            path.push(MASTODON_CACHE_OLD);
-            let source_file = match (std::fs::File::open(&path), parsed.len()) {
-                (Ok(f), _) => f,
-                (Err(_), 0) => {
-                    // We can't find the cache, but don't have anything to write anyway
-                    return Ok(());
-                }
-                (Err(e), _) => {
-                    error!("Failed to open cache: {}", e);
-                    return Err(Box::new(e));
-                }
-            };
-            trace!("found cache");
-            let (prefs, source) = match serde_json::from_reader(&source_file) {
-                Ok(ok) => ok,
-                Err(e) => {
-                    error!("Failed to deserialize cache with error: {}", e);
-                    return Err(Box::new(e));
-                }
-            };
-            trace!("deserialized cache");
-
-            if let Err(e) = runtime.block_on(execute_writes(client.as_ref(), parsed, source, prefs))
-            {
-                error!("Failed to write with result: {}", e);
-                return Err(Box::new(e));
-            }
-            trace!("executed writes");
-            Ok(())
-        })),
-    )
-    .overwrite_db(ConduitDBSource::Static(
-        CString::new(DB_NAME_AUTHOR).unwrap(),
-        [b'A', b'u', b't', b'h'],
-        author_db,
-    ))
-    .overwrite_db(ConduitDBSource::Static(
-        CString::new(DB_NAME_CONTENT).unwrap(),
-        [b'T', b'o', b'o', b't'],
-        content_db,
-    ))
-    .set_preferences(PreferenceType::Static(0, prefs))
-    .build();
-
-    match conduit.sync() {
-        Ok(_) => 0,
-        Err(_) => -1,
-    }
+/// Where a content record sits in its conversation, for `TootContentV3`
+#[derive(Clone, Copy)]
+struct Tree {
+    /// Content index of the toot it replies to
+    parent: Option<usize>,
+    depth: u8,
+    ancestor: bool,
 }
 
-unsafe fn path_from_sync_props(props: *const CSyncProperties) -> Option<PathBuf> {
-    // SAFETY this is initialized by HS Manager
-    // this is the only way to retrieve the path for the application's
-    // HotSync-created directory
-    match unsafe { props.as_ref() } {
-        Some(sync_props) => sync_props.get_dir_path(),
-        None => None,
-    }
+fn read_config(config_path: &Path) -> Result<Config, Box<dyn std::error::Error>> {
+    let config = std::fs::read_to_string(config_path).map_err(log_err)?;
+    parse_config(&config).map_err(log_err)
 }
 
 fn to_latin_1(arg: impl AsRef<str>, cutoff: Option<usize>, add_null: bool) -> Vec<u8> {
@@ -192,17 +134,43 @@ fn to_latin_1(arg: impl AsRef<str>, cutoff: Option<usize>, add_null: bool) -> Ve
     ret
 }
 
-fn initialize_logger(at: &Path) {
-    let mut log_path = at.to_owned();
-    log_path.push("heffalump.log");
-    CombinedLogger::init(vec![WriteLogger::new(
-        LevelFilter::Info,
-        Config::default(),
-        std::fs::File::create(log_path).unwrap(),
-    )])
-    .unwrap();
-
-    info!("Logger Initialized");
+/// A content database index or length, `create_dbs` keeps those within
+/// `MAX_CONTENT_RECORDS`
+fn content_index(index: usize) -> u16 {
+    u16::try_from(index).expect("content records are capped to MAX_CONTENT_RECORDS")
+}
+
+/// How many of `len` records still fit in the `room` left, taking them
+fn fit(len: usize, room: &mut usize) -> usize {
+    let kept = len.min(*room);
+    if kept < len {
+        warn!("Only {kept} of {len} toots fit in the content database, dropping the rest");
+    }
+    *room -= kept;
+    kept
+}
+
+/// `items` cut to the `max` a record's length prefix allows. Cutting some
+/// off is noted in `failures` under the `database` the record is for.
+fn cut_to_fit<T>(
+    mut items: Vec<T>,
+    max: usize,
+    what: &str,
+    database: &str,
+    failures: &mut Vec<SyncFailure>,
+) -> Vec<T> {
+    if items.len() > max {
+        let error = format!("{} {what} cut to {max}", items.len());
+        warn!("A {database} record is too long, {error}");
+        failures.push(SyncFailure {
+            source: SyncSource::Record,
+            cached: false,
+            title: to_latin_1(database, None, false),
+            error: to_latin_1(error, None, false),
+        });
+        items.truncate(max);
+    }
+    items
 }
 
 fn log_err<E: Display>(error: E) -> E {
@@ -210,117 +178,1423 @@ fn log_err<E: Display>(error: E) -> E {
     error
 }
 
+/// What `source` downloaded, or when that failed, whatever `fallback` has
+/// in its place. The failure is noted in `failures` so the rest of the sync
+/// can still ship, with the `title` of the extra timeline that failed.
+fn degrade<T, E: Display>(
+    res: Result<T, E>,
+    source: SyncSource,
+    title: &str,
+    fallback: impl FnOnce() -> Option<T>,
+    failures: &mut Vec<SyncFailure>,
+) -> Option<T> {
+    match res {
+        Ok(value) => Some(value),
+        Err(e) => {
+            let fallback = fallback();
+            error!(
+                "Failed to download {source:?} {title}, shipping {}: {e}",
+                match fallback {
+                    Some(_) => "the previous sync's copy",
+                    None => "it empty",
+                }
+            );
+            failures.push(SyncFailure {
+                source,
+                cached: fallback.is_some(),
+                title: to_latin_1(title, Some(u8::MAX as usize), false),
+                error: to_latin_1(e.to_string(), Some(u8::MAX as usize), false),
+            });
+            fallback
+        }
+    }
+}
+
 async fn create_dbs(
-    client: &(dyn Megalodon + Send + Sync),
+    client: &dyn MastodonApi,
+    schema: SchemaHeader,
+    fetch: &FetchConfig,
     write_to_path: Option<&Path>,
-) -> Result<
-    (
-        PalmDatabase<PdbDatabase>,
-        PalmDatabase<PdbDatabase>,
-        HeffalumpPrefs,
-    ),
-    (),
-> {
+) -> Result<Databases, ()> {
     let mut base_author =
         PalmDatabase::<PdbDatabase>::from_bytes(AUTHOR_DB).map_err(|e| error!("{}", e))?;
     let mut base_content =
         PalmDatabase::<PdbDatabase>::from_bytes(CONTENT_DB).map_err(|e| error!("{}", e))?;
-    let mut prefs = HeffalumpPrefs::default();
+    let mut prefs = HeffalumpPrefs {
+        schema,
+        ..Default::default()
+    };
 
-    let ((feed_contents, mut feed_raw), (self_contents, self_raw)) =
-        try_join!(feed(client, 100), self_posts(client, 40)).map_err(|e| error!("{}", e))?;
-    let replies = replies(client, feed_raw.iter().chain(self_raw.iter()), 10)
+    let previous = write_to_path.and_then(PreviousSync::read);
+    let previous_state = write_to_path.map(SyncState::read).unwrap_or_default();
+    // older device apps don't know these databases
+    let wants_notifications = schema.version >= 3 && fetch.notifications;
+    let wants_conversations = schema.version >= 4 && fetch.conversations;
+    // replies only make sense in a feed next to what they reply to
+    let with_replies = schema.version >= 11 && fetch.replies && fetch.ancestors_limit > 0;
+    let (home, self_posts, interactions, dms, extras) = join!(
+        async {
+            let since_id = previous_state.home_newest_id.as_ref();
+            match (fetch.home_timeline, since_id.zip(previous.as_ref())) {
+                (true, Some((since_id, previous))) => {
+                    let gaps = previous_state.home_gaps.clone();
+                    let home = previous.home.clone();
+                    feed_since(
+                        client,
+                        fetch.home_timeline_limit,
+                        since_id,
+                        gaps,
+                        home,
+                        with_replies,
+                    )
+                    .await
+                    .map(|(contents, raw, gaps)| ((contents, raw), gaps))
+                }
+                (true, None) => feed(client, fetch.home_timeline_limit, with_replies)
+                    .await
+                    .map(|home| (home, Vec::new())),
+                (false, _) => Ok(Default::default()),
+            }
+        },
+        async {
+            match fetch.self_timeline {
+                true => self_posts(client, fetch.self_timeline_limit).await,
+                false => Ok(Default::default()),
+            }
+        },
+        async {
+            match wants_notifications {
+                true => notifications(client, fetch.notifications_limit).await,
+                false => Ok(Vec::new()),
+            }
+        },
+        async {
+            match wants_conversations {
+                true => {
+                    let requests = fetch.replies_requests as usize;
+                    conversations(client, fetch.conversations_limit, requests).await
+                }
+                false => Ok(Default::default()),
+            }
+        },
+        async {
+            // older device apps would show these as part of the self timeline
+            match schema.version >= 5 {
+                true => extra_timelines(client, fetch, with_replies).await,
+                false => Vec::new(),
+            }
+        },
+    );
+
+    // a source that failed is shipped as the last sync left it where the
+    // timeline cache has it, and empty otherwise. Indices into the other
+    // databases change every sync, so the device can't keep its old copy.
+    let mut failures = Vec::new();
+    let ((feed_contents, feed_raw), home_gaps) = degrade(
+        home,
+        SyncSource::Home,
+        "",
+        || {
+            previous
+                .as_ref()
+                .map(|p| (from_cache(p.home.clone()), previous_state.home_gaps.clone()))
+        },
+        &mut failures,
+    )
+    .unwrap_or_default();
+    let (mut self_contents, mut self_raw) = degrade(
+        self_posts,
+        SyncSource::SelfTimeline,
+        "",
+        || previous.as_ref().map(|p| from_cache(p.self_posts.clone())),
+        &mut failures,
+    )
+    .unwrap_or_default();
+    let mut extras = extras
+        .into_iter()
+        .map(|(source, title, timeline)| {
+            // the previous copy is the one the prefs gave the same title
+            let cached = to_latin_1(&title, Some(u8::MAX as usize), false);
+            degrade(
+                timeline,
+                source,
+                &title,
+                || {
+                    let (_, raw) = previous
+                        .as_ref()?
+                        .timelines
+                        .iter()
+                        .find(|(t, _)| *t == cached)?;
+                    let (contents, raw) = from_cache(raw.clone());
+                    Some(ParsedTimeline {
+                        title: title.clone(),
+                        contents,
+                        raw,
+                    })
+                },
+                &mut failures,
+            )
+            .unwrap_or_else(|| ParsedTimeline {
+                title: title.clone(),
+                contents: Vec::new(),
+                raw: Vec::new(),
+            })
+        })
+        .collect::<Vec<_>>();
+    let interactions = wants_notifications.then(|| {
+        degrade(
+            interactions,
+            SyncSource::Notifications,
+            "",
+            || None,
+            &mut failures,
+        )
+        .unwrap_or_default()
+    });
+    let (dms, conversation_lookups) = match wants_conversations {
+        true => {
+            let (dms, lookups) =
+                degrade(dms, SyncSource::Conversations, "", || None, &mut failures)
+                    .unwrap_or_default();
+            (Some(dms), lookups)
+        }
+        false => (None, 0),
+    };
+
+    let state = SyncState {
+        home_newest_id: feed_raw.first().map(|s| s.id.clone()),
+        home_gaps,
+    };
+    // direct messages go to the conversations database instead
+    let (mut feed_contents, mut feed_raw): (Vec<_>, Vec<_>) = match dms {
+        Some(_) => feed_contents
+            .into_iter()
+            .zip(feed_raw)
+            .filter(|(_, status)| !matches!(status.visibility, StatusVisibility::Direct))
+            .unzip(),
+        None => (feed_contents, feed_raw),
+    };
+
+    // the content database's u16 indices only reach so far, the oldest
+    // toots that don't fit are dropped
+    let mut room = MAX_CONTENT_RECORDS;
+    let kept = fit(feed_contents.len(), &mut room);
+    feed_contents.truncate(kept);
+    feed_raw.truncate(kept);
+    let kept = fit(self_contents.len(), &mut room);
+    self_contents.truncate(kept);
+    self_raw.truncate(kept);
+    for timeline in extras.iter_mut() {
+        let kept = fit(timeline.contents.len(), &mut room);
+        timeline.contents.truncate(kept);
+        timeline.raw.truncate(kept);
+    }
+
+    // one empty entry per toot, the content records are zipped with these
+    let unthreaded = || {
+        feed_raw
+            .iter()
+            .chain(self_raw.iter())
+            .chain(extras.iter().flat_map(|t| &t.raw))
+            .map(|_| Default::default())
+            .collect::<Vec<_>>()
+    };
+    // a lookup that fails leaves that toot without its thread
+    let mut replies = match fetch.replies {
+        true => replies(
+            client,
+            feed_raw
+                .iter()
+                .chain(self_raw.iter())
+                .chain(extras.iter().flat_map(|t| &t.raw)),
+            ThreadLimits {
+                // older device apps would show them as replies
+                ancestors: match schema.version >= 11 {
+                    true => fetch.ancestors_limit as usize,
+                    false => 0,
+                },
+                depth: fetch.replies_depth as usize,
+                breadth: fetch.replies_breadth as usize,
+                replies: fetch.replies_limit as usize,
+                // the direct messages' lookups come out of the same budget
+                requests: (fetch.replies_requests as usize).saturating_sub(conversation_lookups),
+            },
+        )
         .await
-        .map_err(|e| error!("{}", e))?;
+        .into_iter()
+        .map(|thread| {
+            degrade(thread, SyncSource::Replies, "", || None, &mut failures).unwrap_or_default()
+        })
+        .collect(),
+        false => unthreaded(),
+    };
+    // threads are kept whole, the positions within them can't be cut short
+    for thread in replies.iter_mut() {
+        if thread.len() > room {
+            warn!(
+                "The thread of {} toots doesn't fit in the content database",
+                thread.len()
+            );
+            *thread = Default::default();
+        }
+        room -= thread.len();
+    }
+
+    prefs.home_timeline_len = content_index(feed_contents.len());
+    prefs.self_timeline_len = content_index(self_contents.len());
+    prefs.reply_content_len = content_index(replies.len());
 
-    prefs.home_timeline_len = feed_contents.len() as u16;
-    prefs.self_timeline_len = self_contents.len() as u16;
-    prefs.reply_content_len = replies.len() as u16;
+    // the extra timelines follow the self timeline
+    let mut extra_start = feed_contents.len() + self_contents.len();
+    for timeline in extras.iter() {
+        prefs.timelines.push(ExtraTimeline {
+            start: content_index(extra_start),
+            timeline_len: content_index(timeline.contents.len()),
+            title: to_latin_1(&timeline.title, Some(u8::MAX as usize), false),
+        });
+        extra_start += timeline.contents.len();
+    }
 
     feed_raw.extend(self_raw);
+    feed_raw.extend(extras.iter().flat_map(|t| t.raw.iter().cloned()));
 
     let authors = self_contents
         .iter()
         .chain(&feed_contents)
-        .chain(replies.iter().flat_map(|t| &t.0))
-        .map(|(author, _)| (author.to_string(), to_latin_1(author, Some(39), true)))
+        .chain(extras.iter().flat_map(|t| &t.contents))
+        .chain(replies.iter().flat_map(|t| t.toots().map(|t| &t.toot)))
+        .map(|toot| &toot.author)
+        .chain(interactions.iter().flatten().map(|n| &n.author))
+        .chain(dms.iter().flatten().flat_map(|c| {
+            c.messages
+                .iter()
+                .map(|toot| &toot.author)
+                .chain(c.participants.values())
+        }))
+        .map(|author| (author.key(), author.clone()))
         .collect::<BTreeMap<_, _>>();
 
-    let mut start = feed_contents.len() + self_contents.len();
-    for ((author, content), replies) in feed_contents
+    let author_idx =
+        |author: &ParsedAuthor| authors.keys().position(|k| k == &author.key()).unwrap();
+
+    let extra_contents = extras
+        .iter()
+        .flat_map(|t| t.contents.iter().cloned())
+        .collect::<Vec<_>>();
+    // older device apps don't know about content warnings or nested replies
+    let content_record = |toot: &ParsedToot,
+                          is_reply_to: usize,
+                          replies_start: usize,
+                          tree: Tree,
+                          failures: &mut Vec<SyncFailure>| {
+        let author = author_idx(&toot.author) as u16;
+        let contents = cut_to_fit(
+            to_latin_1(&toot.content, None, false),
+            u16::MAX as usize,
+            "bytes",
+            "content",
+            failures,
+        );
+        let warning = to_latin_1(&toot.warning, Some(u8::MAX as usize), false);
+        match schema.version {
+            11.. => TootContentV3 {
+                author,
+                is_reply_to: content_index(is_reply_to),
+                replies_start: content_index(replies_start),
+                parent: tree.parent.map_or(NO_CONTENT, content_index),
+                depth: tree.depth,
+                ancestor: tree.ancestor,
+                sensitive: toot.sensitive,
+                warning,
+                contents,
+            }
+            .to_hh_bytes(),
+            9..=10 => TootContentV2 {
+                author,
+                is_reply_to: content_index(is_reply_to),
+                replies_start: content_index(replies_start),
+                sensitive: toot.sensitive,
+                warning,
+                contents,
+            }
+            .to_hh_bytes(),
+            _ => TootContent {
+                author,
+                is_reply_to: content_index(is_reply_to),
+                replies_start: content_index(replies_start),
+                contents,
+            }
+            .to_hh_bytes(),
+        }
+        .expect("content records are cut to fit")
+    };
+
+    // content database indices of the toots with polls
+    let mut polls = Vec::new();
+    let mut start = feed_contents.len() + self_contents.len() + extra_contents.len();
+    let mut reply_index = start;
+    for (index, (toot, thread)) in feed_contents
         .into_iter()
         .chain(self_contents)
-        .zip(replies.iter().map(|t| t.0.len()))
+        .chain(extra_contents)
+        .zip(replies.iter())
+        .enumerate()
     {
-        let (idx, _) = authors
-            .iter()
-            .enumerate()
-            .find(|(_idx, (k, _v))| k == &&author)
-            .to_owned()
-            .unwrap();
-        let content = match replies == 0 {
-            true => TootContent {
-                author: idx as u16,
-                is_reply_to: 0,
-                replies_start: 0,
-                contents: to_latin_1(content, None, false),
-            },
-            false => {
-                let ret = TootContent {
-                    author: idx as u16,
-                    is_reply_to: 0,
-                    replies_start: start as u16,
-                    contents: to_latin_1(content, None, false),
-                };
-                start += replies;
-                ret
-            }
+        let replies_start = match thread.len() == 0 {
+            true => 0,
+            false => start,
         };
-        let content = content.to_hh_bytes().map_err(|e| error!("{}", e))?;
+        // the last ancestor is what the toot replies to
+        let tree = Tree {
+            parent: thread.ancestors.len().checked_sub(1).map(|a| start + a),
+            depth: 0,
+            ancestor: false,
+        };
+        start += thread.len();
+        let content = content_record(&toot, 0, replies_start, tree, &mut failures);
         base_content.insert_record(RecordAttributes::default(), &content);
+        polls.extend(toot.poll.map(|poll| (index, poll)));
     }
 
-    for (index, contents) in replies.iter().map(|t| &t.0).enumerate() {
-        for (author, content) in contents.iter() {
-            let (author_idx, _) = authors
-                .iter()
-                .enumerate()
-                .find(|(_idx, (k, _v))| k == &author)
-                .to_owned()
-                .unwrap();
-            let content = TootContent {
-                author: author_idx as u16,
-                is_reply_to: index as u16,
-                replies_start: 0,
-                contents: to_latin_1(content, None, false),
+    for (index, thread) in replies.iter().enumerate() {
+        let thread_start = reply_index;
+        for (position, toot) in thread.toots().enumerate() {
+            let tree = Tree {
+                parent: match toot.reply_to {
+                    ReplyTo::Toot => Some(index),
+                    ReplyTo::Thread(parent) => Some(thread_start + parent),
+                    ReplyTo::Unsynced => None,
+                },
+                depth: toot.depth,
+                ancestor: position < thread.ancestors.len(),
             };
-            let content = content.to_hh_bytes().map_err(|e| error!("{}", e))?;
+            let content = content_record(&toot.toot, index, 0, tree, &mut failures);
             base_content.insert_record(RecordAttributes::default(), &content);
+            polls.extend(toot.toot.poll.clone().map(|poll| (reply_index, poll)));
+            reply_index += 1;
         }
     }
 
-    feed_raw.extend(replies.into_iter().flat_map(|t| t.1));
+    feed_raw.extend(
+        replies
+            .into_iter()
+            .flat_map(|t| t.ancestors.into_iter().chain(t.replies))
+            .map(|t| t.raw),
+    );
+
+    let notifications_db = match interactions {
+        Some(interactions) => {
+            // feed_raw is in content record order now, the first copy of a
+            // status wins
+            let content_indices = feed_raw
+                .iter()
+                .enumerate()
+                .rev()
+                .map(|(idx, status)| (status.id.as_str(), content_index(idx)))
+                .collect::<HashMap<_, _>>();
+            let mut pdb = PdbBuilder::new(DB_NAME_NOTIFICATIONS, NOTIFICATIONS_DB_TYPE, CREATOR);
+            for notification in interactions {
+                let record = TootNotification {
+                    kind: notification.kind,
+                    author: author_idx(&notification.author) as u16,
+                    content: notification
+                        .status_id
+                        .and_then(|id| content_indices.get(id.as_str()).copied())
+                        .unwrap_or(NO_CONTENT),
+                    created: (notification.created.max(0) as u64 + PALM_EPOCH_OFFSET) as u32,
+                    excerpt: to_latin_1(
+                        &notification.excerpt,
+                        Some(NOTIFICATION_EXCERPT_LEN),
+                        false,
+                    ),
+                };
+                let record = record
+                    .to_hh_bytes()
+                    .expect("notification records are cut to fit");
+                pdb = pdb.with_record(0, record);
+            }
+            let bytes = pdb.to_bytes().map_err(|e| error!("{}", e))?;
+            Some(PalmDatabase::<PdbDatabase>::from_bytes(&bytes).map_err(|e| error!("{}", e))?)
+        }
+        None => None,
+    };
+
+    let conversations_db = match &dms {
+        Some(dms) => {
+            let mut pdb = PdbBuilder::new(DB_NAME_CONVERSATIONS, CONVERSATIONS_DB_TYPE, CREATOR);
+            for (index, conversation) in dms.iter().enumerate() {
+                let participants = cut_to_fit(
+                    conversation
+                        .participants
+                        .values()
+                        .map(|p| author_idx(p) as u16)
+                        .collect(),
+                    u8::MAX as usize,
+                    "participants",
+                    "conversations",
+                    &mut failures,
+                );
+                for message in conversation.messages.iter() {
+                    let record = TootMessage {
+                        conversation: index as u16,
+                        author: author_idx(&message.author) as u16,
+                        participants: participants.clone(),
+                        unread: conversation.unread,
+                        contents: cut_to_fit(
+                            to_latin_1(&message.content, None, false),
+                            u16::MAX as usize,
+                            "bytes",
+                            "conversations",
+                            &mut failures,
+                        ),
+                    };
+                    let record = record
+                        .to_hh_bytes()
+                        .expect("message records are cut to fit");
+                    pdb = pdb.with_record(ATTR_SECRET, record);
+                }
+            }
+            let bytes = pdb.to_bytes().map_err(|e| error!("{}", e))?;
+            Some(PalmDatabase::<PdbDatabase>::from_bytes(&bytes).map_err(|e| error!("{}", e))?)
+        }
+        None => None,
+    };
+
+    let polls_db = match schema.version >= 10 {
+        true => {
+            let mut pdb = PdbBuilder::new(DB_NAME_POLLS, POLLS_DB_TYPE, CREATOR);
+            for (index, poll) in polls {
+                let record = TootPoll {
+                    content: content_index(index),
+                    expires: poll
+                        .expires
+                        .map_or(0, |at| (at.max(0) as u64 + PALM_EPOCH_OFFSET) as u32),
+                    expired: poll.expired,
+                    multiple: poll.multiple,
+                    voted: poll.voted,
+                    votes_count: poll.votes_count,
+                    options: poll
+                        .options
+                        .iter()
+                        .take(u8::MAX as usize)
+                        .map(|(title, votes)| PollOption {
+                            votes_count: votes.unwrap_or(HIDDEN_VOTES),
+                            title: to_latin_1(title, Some(u8::MAX as usize), false),
+                        })
+                        .collect(),
+                };
+                let record = record.to_hh_bytes().expect("poll records are cut to fit");
+                pdb = pdb.with_record(0, record);
+            }
+            let bytes = pdb.to_bytes().map_err(|e| error!("{}", e))?;
+            Some(PalmDatabase::<PdbDatabase>::from_bytes(&bytes).map_err(|e| error!("{}", e))?)
+        }
+        false => None,
+    };
 
-    for author_name in authors.into_values() {
-        let author = TootAuthor { author_name }
-            .to_hh_bytes()
-            .map_err(|e| error!("{}", e))?;
+    // only spell out the instance where two authors would look the same
+    let ambiguous = ambiguous_usernames(authors.values());
+    let followed = match schema.version >= 8 {
+        true => {
+            let ids = authors
+                .values()
+                .map(|author| author.id.as_str())
+                .collect::<BTreeSet<_>>();
+            degrade(
+                following(client, &ids.into_iter().collect::<Vec<_>>()).await,
+                SyncSource::Following,
+                "",
+                || None,
+                &mut failures,
+            )
+            .unwrap_or_default()
+        }
+        false => HashSet::new(),
+    };
+    for author in authors.values() {
+        let author_name = author.shown_as(&ambiguous);
+        let account = TootAccount {
+            author_name: to_latin_1(&author_name, Some(39), false),
+            handle: to_latin_1(format!("@{}", author.handle), Some(254), false),
+            display_name: to_latin_1(&author.display_name, Some(254), false),
+            account_id: to_latin_1(&author.id, Some(254), false),
+        };
+        let author = match schema.version {
+            8.. => TootProfile {
+                account,
+                followers_count: author.followers_count,
+                following_count: author.following_count,
+                bot: author.bot,
+                locked: author.locked,
+                following: followed.contains(&author.id),
+                bio: to_latin_1(&author.bio, Some(PROFILE_BIO_LEN), false),
+            }
+            .to_hh_bytes(),
+            7 => account.to_hh_bytes(),
+            _ => TootAuthor {
+                author_name: to_latin_1(&author_name, Some(39), true),
+            }
+            .to_hh_bytes(),
+        }
+        .expect("author records are cut to fit");
         base_author.insert_record(RecordAttributes::default(), &author);
     }
 
+    let status_db = match schema.version >= 12 {
+        true => {
+            let pdb = PdbBuilder::new(DB_NAME_STATUS, STATUS_DB_TYPE, CREATOR);
+            if failures.len() > u8::MAX as usize {
+                warn!(
+                    "Only reporting the first {} of {} failures",
+                    u8::MAX,
+                    failures.len()
+                );
+                failures.truncate(u8::MAX as usize);
+            }
+            let record = SyncStatus {
+                synced: pdb.timestamp(),
+                failures,
+            };
+            let record = record
+                .to_hh_bytes()
+                .expect("the status record is cut to fit");
+            let bytes = pdb
+                .with_record(0, record)
+                .to_bytes()
+                .map_err(|e| error!("{}", e))?;
+            Some(PalmDatabase::<PdbDatabase>::from_bytes(&bytes).map_err(|e| error!("{}", e))?)
+        }
+        false => None,
+    };
+
     if let Some(path) = write_to_path {
-        let mut owned = path.to_owned();
-        owned.push(MASTODON_CACHE_NEW);
-        if std::fs::metadata(&owned).is_ok() {
-            // a previous version exists, move it to a different path
-            // (overwrites the previous _old file)
-            let mut prev = path.to_owned();
-            prev.push(MASTODON_CACHE_OLD);
-            std::fs::rename(&owned, &prev).map_err(|e| error!("{}", e))?;
+        write_cache(
+            path,
+            MASTODON_CACHE_NEW,
+            MASTODON_CACHE_OLD,
+            &(&prefs, feed_raw),
+        )?;
+        // in conversations database order, replies point into it
+        let messages = dms
+            .iter()
+            .flatten()
+            .flat_map(|c| &c.raw)
+            .collect::<Vec<_>>();
+        write_cache(
+            path,
+            CONVERSATIONS_CACHE_NEW,
+            CONVERSATIONS_CACHE_OLD,
+            &messages,
+        )?;
+        // in author database order, follows point into it
+        let account_ids = authors.values().map(|a| &a.id).collect::<Vec<_>>();
+        write_cache(path, AUTHORS_CACHE_NEW, AUTHORS_CACHE_OLD, &account_ids)?;
+        state.save(path).map_err(|e| error!("{}", e))?;
+    }
+    Ok(Databases {
+        author: base_author,
+        content: base_content,
+        notifications: notifications_db,
+        conversations: conversations_db,
+        polls: polls_db,
+        status: status_db,
+        prefs,
+    })
+}
+
+/// The saved toots, lists, hashtags and public timelines `fetch` asks for,
+/// in the order they follow the self timeline. Each comes with its kind and
+/// title, and fails on its own. Replies are only kept in the feeds
+/// `with_replies`.
+async fn extra_timelines(
+    client: &dyn MastodonApi,
+    fetch: &FetchConfig,
+    with_replies: bool,
+) -> Vec<(SyncSource, String, Result<ParsedTimeline, String>)> {
+    let mut timelines = Vec::new();
+    let mut push = |source, title: &str, res: Result<ParsedTimeline, String>| {
+        timelines.push((source, title.to_string(), res));
+    };
+    if fetch.bookmarks {
+        let res = bookmarks(client, fetch.bookmarks_limit).await;
+        push(
+            SyncSource::Bookmarks,
+            BOOKMARKS_TITLE,
+            res.map_err(|e| e.to_string()),
+        );
+    }
+    if fetch.favourites {
+        let res = favourites(client, fetch.favourites_limit).await;
+        push(
+            SyncSource::Favourites,
+            FAVOURITES_TITLE,
+            res.map_err(|e| e.to_string()),
+        );
+    }
+    if !fetch.lists.is_empty() {
+        match lists(client, &fetch.lists, fetch.lists_limit, with_replies).await {
+            Ok(lists) => {
+                for (title, res) in lists {
+                    push(SyncSource::List, &title, res.map_err(|e| e.to_string()));
+                }
+            }
+            // without the account's lists none of them can be found
+            Err(e) => {
+                for title in fetch.lists.iter() {
+                    push(SyncSource::List, title, Err(e.to_string()));
+                }
+            }
+        }
+    }
+    for (title, res) in hashtags(client, &fetch.hashtags, fetch.hashtags_limit, with_replies).await
+    {
+        push(SyncSource::Hashtag, &title, res.map_err(|e| e.to_string()));
+    }
+    for (wanted, local, limit, source, title) in [
+        (
+            fetch.local_timeline,
+            true,
+            fetch.local_timeline_limit,
+            SyncSource::LocalTimeline,
+            LOCAL_TITLE,
+        ),
+        (
+            fetch.federated_timeline,
+            false,
+            fetch.federated_timeline_limit,
+            SyncSource::FederatedTimeline,
+            FEDERATED_TITLE,
+        ),
+    ] {
+        if wanted {
+            let res = public(client, local, limit, with_replies).await;
+            push(source, title, res.map_err(|e| e.to_string()));
+        }
+    }
+    timelines
+}
+
+/// Writes `value` to `new` in `dir`. The writes the device makes before the
+/// next sync refer to this version, which has moved to `old` by then.
+fn write_cache(dir: &Path, new: &str, old: &str, value: &impl serde::Serialize) -> Result<(), ()> {
+    let mut owned = dir.to_owned();
+    owned.push(new);
+    if std::fs::metadata(&owned).is_ok() {
+        // a previous version exists, move it to a different path
+        // (overwrites the previous _old file)
+        let mut prev = dir.to_owned();
+        prev.push(old);
+        std::fs::rename(&owned, &prev).map_err(|e| error!("{}", e))?;
+    }
+    let file = std::fs::File::create(&owned).map_err(|e| error!("{}", e))?;
+    serde_json::to_writer(&file, value).map_err(|e| error!("{}", e))?;
+    file.sync_all().map_err(|e| error!("{}", e))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        config::FetchConfig,
+        create_dbs,
+        headless::database_records,
+        heffalump_hh_types::{
+            ExtraTimeline, NotificationKind, OnDevice, PollOption, SchemaHeader, SyncSource,
+            SyncStatus, TootAccount, TootAuthor, TootContent, TootContentV3, TootMessage,
+            TootNotification, TootPoll, TootProfile, HIDDEN_VOTES, NO_CONTENT,
+        },
+        mock::{
+            conversation, direct, notification, poll, reply, status, timeline, Call, Endpoint,
+            MockMastodon, TempDir,
+        },
+        pdb::{HEADER_LEN, RECORD_ATTRIBUTES_OFFSET},
+        AUTHORS_CACHE_NEW, CONVERSATIONS_CACHE_NEW,
+    };
+
+    #[tokio::test]
+    async fn test_create_dbs() {
+        let mut home = timeline(1000, 50, "alice");
+        home[0].replies_count = 2;
+        let replies = vec![
+            reply("2000", "carol", "<p>first</p>", &home[0]),
+            reply("2001", "carol", "<p>second</p>", &home[0]),
+        ];
+        let client = MockMastodon::new("me")
+            .with_home(home)
+            .with_account_statuses(timeline(500, 40, "me"))
+            .with_replies("1000", replies);
+
+        let dbs = create_dbs(
+            &client,
+            SchemaHeader::default(),
+            &FetchConfig::default(),
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(dbs.prefs.home_timeline_len, 50);
+        assert_eq!(dbs.prefs.self_timeline_len, 40);
+        assert!(dbs.notifications.is_none());
+        assert!(!client
+            .calls()
+            .iter()
+            .any(|call| matches!(call, Call::Notifications { .. })));
+
+        let authors = database_records(&dbs.author)
+            .into_iter()
+            .map(|(bytes, _, _)| TootAuthor::from_hh_bytes(&bytes).unwrap().author_name)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            authors,
+            vec![
+                b"@alice\0".to_vec(),
+                b"@carol\0".to_vec(),
+                b"@me\0".to_vec()
+            ]
+        );
+
+        let contents = database_records(&dbs.content)
+            .into_iter()
+            .map(|(bytes, _, _)| TootContent::from_hh_bytes(&bytes).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(contents.len(), 92);
+        assert_eq!(contents[0].author, 0);
+        assert_eq!(contents[0].replies_start, 90);
+        assert_eq!(contents[0].contents, b"post 1000\n");
+        assert_eq!(contents[1].replies_start, 0);
+        assert_eq!(contents[50].author, 2);
+        assert_eq!(contents[90].author, 1);
+        assert_eq!(contents[90].is_reply_to, 0);
+        assert_eq!(contents[91].contents, b"second\n");
+    }
+
+    #[tokio::test]
+    async fn test_create_dbs_reply_trees() {
+        let root = status("40", "carol", "<p>root</p>");
+        let start = reply("50", "carol", "<p>start</p>", &root);
+        let mut mine = reply("999", "me", "<p>post 999</p>", &start);
+        mine.replies_count = 2;
+        let first = reply("2000", "bob", "<p>first</p>", &mine);
+        // replies stay in the home timeline, next to what they reply to
+        let mut home = timeline(1000, 1, "alice");
+        home.insert(0, reply("1001", "dave", "<p>reply</p>", &root));
+        let client = MockMastodon::new("me")
+            .with_home(home)
+            .with_account_statuses(vec![mine.clone()])
+            .with_ancestors("1001", vec![root.clone()])
+            .with_ancestors("999", vec![root, start])
+            .with_replies(
+                "999",
+                vec![
+                    first.clone(),
+                    reply("2001", "carol", "<p>nested</p>", &first),
+                    reply("2002", "bob", "<p>second</p>", &mine),
+                ],
+            );
+        let schema = SchemaHeader {
+            version: 11,
+            features: 0,
+        };
+        let dbs = create_dbs(&client, schema, &FetchConfig::default(), None)
+            .await
+            .unwrap();
+
+        let contents = database_records(&dbs.content)
+            .into_iter()
+            .map(|(bytes, _, _)| TootContentV3::from_hh_bytes(&bytes).unwrap())
+            .collect::<Vec<_>>();
+        let tree = contents
+            .iter()
+            .map(|c| (c.is_reply_to, c.parent, c.depth, c.ancestor))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tree,
+            vec![
+                // replies to the newest ancestor
+                (0, 3, 0, false),
+                (0, NO_CONTENT, 0, false),
+                (0, 5, 0, false),
+                (0, NO_CONTENT, 0, true),
+                (2, NO_CONTENT, 0, true),
+                (2, 4, 0, true),
+                (2, 2, 1, false),
+                (2, 6, 2, false),
+                (2, 2, 1, false),
+            ]
+        );
+        assert_eq!(dbs.prefs.home_timeline_len, 2);
+        assert_eq!(contents[0].contents, b"reply\n");
+        assert_eq!(contents[0].replies_start, 3);
+        assert_eq!(contents[2].replies_start, 4);
+        assert_eq!(contents[4].contents, b"root\n");
+        assert_eq!(contents[7].contents, b"nested\n");
+    }
+
+    #[tokio::test]
+    async fn test_create_dbs_incremental() {
+        let dir = TempDir::new("incremental");
+        let fetch = FetchConfig {
+            self_timeline: false,
+            replies: false,
+            ..Default::default()
+        };
+
+        let client = MockMastodon::new("me").with_home(timeline(1000, 50, "alice"));
+        create_dbs(&client, SchemaHeader::default(), &fetch, Some(dir.path()))
+            .await
+            .unwrap();
+
+        let client = MockMastodon::new("me").with_home(timeline(1010, 60, "alice"));
+        let dbs = create_dbs(&client, SchemaHeader::default(), &fetch, Some(dir.path()))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            client.calls()[0],
+            Call::HomeTimeline {
+                limit: Some(100),
+                max_id: None,
+                since_id: Some(String::from("1000")),
+            }
+        );
+        assert_eq!(dbs.prefs.home_timeline_len, 60);
+        let contents = database_records(&dbs.content)
+            .into_iter()
+            .map(|(bytes, _, _)| TootContent::from_hh_bytes(&bytes).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(contents[0].contents, b"post 1010\n");
+        assert_eq!(contents[10].contents, b"post 1000\n");
+    }
+
+    #[tokio::test]
+    async fn test_create_dbs_fetch_config() {
+        let client = MockMastodon::new("me")
+            .with_home(timeline(1000, 50, "alice"))
+            .with_account_statuses(timeline(500, 40, "me"));
+        let fetch = FetchConfig {
+            home_timeline_limit: 20,
+            self_timeline: false,
+            replies: false,
+            ..Default::default()
+        };
+
+        let dbs = create_dbs(&client, SchemaHeader::default(), &fetch, None)
+            .await
+            .unwrap();
+        assert_eq!(dbs.prefs.home_timeline_len, 20);
+        assert_eq!(dbs.prefs.self_timeline_len, 0);
+        assert_eq!(database_records(&dbs.content).len(), 20);
+        assert!(client
+            .calls()
+            .iter()
+            .all(|call| matches!(call, Call::HomeTimeline { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_create_dbs_notifications() {
+        let mine = timeline(500, 3, "me");
+        let mention = status("3000", "dave@elsewhere.example", "<p>@me hello</p>");
+        let client = MockMastodon::new("me")
+            .with_account_statuses(mine.clone())
+            .with_notifications(vec![
+                notification("12", "mention", "dave@elsewhere.example", Some(&mention)),
+                notification("11", "favourite", "bob", Some(&mine[1])),
+                notification("10", "follow", "carol", None),
+            ]);
+        let fetch = FetchConfig {
+            home_timeline: false,
+            replies: false,
+            ..Default::default()
+        };
+        let schema = SchemaHeader {
+            version: 3,
+            features: 0,
+        };
+
+        let dbs = create_dbs(&client, schema, &fetch, None).await.unwrap();
+        let authors = database_records(&dbs.author)
+            .into_iter()
+            .map(|(bytes, _, _)| TootAuthor::from_hh_bytes(&bytes).unwrap().author_name)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            authors,
+            vec![
+                b"@bob\0".to_vec(),
+                b"@carol\0".to_vec(),
+                b"@dave\0".to_vec(),
+                b"@me\0".to_vec()
+            ]
+        );
+
+        let notifications = database_records(&dbs.notifications.unwrap())
+            .into_iter()
+            .map(|(bytes, _, _)| TootNotification::from_hh_bytes(&bytes).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(notifications.len(), 3);
+        assert_eq!(notifications[0].kind, NotificationKind::Mention);
+        assert_eq!(notifications[0].author, 2);
+        // not synced, so only the excerpt is there
+        assert_eq!(notifications[0].content, NO_CONTENT);
+        assert_eq!(notifications[0].excerpt, b"@me hello\n");
+        assert_eq!(notifications[0].created, 3_786_998_400);
+        assert_eq!(notifications[1].kind, NotificationKind::Favourite);
+        assert_eq!(notifications[1].author, 0);
+        assert_eq!(notifications[1].content, 1);
+        assert_eq!(notifications[2].kind, NotificationKind::Follow);
+        assert_eq!(notifications[2].content, NO_CONTENT);
+        assert!(notifications[2].excerpt.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_create_dbs_polls() {
+        let mut home = timeline(1000, 3, "alice");
+        home[0].poll = Some(poll("7", &[("yes", Some(3)), ("no", Some(1))]));
+        home[2].replies_count = 1;
+        let mut asked = reply("2000", "bob", "<p>and you?</p>", &home[2]);
+        asked.poll = Some(poll("8", &[("me", None), ("too", None)]));
+        let client = MockMastodon::new("me")
+            .with_home(home)
+            .with_replies("998", vec![asked]);
+        let fetch = FetchConfig {
+            self_timeline: false,
+            ..Default::default()
+        };
+
+        let schema = SchemaHeader {
+            version: 9,
+            features: 0,
+        };
+        let dbs = create_dbs(&client, schema, &fetch, None).await.unwrap();
+        assert!(dbs.polls.is_none());
+
+        let schema = SchemaHeader {
+            version: 10,
+            features: 0,
+        };
+        let dbs = create_dbs(&client, schema, &fetch, None).await.unwrap();
+        let polls = database_records(&dbs.polls.unwrap())
+            .into_iter()
+            .map(|(bytes, _, _)| TootPoll::from_hh_bytes(&bytes).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            polls[0],
+            TootPoll {
+                content: 0,
+                expires: 3_787_084_800,
+                expired: false,
+                multiple: false,
+                voted: false,
+                votes_count: 4,
+                options: vec![
+                    PollOption {
+                        votes_count: 3,
+                        title: b"yes".to_vec(),
+                    },
+                    PollOption {
+                        votes_count: 1,
+                        title: b"no".to_vec(),
+                    },
+                ],
+            }
+        );
+        // the reply follows the three toots it could be a reply to
+        assert_eq!(polls[1].content, 3);
+        assert_eq!(polls[1].options[0].votes_count, HIDDEN_VOTES);
+        assert_eq!(polls.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_create_dbs_accounts() {
+        let mut home = vec![
+            status("1002", "alice", "<p>here</p>"),
+            status("1001", "alice@hachyderm.io", "<p>there</p>"),
+            status("1000", "bob", ""),
+        ];
+        home[2].reblog = Some(Box::new(status("900", "carol", "<p>boosted</p>")));
+        let client = MockMastodon::new("me").with_home(home);
+        let fetch = FetchConfig {
+            self_timeline: false,
+            replies: false,
+            bookmarks: false,
+            favourites: false,
+            ..Default::default()
+        };
+        let schema = SchemaHeader {
+            version: 7,
+            features: 0,
+        };
+
+        let dbs = create_dbs(&client, schema, &fetch, None).await.unwrap();
+        let accounts = database_records(&dbs.author)
+            .into_iter()
+            .map(|(bytes, _, _)| TootAccount::from_hh_bytes(&bytes).unwrap())
+            .collect::<Vec<_>>();
+        // the two alices are different authors, and only they need the instance
+        let names = accounts
+            .iter()
+            .map(|a| a.author_name.as_slice())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                b"@alice@hachyderm.io".as_slice(),
+                b"@alice@mastodon.example",
+                b"@carol via @bob",
+            ]
+        );
+        assert_eq!(accounts[0].handle, b"@alice@hachyderm.io");
+        assert_eq!(accounts[2].handle, b"@carol@mastodon.example");
+        assert_eq!(accounts[2].display_name, b"carol");
+        assert_eq!(accounts[2].account_id, b"carol");
+
+        let authors = database_records(&dbs.content)
+            .into_iter()
+            .map(|(bytes, _, _)| TootContent::from_hh_bytes(&bytes).unwrap().author)
+            .collect::<Vec<_>>();
+        assert_eq!(authors, vec![1, 0, 2]);
+
+        // older devices get the same names in the legacy record
+        let schema = SchemaHeader {
+            version: 6,
+            features: 0,
+        };
+        let dbs = create_dbs(&client, schema, &fetch, None).await.unwrap();
+        let author = TootAuthor::from_hh_bytes(&database_records(&dbs.author)[0].0).unwrap();
+        assert_eq!(author.author_name, b"@alice@hachyderm.io\0");
+    }
+
+    #[tokio::test]
+    async fn test_create_dbs_profiles() {
+        let dir = TempDir::new("profiles");
+        let mut home = timeline(1000, 2, "alice");
+        for status in home.iter_mut() {
+            status.account.note = String::from("<p>Palm <b>fan</b></p>");
+            status.account.followers_count = 12;
+            status.account.locked = true;
         }
-        let file = std::fs::File::create(&owned).map_err(|e| error!("{}", e))?;
-        serde_json::to_writer(&file, &(&prefs, feed_raw)).map_err(|e| error!("{}", e))?;
-        file.sync_all().map_err(|e| error!("{}", e))?;
+        home.push(status("990", "bot@elsewhere.example", "<p>beep</p>"));
+        home[2].account.bot = Some(true);
+        let client = MockMastodon::new("me")
+            .with_home(home)
+            .with_following(&["alice"]);
+        let fetch = FetchConfig {
+            self_timeline: false,
+            replies: false,
+            notifications: false,
+            conversations: false,
+            bookmarks: false,
+            favourites: false,
+            ..Default::default()
+        };
+        let schema = SchemaHeader {
+            version: 8,
+            features: 0,
+        };
+
+        let dbs = create_dbs(&client, schema, &fetch, Some(dir.path()))
+            .await
+            .unwrap();
+        let cached = std::fs::read_to_string(dir.path().join(AUTHORS_CACHE_NEW)).unwrap();
+        assert_eq!(cached, r#"["alice","bot@elsewhere.example"]"#);
+        // one request for every author
+        assert!(client.calls().contains(&Call::Relationships(vec![
+            String::from("alice"),
+            String::from("bot@elsewhere.example")
+        ])));
+
+        let profiles = database_records(&dbs.author)
+            .into_iter()
+            .map(|(bytes, _, _)| TootProfile::from_hh_bytes(&bytes).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(profiles.len(), 2);
+        assert_eq!(profiles[0].account.author_name, b"@alice");
+        assert_eq!(profiles[0].bio, b"Palm **fan**\n");
+        assert_eq!(profiles[0].followers_count, 12);
+        assert!(profiles[0].locked && profiles[0].following && !profiles[0].bot);
+        assert_eq!(profiles[1].account.handle, b"@bot@elsewhere.example");
+        assert!(profiles[1].bot && !profiles[1].following);
+    }
+
+    #[tokio::test]
+    async fn test_create_dbs_conversations() {
+        let dir = TempDir::new("conversations");
+        let mut home = timeline(1000, 5, "alice");
+        let latest = direct("1005", "bob", "<p>@me see you</p>", &["me"]);
+        home.insert(0, latest.clone());
+        let other_thread = direct("950", "bob", "<p>@me hey</p>", &["me"]);
+        let group = direct(
+            "960",
+            "carol@elsewhere.example",
+            "<p>hi all</p>",
+            &["me", "dave"],
+        );
+        let client = MockMastodon::new("me")
+            .with_home(home)
+            .with_ancestors(
+                "1005",
+                vec![direct("900", "me", "<p>@bob lunch?</p>", &["bob"])],
+            )
+            .with_conversations(vec![
+                conversation("1", &["bob"], &latest),
+                conversation("2", &["carol@elsewhere.example", "dave"], &group),
+                conversation("3", &["bob"], &other_thread),
+            ]);
+        let fetch = FetchConfig {
+            self_timeline: false,
+            replies: false,
+            ..Default::default()
+        };
+        let schema = SchemaHeader {
+            version: 4,
+            features: 0,
+        };
+
+        let dbs = create_dbs(&client, schema, &fetch, Some(dir.path()))
+            .await
+            .unwrap();
+        let cached = std::fs::read_to_string(dir.path().join(CONVERSATIONS_CACHE_NEW)).unwrap();
+
+        // the direct message only shows up in its conversation
+        assert_eq!(dbs.prefs.home_timeline_len, 5);
+        let authors = database_records(&dbs.author)
+            .into_iter()
+            .map(|(bytes, _, _)| TootAuthor::from_hh_bytes(&bytes).unwrap().author_name)
+            .collect::<Vec<_>>();
+        assert_eq!(authors[1], b"@bob\0");
+        assert_eq!(authors[4], b"@me\0");
+
+        let conversations = dbs.conversations.unwrap();
+        let messages = database_records(&conversations)
+            .into_iter()
+            .map(|(bytes, _, _)| TootMessage::from_hh_bytes(&bytes).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(messages.len(), 4);
+        // both threads with bob, merged and oldest first
+        assert_eq!(messages[0].conversation, 0);
+        assert_eq!(messages[0].author, 4);
+        assert_eq!(messages[0].participants, vec![1]);
+        assert_eq!(messages[0].contents, b"@bob lunch?\n");
+        assert_eq!(messages[1].contents, b"@me hey\n");
+        assert_eq!(messages[2].contents, b"@me see you\n");
+        assert_eq!(messages[3].conversation, 1);
+        assert_eq!(messages[3].participants, vec![2, 3]);
+
+        let bytes = conversations.to_bytes().unwrap();
+        assert_eq!(
+            bytes[HEADER_LEN + RECORD_ATTRIBUTES_OFFSET],
+            super::ATTR_SECRET
+        );
+
+        let cached: Vec<megalodon::entities::Status> = serde_json::from_str(&cached).unwrap();
+        let ids = cached.iter().map(|s| s.id.as_str()).collect::<Vec<_>>();
+        assert_eq!(ids, vec!["900", "950", "1005", "960"]);
+    }
+
+    #[tokio::test]
+    async fn test_create_dbs_partial() {
+        let dir = TempDir::new("partial");
+        let fetch = FetchConfig {
+            bookmarks: false,
+            favourites: false,
+            lists: vec![String::from("team")],
+            ..Default::default()
+        };
+        let schema = SchemaHeader {
+            version: 12,
+            features: 0,
+        };
+
+        let client = MockMastodon::new("me")
+            .with_home(timeline(1000, 20, "alice"))
+            .with_list("7", "team", timeline(800, 5, "bob"));
+        create_dbs(&client, schema, &fetch, Some(dir.path()))
+            .await
+            .unwrap();
+
+        // the home and list timelines, replies and notifications fail the
+        // second time
+        let mut own = timeline(500, 3, "me");
+        own[0].replies_count = 1;
+        let replies = vec![reply("2000", "carol", "<p>hi</p>", &own[0])];
+        let client = MockMastodon::new("me")
+            .with_home(timeline(1010, 30, "alice"))
+            .with_account_statuses(own)
+            .with_replies("500", replies)
+            .with_list("7", "team", timeline(810, 5, "bob"))
+            .with_too_many_requests(Endpoint::HomeTimeline, 1)
+            .with_too_many_requests(Endpoint::ListTimeline, 1)
+            .with_too_many_requests(Endpoint::StatusContext, 1)
+            .with_too_many_requests(Endpoint::Notifications, 1);
+        let dbs = create_dbs(&client, schema, &fetch, Some(dir.path()))
+            .await
+            .unwrap();
+
+        // the timelines are the previous sync's, the rest is empty
+        assert_eq!(dbs.prefs.home_timeline_len, 20);
+        assert_eq!(dbs.prefs.self_timeline_len, 3);
+        assert_eq!(dbs.prefs.reply_content_len, 0);
+        assert_eq!(
+            dbs.prefs.timelines,
+            vec![ExtraTimeline {
+                start: 23,
+                timeline_len: 5,
+                title: b"team".to_vec(),
+            }]
+        );
+        let contents = database_records(&dbs.content)
+            .into_iter()
+            .map(|(bytes, _, _)| TootContentV3::from_hh_bytes(&bytes).unwrap().contents)
+            .collect::<Vec<_>>();
+        assert_eq!(contents.len(), 28);
+        assert_eq!(contents[0], b"post 1000\n");
+        assert_eq!(contents[20], b"post 500\n");
+        assert_eq!(contents[23], b"post 800\n");
+        assert!(database_records(&dbs.notifications.unwrap()).is_empty());
+
+        let status = database_records(&dbs.status.unwrap())
+            .into_iter()
+            .map(|(bytes, _, _)| SyncStatus::from_hh_bytes(&bytes).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(status.len(), 1);
+        assert!(status[0].synced > super::PALM_EPOCH_OFFSET as u32);
+        let failures = status[0]
+            .failures
+            .iter()
+            .map(|f| (f.source, f.cached, f.title.as_slice(), f.error.is_empty()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            failures,
+            vec![
+                (SyncSource::Home, true, &b""[..], false),
+                (SyncSource::List, true, &b"team"[..], false),
+                (SyncSource::Notifications, false, &b""[..], false),
+                (SyncSource::Replies, false, &b""[..], false),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_dbs_cuts_long_records() {
+        let long = format!("<p>{}</p>", "a".repeat(70_000));
+        let client = MockMastodon::new("me").with_home(vec![
+            status("1000", "alice", &long),
+            status("999", "bob", "<p>hi</p>"),
+        ]);
+        let fetch = FetchConfig {
+            self_timeline: false,
+            replies: false,
+            ..Default::default()
+        };
+        let schema = SchemaHeader {
+            version: 12,
+            features: 0,
+        };
+        let dbs = create_dbs(&client, schema, &fetch, None).await.unwrap();
+
+        let contents = database_records(&dbs.content)
+            .into_iter()
+            .map(|(bytes, _, _)| TootContentV3::from_hh_bytes(&bytes).unwrap().contents)
+            .collect::<Vec<_>>();
+        assert_eq!(contents.len(), 2);
+        assert_eq!(contents[0].len(), u16::MAX as usize);
+        assert_eq!(contents[1], b"hi\n");
+        let status = database_records(&dbs.status.unwrap())
+            .into_iter()
+            .map(|(bytes, _, _)| SyncStatus::from_hh_bytes(&bytes).unwrap())
+            .collect::<Vec<_>>();
+        let failures = &status[0].failures;
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].source, SyncSource::Record);
+        assert_eq!(failures[0].title, b"content");
+        assert!(failures[0].error.ends_with(b" bytes cut to 65535"));
+    }
+
+    #[tokio::test]
+    async fn test_create_dbs_extra_timelines() {
+        let client = MockMastodon::new("me")
+            .with_home(timeline(1000, 3, "alice"))
+            .with_list("7", "team", timeline(900, 2, "dave"))
+            .with_tag("palm", timeline(700, 1, "frank"))
+            .with_public(vec![
+                status("650", "gina@elsewhere.example", "<p>far</p>"),
+                status("640", "hank", "<p>near</p>"),
+            ])
+            .with_bookmarks(vec![status("600", "ivy", "<p>saved</p>")]);
+        let fetch = FetchConfig {
+            self_timeline: false,
+            replies: false,
+            lists: vec![String::from("team")],
+            hashtags: vec![String::from("#palm")],
+            local_timeline: true,
+            ..Default::default()
+        };
+
+        // a device that can't switch to them doesn't get them
+        let schema = SchemaHeader {
+            version: 4,
+            features: 0,
+        };
+        let dbs = create_dbs(&client, schema, &fetch, None).await.unwrap();
+        assert!(dbs.prefs.timelines.is_empty());
+        assert_eq!(database_records(&dbs.content).len(), 3);
+        assert!(!client.calls().iter().any(|call| {
+            matches!(
+                call,
+                Call::Bookmarks { .. }
+                    | Call::Favourites { .. }
+                    | Call::Lists
+                    | Call::TagTimeline { .. }
+                    | Call::PublicTimeline { .. }
+            )
+        }));
+
+        let schema = SchemaHeader {
+            version: 5,
+            features: 0,
+        };
+        let dbs = create_dbs(&client, schema, &fetch, None).await.unwrap();
+        assert_eq!(dbs.prefs.home_timeline_len, 3);
+        assert_eq!(
+            dbs.prefs.timelines,
+            vec![
+                ExtraTimeline {
+                    start: 3,
+                    timeline_len: 1,
+                    title: b"Bookmarks".to_vec(),
+                },
+                // still there to switch to when empty
+                ExtraTimeline {
+                    start: 4,
+                    timeline_len: 0,
+                    title: b"Favourites".to_vec(),
+                },
+                ExtraTimeline {
+                    start: 4,
+                    timeline_len: 2,
+                    title: b"team".to_vec(),
+                },
+                ExtraTimeline {
+                    start: 6,
+                    timeline_len: 1,
+                    title: b"#palm".to_vec(),
+                },
+                ExtraTimeline {
+                    start: 7,
+                    timeline_len: 1,
+                    title: b"Local".to_vec(),
+                },
+            ]
+        );
+        let contents = database_records(&dbs.content)
+            .into_iter()
+            .map(|(bytes, _, _)| TootContent::from_hh_bytes(&bytes).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(contents.len(), 8);
+        assert_eq!(contents[3].author, 4);
+        assert_eq!(contents[3].contents, b"saved\n");
+        assert_eq!(contents[4].author, 1);
+        assert_eq!(contents[4].contents, b"post 900\n");
+        assert_eq!(contents[6].author, 2);
+        assert_eq!(contents[7].author, 3);
+        assert_eq!(contents[7].contents, b"near\n");
     }
-    Ok((base_author, base_content, prefs))
 }
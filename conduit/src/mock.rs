@@ -0,0 +1,894 @@
+//! An offline stand-in for a Mastodon instance. Timelines are scripted up
+//! front, every request is recorded, and endpoints can be told to answer
+//! with 429s to exercise `rate_limit`.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use megalodon::{
+    entities::{
+        Account, Context, Conversation, List, Notification, Poll, Relationship, Status,
+        StatusVisibility,
+    },
+    error::Error,
+    megalodon::{
+        GetAccountStatusesInputOptions, GetBookmarksInputOptions,
+        GetConversationTimelineInputOptions, GetFavouritesInputOptions,
+        GetListTimelineInputOptions, GetNotificationsInputOptions, GetStatusContextInputOptions,
+        GetTimelineOptionsWithLocal, PostStatusInputOptions, PostStatusOutput,
+    },
+    response::Response,
+};
+
+use crate::{
+    api::MastodonApi,
+    download::id_cmp,
+    rate_limit::{RateLimited, RetryPolicy},
+};
+
+/// A scratch directory for the caches a sync writes, removed again when
+/// dropped, even if the test panics first
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    /// `name` keeps the tests running at the same time apart
+    pub(crate) fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("heffalump_{name}_{}", std::process::id()));
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Mastodon caps `limit` on timelines at 40
+const PAGE_LIMIT: usize = 40;
+/// and on notifications at 80
+const NOTIFICATIONS_PAGE_LIMIT: usize = 80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Endpoint {
+    VerifyCredentials,
+    HomeTimeline,
+    AccountStatuses,
+    StatusContext,
+    Notifications,
+    Conversations,
+    Lists,
+    ListTimeline,
+    TagTimeline,
+    PublicTimeline,
+    Bookmarks,
+    Favourites,
+    Favourite,
+    Reblog,
+    Bookmark,
+    Unbookmark,
+    PostStatus,
+    Relationships,
+    Follow,
+    Unfollow,
+    Vote,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Call {
+    VerifyCredentials,
+    HomeTimeline {
+        limit: Option<u32>,
+        max_id: Option<String>,
+        since_id: Option<String>,
+    },
+    AccountStatuses {
+        limit: Option<u32>,
+        max_id: Option<String>,
+    },
+    StatusContext(String),
+    Notifications {
+        limit: Option<u32>,
+        max_id: Option<String>,
+    },
+    Conversations {
+        limit: Option<u32>,
+    },
+    Lists,
+    ListTimeline {
+        id: String,
+        limit: Option<u32>,
+        max_id: Option<String>,
+    },
+    TagTimeline {
+        tag: String,
+        limit: Option<u32>,
+        max_id: Option<String>,
+    },
+    PublicTimeline {
+        local: Option<bool>,
+        limit: Option<u32>,
+        max_id: Option<String>,
+    },
+    Bookmarks {
+        limit: Option<u32>,
+        max_id: Option<String>,
+    },
+    Favourites {
+        limit: Option<u32>,
+        max_id: Option<String>,
+    },
+    Favourite(String),
+    Reblog(String),
+    Bookmark(String),
+    Unbookmark(String),
+    PostStatus {
+        status: String,
+        in_reply_to_id: Option<String>,
+        direct: bool,
+        spoiler_text: Option<String>,
+    },
+    Relationships(Vec<String>),
+    Follow(String),
+    Unfollow(String),
+    Vote {
+        poll: String,
+        choices: Vec<u32>,
+    },
+}
+
+pub(crate) struct MockMastodon {
+    account: Account,
+    /// newest first, as the API returns them
+    home: Vec<Status>,
+    /// newest first, as the API returns them
+    account_statuses: Vec<Status>,
+    contexts: HashMap<String, Context>,
+    /// newest first, as the API returns them
+    notifications: Vec<Notification>,
+    /// most recently active first, as the API returns them
+    conversations: Vec<Conversation>,
+    lists: Vec<List>,
+    /// by list id, newest first
+    list_timelines: HashMap<String, Vec<Status>>,
+    /// by hashtag, newest first
+    tag_timelines: HashMap<String, Vec<Status>>,
+    /// the federated timeline, newest first. Accounts without a domain are
+    /// local.
+    public: Vec<Status>,
+    /// most recently saved first, as the API returns them
+    bookmarks: Vec<Status>,
+    /// most recently saved first, as the API returns them
+    favourites: Vec<Status>,
+    /// ids of the accounts the user follows
+    following: Vec<String>,
+    too_many_requests: Mutex<HashMap<Endpoint, usize>>,
+    calls: Mutex<Vec<Call>>,
+}
+
+impl MockMastodon {
+    pub(crate) fn new(acct: &str) -> Self {
+        Self {
+            account: account("1", acct),
+            home: Vec::new(),
+            account_statuses: Vec::new(),
+            contexts: HashMap::new(),
+            notifications: Vec::new(),
+            conversations: Vec::new(),
+            lists: Vec::new(),
+            list_timelines: HashMap::new(),
+            tag_timelines: HashMap::new(),
+            public: Vec::new(),
+            bookmarks: Vec::new(),
+            favourites: Vec::new(),
+            following: Vec::new(),
+            too_many_requests: Mutex::new(HashMap::new()),
+            calls: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub(crate) fn with_home(mut self, statuses: Vec<Status>) -> Self {
+        self.home = statuses;
+        self
+    }
+
+    pub(crate) fn with_account_statuses(mut self, statuses: Vec<Status>) -> Self {
+        self.account_statuses = statuses;
+        self
+    }
+
+    pub(crate) fn with_replies(mut self, id: &str, descendants: Vec<Status>) -> Self {
+        self.context(id).descendants = descendants;
+        self
+    }
+
+    /// What `id` replies to, oldest first
+    pub(crate) fn with_ancestors(mut self, id: &str, ancestors: Vec<Status>) -> Self {
+        self.context(id).ancestors = ancestors;
+        self
+    }
+
+    pub(crate) fn with_notifications(mut self, notifications: Vec<Notification>) -> Self {
+        self.notifications = notifications;
+        self
+    }
+
+    pub(crate) fn with_conversations(mut self, conversations: Vec<Conversation>) -> Self {
+        self.conversations = conversations;
+        self
+    }
+
+    pub(crate) fn with_list(mut self, id: &str, title: &str, statuses: Vec<Status>) -> Self {
+        self.lists.push(list(id, title));
+        self.list_timelines.insert(id.to_string(), statuses);
+        self
+    }
+
+    pub(crate) fn with_tag(mut self, tag: &str, statuses: Vec<Status>) -> Self {
+        self.tag_timelines.insert(tag.to_string(), statuses);
+        self
+    }
+
+    pub(crate) fn with_public(mut self, statuses: Vec<Status>) -> Self {
+        self.public = statuses;
+        self
+    }
+
+    pub(crate) fn with_bookmarks(mut self, statuses: Vec<Status>) -> Self {
+        self.bookmarks = statuses;
+        self
+    }
+
+    pub(crate) fn with_favourites(mut self, statuses: Vec<Status>) -> Self {
+        self.favourites = statuses;
+        self
+    }
+
+    pub(crate) fn with_following(mut self, ids: &[&str]) -> Self {
+        self.following = ids.iter().map(|id| id.to_string()).collect();
+        self
+    }
+
+    /// The next `count` requests to `endpoint` fail with a 429
+    pub(crate) fn with_too_many_requests(self, endpoint: Endpoint, count: usize) -> Self {
+        self.too_many_requests
+            .lock()
+            .unwrap()
+            .insert(endpoint, count);
+        self
+    }
+
+    pub(crate) fn calls(&self) -> MutexGuard<'_, Vec<Call>> {
+        self.calls.lock().unwrap()
+    }
+
+    fn context(&mut self, id: &str) -> &mut Context {
+        self.contexts
+            .entry(id.to_string())
+            .or_insert_with(|| Context {
+                ancestors: Vec::new(),
+                descendants: Vec::new(),
+            })
+    }
+
+    fn request(&self, endpoint: Endpoint, call: Call) -> Result<(), Error> {
+        self.calls.lock().unwrap().push(call);
+        match self.too_many_requests.lock().unwrap().get_mut(&endpoint) {
+            Some(remaining) if *remaining > 0 => {
+                *remaining -= 1;
+                Err(too_many_requests())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn find(&self, id: &str) -> Result<&Status, Error> {
+        self.home
+            .iter()
+            .chain(&self.account_statuses)
+            .chain(self.contexts.values().flat_map(|c| &c.descendants))
+            .chain(&self.bookmarks)
+            .chain(&self.favourites)
+            .find(|s| s.id == id)
+            .ok_or_else(|| {
+                Error::StandardError(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("Record not found: {id}"),
+                ))
+            })
+    }
+}
+
+#[async_trait]
+impl MastodonApi for MockMastodon {
+    async fn verify_account_credentials(&self) -> Result<Response<Account>, Error> {
+        self.request(Endpoint::VerifyCredentials, Call::VerifyCredentials)?;
+        Ok(ok(self.account.clone()))
+    }
+
+    async fn get_home_timeline(
+        &self,
+        options: Option<&GetTimelineOptionsWithLocal>,
+    ) -> Result<Response<Vec<Status>>, Error> {
+        let (limit, max_id, since_id) = options
+            .map(|o| (o.limit, o.max_id.clone(), o.since_id.clone()))
+            .unwrap_or_default();
+        self.request(
+            Endpoint::HomeTimeline,
+            Call::HomeTimeline {
+                limit,
+                max_id: max_id.clone(),
+                since_id: since_id.clone(),
+            },
+        )?;
+        Ok(ok(page(
+            &self.home,
+            limit,
+            max_id.as_deref(),
+            since_id.as_deref(),
+        )))
+    }
+
+    async fn get_account_statuses(
+        &self,
+        id: String,
+        options: Option<&GetAccountStatusesInputOptions>,
+    ) -> Result<Response<Vec<Status>>, Error> {
+        let (limit, max_id) = options
+            .map(|o| (o.limit, o.max_id.clone()))
+            .unwrap_or_default();
+        self.request(
+            Endpoint::AccountStatuses,
+            Call::AccountStatuses {
+                limit,
+                max_id: max_id.clone(),
+            },
+        )?;
+        match id == self.account.id {
+            true => Ok(ok(page(
+                &self.account_statuses,
+                limit,
+                max_id.as_deref(),
+                None,
+            ))),
+            false => Ok(ok(Vec::new())),
+        }
+    }
+
+    async fn get_status_context(
+        &self,
+        id: String,
+        _options: Option<&GetStatusContextInputOptions>,
+    ) -> Result<Response<Context>, Error> {
+        self.request(Endpoint::StatusContext, Call::StatusContext(id.clone()))?;
+        Ok(ok(self.contexts.get(&id).cloned().unwrap_or(Context {
+            ancestors: Vec::new(),
+            descendants: Vec::new(),
+        })))
+    }
+
+    async fn get_notifications(
+        &self,
+        options: Option<&GetNotificationsInputOptions>,
+    ) -> Result<Response<Vec<Notification>>, Error> {
+        let (limit, max_id) = options
+            .map(|o| (o.limit, o.max_id.clone()))
+            .unwrap_or_default();
+        self.request(
+            Endpoint::Notifications,
+            Call::Notifications {
+                limit,
+                max_id: max_id.clone(),
+            },
+        )?;
+        let limit = limit
+            .map_or(40, |l| l as usize)
+            .min(NOTIFICATIONS_PAGE_LIMIT);
+        Ok(ok(self
+            .notifications
+            .iter()
+            .filter(|n| {
+                max_id
+                    .as_deref()
+                    .map_or(true, |max| id_cmp(&n.id, max).is_lt())
+            })
+            .take(limit)
+            .cloned()
+            .collect()))
+    }
+
+    async fn get_conversation_timeline(
+        &self,
+        options: Option<&GetConversationTimelineInputOptions>,
+    ) -> Result<Response<Vec<Conversation>>, Error> {
+        let limit = options.and_then(|o| o.limit);
+        self.request(Endpoint::Conversations, Call::Conversations { limit })?;
+        let limit = limit.map_or(20, |l| l as usize).min(PAGE_LIMIT);
+        Ok(ok(self.conversations.iter().take(limit).cloned().collect()))
+    }
+
+    async fn get_lists(&self) -> Result<Response<Vec<List>>, Error> {
+        self.request(Endpoint::Lists, Call::Lists)?;
+        Ok(ok(self.lists.clone()))
+    }
+
+    async fn get_list_timeline(
+        &self,
+        list_id: String,
+        options: Option<&GetListTimelineInputOptions>,
+    ) -> Result<Response<Vec<Status>>, Error> {
+        let (limit, max_id) = options
+            .map(|o| (o.limit, o.max_id.clone()))
+            .unwrap_or_default();
+        self.request(
+            Endpoint::ListTimeline,
+            Call::ListTimeline {
+                id: list_id.clone(),
+                limit,
+                max_id: max_id.clone(),
+            },
+        )?;
+        let statuses = self
+            .list_timelines
+            .get(&list_id)
+            .map_or(&[][..], |s| s.as_slice());
+        Ok(ok(page(statuses, limit, max_id.as_deref(), None)))
+    }
+
+    async fn get_tag_timeline(
+        &self,
+        hashtag: String,
+        options: Option<&GetTimelineOptionsWithLocal>,
+    ) -> Result<Response<Vec<Status>>, Error> {
+        let (limit, max_id) = options
+            .map(|o| (o.limit, o.max_id.clone()))
+            .unwrap_or_default();
+        self.request(
+            Endpoint::TagTimeline,
+            Call::TagTimeline {
+                tag: hashtag.clone(),
+                limit,
+                max_id: max_id.clone(),
+            },
+        )?;
+        let statuses = self
+            .tag_timelines
+            .get(&hashtag)
+            .map_or(&[][..], |s| s.as_slice());
+        Ok(ok(page(statuses, limit, max_id.as_deref(), None)))
+    }
+
+    async fn get_public_timeline(
+        &self,
+        options: Option<&GetTimelineOptionsWithLocal>,
+    ) -> Result<Response<Vec<Status>>, Error> {
+        let (local, limit, max_id) = options
+            .map(|o| (o.local, o.limit, o.max_id.clone()))
+            .unwrap_or_default();
+        self.request(
+            Endpoint::PublicTimeline,
+            Call::PublicTimeline {
+                local,
+                limit,
+                max_id: max_id.clone(),
+            },
+        )?;
+        let statuses = self
+            .public
+            .iter()
+            .filter(|s| local != Some(true) || !s.account.acct.contains('@'))
+            .cloned()
+            .collect::<Vec<_>>();
+        Ok(ok(page(&statuses, limit, max_id.as_deref(), None)))
+    }
+
+    async fn get_bookmarks(
+        &self,
+        options: Option<&GetBookmarksInputOptions>,
+    ) -> Result<Response<Vec<Status>>, Error> {
+        let (limit, max_id) = options
+            .map(|o| (o.limit, o.max_id.clone()))
+            .unwrap_or_default();
+        self.request(
+            Endpoint::Bookmarks,
+            Call::Bookmarks {
+                limit,
+                max_id: max_id.clone(),
+            },
+        )?;
+        Ok(linked_page(
+            "bookmarks",
+            &self.bookmarks,
+            limit,
+            max_id.as_deref(),
+        ))
+    }
+
+    async fn get_favourites(
+        &self,
+        options: Option<&GetFavouritesInputOptions>,
+    ) -> Result<Response<Vec<Status>>, Error> {
+        let (limit, max_id) = options
+            .map(|o| (o.limit, o.max_id.clone()))
+            .unwrap_or_default();
+        self.request(
+            Endpoint::Favourites,
+            Call::Favourites {
+                limit,
+                max_id: max_id.clone(),
+            },
+        )?;
+        Ok(linked_page(
+            "favourites",
+            &self.favourites,
+            limit,
+            max_id.as_deref(),
+        ))
+    }
+
+    async fn favourite_status(&self, id: String) -> Result<Response<Status>, Error> {
+        self.request(Endpoint::Favourite, Call::Favourite(id.clone()))?;
+        Ok(ok(self.find(&id)?.clone()))
+    }
+
+    async fn reblog_status(&self, id: String) -> Result<Response<Status>, Error> {
+        self.request(Endpoint::Reblog, Call::Reblog(id.clone()))?;
+        Ok(ok(self.find(&id)?.clone()))
+    }
+
+    async fn bookmark_status(&self, id: String) -> Result<Response<Status>, Error> {
+        self.request(Endpoint::Bookmark, Call::Bookmark(id.clone()))?;
+        Ok(ok(self.find(&id)?.clone()))
+    }
+
+    async fn unbookmark_status(&self, id: String) -> Result<Response<Status>, Error> {
+        self.request(Endpoint::Unbookmark, Call::Unbookmark(id.clone()))?;
+        Ok(ok(self.find(&id)?.clone()))
+    }
+
+    async fn post_status(
+        &self,
+        status: String,
+        options: Option<&PostStatusInputOptions>,
+    ) -> Result<Response<PostStatusOutput>, Error> {
+        let in_reply_to_id = options.and_then(|o| o.in_reply_to_id.clone());
+        let direct =
+            options.is_some_and(|o| matches!(o.visibility, Some(StatusVisibility::Direct)));
+        let spoiler_text = options.and_then(|o| o.spoiler_text.clone());
+        self.request(
+            Endpoint::PostStatus,
+            Call::PostStatus {
+                status: status.clone(),
+                in_reply_to_id: in_reply_to_id.clone(),
+                direct,
+                spoiler_text: spoiler_text.clone(),
+            },
+        )?;
+        let mut posted = self::status("999999", &self.account.acct, &status);
+        posted.in_reply_to_id = in_reply_to_id;
+        posted.spoiler_text = spoiler_text.unwrap_or_default();
+        Ok(ok(PostStatusOutput::Status(posted)))
+    }
+
+    async fn get_relationships(
+        &self,
+        ids: Vec<String>,
+    ) -> Result<Response<Vec<Relationship>>, Error> {
+        self.request(Endpoint::Relationships, Call::Relationships(ids.clone()))?;
+        Ok(ok(ids
+            .iter()
+            .map(|id| relationship(id, self.following.contains(id)))
+            .collect()))
+    }
+
+    async fn follow_account(&self, id: String) -> Result<Response<Relationship>, Error> {
+        self.request(Endpoint::Follow, Call::Follow(id.clone()))?;
+        Ok(ok(relationship(&id, true)))
+    }
+
+    async fn unfollow_account(&self, id: String) -> Result<Response<Relationship>, Error> {
+        self.request(Endpoint::Unfollow, Call::Unfollow(id.clone()))?;
+        Ok(ok(relationship(&id, false)))
+    }
+
+    async fn vote_poll(&self, id: String, choices: Vec<u32>) -> Result<Response<Poll>, Error> {
+        self.request(
+            Endpoint::Vote,
+            Call::Vote {
+                poll: id.clone(),
+                choices: choices.clone(),
+            },
+        )?;
+        let mut poll = self
+            .home
+            .iter()
+            .chain(&self.account_statuses)
+            .filter_map(|s| s.reblog.as_deref().unwrap_or(s).poll.as_ref())
+            .find(|poll| poll.id == id)
+            .cloned()
+            .ok_or_else(|| {
+                Error::StandardError(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("Record not found: {id}"),
+                ))
+            })?;
+        for choice in choices {
+            if let Some(votes) = poll
+                .options
+                .get_mut(choice as usize)
+                .and_then(|o| o.votes_count.as_mut())
+            {
+                *votes += 1;
+            }
+        }
+        poll.votes_count += 1;
+        poll.voted = Some(true);
+        Ok(ok(poll))
+    }
+}
+
+/// `mock` behind the rate limiter, backing off briefly enough for tests
+pub(crate) fn rate_limited(mock: MockMastodon) -> RateLimited<MockMastodon> {
+    RateLimited::with_policy(
+        mock,
+        RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(10),
+        },
+    )
+}
+
+fn ok<T>(json: T) -> Response<T> {
+    Response::new(json, 200, String::from("OK"), http::HeaderMap::new())
+}
+
+fn too_many_requests() -> Error {
+    let response = http::Response::builder()
+        .status(http::StatusCode::TOO_MANY_REQUESTS)
+        .body("")
+        .unwrap();
+    Error::RequestError(
+        reqwest::Response::from(response)
+            .error_for_status()
+            .unwrap_err(),
+    )
+}
+
+fn page(
+    statuses: &[Status],
+    limit: Option<u32>,
+    max_id: Option<&str>,
+    since_id: Option<&str>,
+) -> Vec<Status> {
+    let limit = limit.map_or(20, |l| l as usize).min(PAGE_LIMIT);
+    statuses
+        .iter()
+        .filter(|s| max_id.map_or(true, |max| id_cmp(&s.id, max).is_lt()))
+        .filter(|s| since_id.map_or(true, |since| id_cmp(&s.id, since).is_gt()))
+        .take(limit)
+        .cloned()
+        .collect()
+}
+
+/// A page of an endpoint that links to the next one. Like Mastodon's own
+/// cursors for these, `max_id` has nothing to do with the status ids, here
+/// it's the position in `statuses`.
+fn linked_page(
+    endpoint: &str,
+    statuses: &[Status],
+    limit: Option<u32>,
+    max_id: Option<&str>,
+) -> Response<Vec<Status>> {
+    let limit = limit.map_or(20, |l| l as usize).min(PAGE_LIMIT);
+    let start = max_id
+        .and_then(|max| max.parse().ok())
+        .unwrap_or(0)
+        .min(statuses.len());
+    let end = (start + limit).min(statuses.len());
+    let mut headers = http::HeaderMap::new();
+    if end < statuses.len() {
+        let link = format!(
+            "<https://mastodon.example/api/v1/{endpoint}?limit={limit}&max_id={end}>; \
+             rel=\"next\", <https://mastodon.example/api/v1/{endpoint}?min_id={start}>; \
+             rel=\"prev\""
+        );
+        headers.insert(http::header::LINK, link.parse().unwrap());
+    }
+    Response::new(
+        statuses[start..end].to_vec(),
+        200,
+        String::from("OK"),
+        headers,
+    )
+}
+
+pub(crate) fn account(id: &str, acct: &str) -> Account {
+    serde_json::from_value(serde_json::json!({
+        "id": id,
+        "username": acct.split('@').next().unwrap(),
+        "acct": acct,
+        "display_name": acct,
+        "locked": false,
+        "discoverable": true,
+        "group": false,
+        "noindex": false,
+        "suspended": false,
+        "limited": false,
+        "created_at": "2023-01-01T00:00:00.000Z",
+        "followers_count": 0,
+        "following_count": 0,
+        "statuses_count": 0,
+        "note": "",
+        "url": format!("https://mastodon.example/@{acct}"),
+        "avatar": "",
+        "avatar_static": "",
+        "header": "",
+        "header_static": "",
+        "emojis": [],
+        "fields": [],
+        "bot": false,
+    }))
+    .unwrap()
+}
+
+pub(crate) fn relationship(id: &str, following: bool) -> Relationship {
+    serde_json::from_value(serde_json::json!({
+        "id": id,
+        "following": following,
+        "followed_by": false,
+        "blocking": false,
+        "blocked_by": false,
+        "muting": false,
+        "muting_notifications": false,
+        "requested": false,
+        "domain_blocking": false,
+        "showing_reblogs": true,
+        "endorsed": false,
+        "notifying": false,
+        "note": null,
+    }))
+    .unwrap()
+}
+
+pub(crate) fn list(id: &str, title: &str) -> List {
+    serde_json::from_value(serde_json::json!({
+        "id": id,
+        "title": title,
+        "replies_policy": "list",
+    }))
+    .unwrap()
+}
+
+/// A public, unthreaded status. `acct` doubles as the account id.
+pub(crate) fn status(id: &str, acct: &str, content: &str) -> Status {
+    serde_json::from_value(serde_json::json!({
+        "id": id,
+        "uri": format!("https://mastodon.example/statuses/{id}"),
+        "url": format!("https://mastodon.example/statuses/{id}"),
+        "account": account(acct, acct),
+        "in_reply_to_id": null,
+        "in_reply_to_account_id": null,
+        "reblog": null,
+        "content": content,
+        "plain_content": null,
+        "created_at": "2024-01-01T00:00:00.000Z",
+        "edited_at": null,
+        "emojis": [],
+        "replies_count": 0,
+        "reblogs_count": 0,
+        "favourites_count": 0,
+        "reblogged": false,
+        "favourited": false,
+        "muted": false,
+        "sensitive": false,
+        "spoiler_text": "",
+        "visibility": "public",
+        "media_attachments": [],
+        "mentions": [],
+        "tags": [],
+        "card": null,
+        "poll": null,
+        "application": null,
+        "language": "en",
+        "pinned": false,
+        "emoji_reactions": [],
+        "quote": false,
+        "bookmarked": false,
+    }))
+    .unwrap()
+}
+
+/// A single choice poll ending 2024-01-03, with each option's title and
+/// votes (`None` while they're hidden)
+pub(crate) fn poll(id: &str, options: &[(&str, Option<u32>)]) -> Poll {
+    serde_json::from_value(serde_json::json!({
+        "id": id,
+        "expires_at": "2024-01-03T00:00:00.000Z",
+        "expired": false,
+        "multiple": false,
+        "votes_count": options.iter().filter_map(|(_, votes)| *votes).sum::<u32>(),
+        "options": options
+            .iter()
+            .map(|(title, votes)| serde_json::json!({ "title": title, "votes_count": votes }))
+            .collect::<Vec<_>>(),
+        "voted": false,
+        "emojis": [],
+    }))
+    .unwrap()
+}
+
+/// A reply from `acct` to `parent`
+pub(crate) fn reply(id: &str, acct: &str, content: &str, parent: &Status) -> Status {
+    let mut reply = status(id, acct, content);
+    reply.in_reply_to_id = Some(parent.id.clone());
+    reply.in_reply_to_account_id = Some(parent.account.id.clone());
+    reply
+}
+
+/// A direct message from `acct`, mentioning each of `to`
+pub(crate) fn direct(id: &str, acct: &str, content: &str, to: &[&str]) -> Status {
+    let mut direct = status(id, acct, content);
+    direct.visibility = StatusVisibility::Direct;
+    direct.mentions = to
+        .iter()
+        .map(|to| {
+            serde_json::from_value(serde_json::json!({
+                "id": to,
+                "username": to.split('@').next().unwrap(),
+                "url": format!("https://mastodon.example/@{to}"),
+                "acct": to,
+            }))
+            .unwrap()
+        })
+        .collect();
+    direct
+}
+
+/// A conversation with `accts` (everyone but the user) that ends in `last_status`
+pub(crate) fn conversation(id: &str, accts: &[&str], last_status: &Status) -> Conversation {
+    serde_json::from_value(serde_json::json!({
+        "id": id,
+        "accounts": accts.iter().map(|acct| account(acct, acct)).collect::<Vec<_>>(),
+        "last_status": last_status,
+        "unread": false,
+    }))
+    .unwrap()
+}
+
+/// `count` statuses by `acct`, newest first, with ids counting down from `newest`
+pub(crate) fn timeline(newest: u32, count: u32, acct: &str) -> Vec<Status> {
+    (0..count)
+        .map(|i| {
+            let id = (newest - i).to_string();
+            status(&id, acct, &format!("<p>post {id}</p>"))
+        })
+        .collect()
+}
+
+/// A notification of `kind` (as the API spells it) from `acct`, about `status`
+pub(crate) fn notification(
+    id: &str,
+    kind: &str,
+    acct: &str,
+    status: Option<&Status>,
+) -> Notification {
+    serde_json::from_value(serde_json::json!({
+        "id": id,
+        "type": kind,
+        "created_at": "2024-01-02T00:00:00.000Z",
+        "account": account(acct, acct),
+        "status": status,
+    }))
+    .unwrap()
+}
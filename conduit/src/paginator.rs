@@ -0,0 +1,222 @@
+//! Pages through the timeline endpoints. Each fetcher in `download` says how
+//! to request a single page, `paginate` moves the cursors, asks again for
+//! whatever the fetcher filtered out and stops when the instance runs out.
+
+use std::future::Future;
+
+use megalodon::{
+    entities::{Notification, Status},
+    error::Error,
+    response::Response,
+};
+
+/// Which way `paginate` walks a timeline, and how it finds the next page
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Direction {
+    /// From the newest, each page older than the previous one's oldest
+    Older,
+    /// From the newest, each page where the `Link` header's next link
+    /// points. Bookmarks and favourites are listed in the order they were
+    /// saved, so the ids can't be cursors.
+    Linked,
+    /// From just before `max_id`, or the newest, each page older than the
+    /// previous one's oldest, down to just after `since_id`
+    Since {
+        since_id: String,
+        max_id: Option<String>,
+    },
+}
+
+/// The cursors for one page request
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Page {
+    pub(crate) limit: u32,
+    pub(crate) max_id: Option<String>,
+    pub(crate) since_id: Option<String>,
+}
+
+/// Something a timeline endpoint lists, by the id its cursors use
+pub(crate) trait Paged {
+    fn page_id(&self) -> &str;
+}
+
+impl Paged for Status {
+    fn page_id(&self) -> &str {
+        &self.id
+    }
+}
+
+impl Paged for Notification {
+    fn page_id(&self) -> &str {
+        &self.id
+    }
+}
+
+/// Up to `count` of the items `fetch` pages through that pass `keep`,
+/// newest first. Each page asks for what's still missing, so the ones `keep`
+/// drops are made up from the next. Stops early on an empty page, a missing
+/// next link or a page that doesn't move the cursor.
+pub(crate) async fn paginate<T, F, Fut>(
+    direction: Direction,
+    count: u32,
+    mut fetch: F,
+    keep: impl Fn(&T) -> bool,
+) -> Result<Vec<T>, Error>
+where
+    T: Paged,
+    F: FnMut(Page) -> Fut,
+    Fut: Future<Output = Result<Response<Vec<T>>, Error>>,
+{
+    let (mut cursor, since_id) = match &direction {
+        Direction::Since { since_id, max_id } => (max_id.clone(), Some(since_id.clone())),
+        Direction::Older | Direction::Linked => (None, None),
+    };
+    let mut res = Vec::new();
+    while res.len() < count as usize {
+        let page = Page {
+            limit: count - res.len() as u32,
+            max_id: cursor.clone(),
+            since_id: since_id.clone(),
+        };
+        let response = fetch(page).await?;
+        let linked = next_max_id(&response.header);
+        let items = response.json();
+
+        let next = match direction {
+            Direction::Older | Direction::Since { .. } => {
+                items.last().map(|item| item.page_id().to_string())
+            }
+            Direction::Linked => linked,
+        };
+        if items.is_empty() || (next.is_some() && next == cursor) {
+            break;
+        }
+        res.extend(items.into_iter().filter(|item| keep(item)));
+        match next {
+            Some(_) => cursor = next,
+            None => break,
+        }
+    }
+
+    res.truncate(count as usize);
+    Ok(res)
+}
+
+/// The `max_id` of the next page in a `Link` header
+fn next_max_id(headers: &http::HeaderMap) -> Option<String> {
+    let link = headers.get(http::header::LINK)?.to_str().ok()?;
+    link.split(',')
+        .find(|link| link.contains("rel=\"next\""))?
+        .split(['?', '&', '>'])
+        .find_map(|param| param.strip_prefix("max_id="))
+        .map(String::from)
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use megalodon::{entities::Status, error::Error, response::Response};
+
+    use crate::{
+        mock::timeline,
+        paginator::{paginate, Direction, Page},
+    };
+
+    fn ok(statuses: Vec<Status>) -> Response<Vec<Status>> {
+        Response::new(statuses, 200, String::from("OK"), http::HeaderMap::new())
+    }
+
+    #[tokio::test]
+    async fn test_paginate_refills() {
+        let all = timeline(100, 30, "alice");
+        let pages = Mutex::new(Vec::new());
+        // pages of 10, every third status is dropped
+        let res = paginate(
+            Direction::Older,
+            15,
+            |page: Page| {
+                pages.lock().unwrap().push(page.clone());
+                let older = all
+                    .iter()
+                    .filter(|s| page.max_id.as_ref().map_or(true, |max| s.id < *max))
+                    .take(10)
+                    .cloned()
+                    .collect();
+                async move { Ok::<_, Error>(ok(older)) }
+            },
+            |s: &Status| s.id.parse::<u32>().unwrap() % 3 != 0,
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.len(), 15);
+        assert_eq!(res[0].id, "100");
+        assert_eq!(res[14].id, "79");
+        assert_eq!(
+            *pages.lock().unwrap(),
+            vec![
+                Page {
+                    limit: 15,
+                    max_id: None,
+                    since_id: None,
+                },
+                Page {
+                    limit: 8,
+                    max_id: Some(String::from("91")),
+                    since_id: None,
+                },
+                Page {
+                    limit: 2,
+                    max_id: Some(String::from("81")),
+                    since_id: None,
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_paginate_exhaustion() {
+        // an endpoint that ignores the cursor and keeps sending the same page
+        let calls = Mutex::new(0);
+        let res = paginate(
+            Direction::Older,
+            40,
+            |_| {
+                *calls.lock().unwrap() += 1;
+                async { Ok::<_, Error>(ok(timeline(100, 3, "alice"))) }
+            },
+            |_| true,
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.len(), 3);
+        assert_eq!(*calls.lock().unwrap(), 2);
+
+        // between two ids, the instance runs out at `since_id`
+        let all = timeline(100, 30, "alice");
+        let res = paginate(
+            Direction::Since {
+                since_id: String::from("80"),
+                max_id: Some(String::from("96")),
+            },
+            40,
+            |page: Page| {
+                let id = |id: Option<&String>| id.unwrap().parse::<u32>().unwrap();
+                let (since, max) = (id(page.since_id.as_ref()), id(page.max_id.as_ref()));
+                let between = all
+                    .iter()
+                    .filter(|s| (since + 1..max).contains(&id(Some(&s.id))))
+                    .take(10)
+                    .cloned()
+                    .collect();
+                async move { Ok::<_, Error>(ok(between)) }
+            },
+            |_| true,
+        )
+        .await
+        .unwrap();
+        let ids = res.iter().map(|s| s.id.as_str()).collect::<Vec<_>>();
+        assert_eq!(ids.len(), 15);
+        assert_eq!((ids[0], ids[14]), ("95", "81"));
+    }
+}
@@ -0,0 +1,131 @@
+//! Serializes record databases in the PalmOS `.pdb` layout, for databases the
+//! conduit has no template for.
+
+use std::io::{Cursor, Write};
+
+use byteorder::{BigEndian, WriteBytesExt};
+
+/// The database header, the record list follows it
+pub(crate) const HEADER_LEN: usize = 78;
+const RECORD_ENTRY_LEN: usize = 8;
+/// Where a record entry's attributes are, after the record's data offset
+pub(crate) const RECORD_ATTRIBUTES_OFFSET: usize = 4;
+/// Seconds between the PalmOS epoch (1904) and the unix epoch
+pub(crate) const PALM_EPOCH_OFFSET: u64 = 2_082_844_800;
+
+#[derive(Debug, Clone)]
+pub(crate) struct PdbBuilder {
+    name: String,
+    type_code: [u8; 4],
+    creator: [u8; 4],
+    timestamp: u32,
+    records: Vec<(u8, Vec<u8>)>,
+}
+
+impl PdbBuilder {
+    pub(crate) fn new(name: &str, type_code: [u8; 4], creator: [u8; 4]) -> Self {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        Self {
+            name: name.to_string(),
+            type_code,
+            creator,
+            timestamp: (now + PALM_EPOCH_OFFSET) as u32,
+            records: Vec::new(),
+        }
+    }
+
+    /// Seconds since 1904-01-01, used for the creation and modification dates
+    pub(crate) fn with_timestamp(mut self, timestamp: u32) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    /// Seconds since 1904-01-01 the database is dated
+    pub(crate) fn timestamp(&self) -> u32 {
+        self.timestamp
+    }
+
+    pub(crate) fn with_record(mut self, attributes: u8, data: Vec<u8>) -> Self {
+        self.records.push((attributes, data));
+        self
+    }
+
+    pub(crate) fn to_bytes(&self) -> std::io::Result<Vec<u8>> {
+        if self.name.len() >= 32 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Database names are limited to 31 bytes",
+            ));
+        }
+
+        let mut cursor = Cursor::new(Vec::new());
+        let mut name = [0_u8; 32];
+        name[..self.name.len()].copy_from_slice(self.name.as_bytes());
+        cursor.write_all(&name)?;
+        cursor.write_u16::<BigEndian>(0)?; // attributes
+        cursor.write_u16::<BigEndian>(0)?; // version
+        cursor.write_u32::<BigEndian>(self.timestamp)?; // creation date
+        cursor.write_u32::<BigEndian>(self.timestamp)?; // modification date
+        cursor.write_u32::<BigEndian>(0)?; // last backup date
+        cursor.write_u32::<BigEndian>(0)?; // modification number
+        cursor.write_u32::<BigEndian>(0)?; // app info offset
+        cursor.write_u32::<BigEndian>(0)?; // sort info offset
+        cursor.write_all(&self.type_code)?;
+        cursor.write_all(&self.creator)?;
+        cursor.write_u32::<BigEndian>(self.records.len() as u32 + 1)?; // unique id seed
+        cursor.write_u32::<BigEndian>(0)?; // next record list
+        cursor.write_u16::<BigEndian>(self.records.len() as u16)?;
+
+        // two bytes of padding follow the record list
+        let mut offset = HEADER_LEN + RECORD_ENTRY_LEN * self.records.len() + 2;
+        for (unique_id, (attributes, data)) in (1_u32..).zip(self.records.iter()) {
+            cursor.write_uint::<BigEndian>(offset as u64, RECORD_ATTRIBUTES_OFFSET)?;
+            cursor.write_u8(*attributes)?;
+            cursor.write_u24::<BigEndian>(unique_id)?;
+            offset += data.len();
+        }
+        cursor.write_u16::<BigEndian>(0)?;
+
+        for (_, data) in self.records.iter() {
+            cursor.write_all(data)?;
+        }
+
+        Ok(cursor.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PdbBuilder, HEADER_LEN, RECORD_ATTRIBUTES_OFFSET};
+
+    #[test]
+    fn test_layout() {
+        let bytes = PdbBuilder::new("TestDB", *b"Test", *b"HEFf")
+            .with_timestamp(0x1234_5678)
+            .with_record(0x40, vec![1, 2, 3])
+            .with_record(0, vec![4])
+            .to_bytes()
+            .unwrap();
+
+        assert_eq!(bytes.len(), 78 + 16 + 2 + 4);
+        assert_eq!(&bytes[..7], b"TestDB\0");
+        assert_eq!(&bytes[36..40], &[0x12, 0x34, 0x56, 0x78]);
+        assert_eq!(&bytes[60..68], b"TestHEFf");
+        assert_eq!(&bytes[76..78], &[0, 2]);
+        // first record entry: offset 96, dirty, unique id 1
+        assert_eq!(&bytes[78..86], &[0, 0, 0, 96, 0x40, 0, 0, 1]);
+        assert_eq!(bytes[HEADER_LEN + RECORD_ATTRIBUTES_OFFSET], 0x40);
+        assert_eq!(&bytes[86..94], &[0, 0, 0, 99, 0, 0, 0, 2]);
+        assert_eq!(&bytes[96..], &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_name_too_long() {
+        assert!(PdbBuilder::new(&"a".repeat(32), *b"Test", *b"HEFf")
+            .to_bytes()
+            .is_err());
+    }
+}
@@ -0,0 +1,44 @@
+//! Everything that differs between the Win32 conduit and development builds
+//! on other hosts. The HotSync entry point itself lives in `hotsync`.
+
+use std::path::PathBuf;
+
+use log::info;
+
+/// Attaches a console to the process so the user can answer the setup
+/// prompts. HotSync Manager runs conduits without one.
+pub(crate) fn alloc_console() {
+    #[cfg(windows)]
+    unsafe {
+        winapi::um::consoleapi::AllocConsole()
+    };
+}
+
+pub(crate) fn free_console() {
+    #[cfg(windows)]
+    unsafe {
+        winapi::um::wincon::FreeConsole()
+    };
+}
+
+/// Opens `url` in the user's browser. Outside of windows the conduit may be
+/// running headless, so the url is also printed for copying by hand.
+pub(crate) fn open_browser(url: &str) -> std::io::Result<()> {
+    info!("Attempting to open {} for app registration.", url);
+    #[cfg(not(windows))]
+    println!("If no browser opens, visit: {url}");
+    open::that(url)
+}
+
+/// Where the conduit keeps its config and caches when not given a directory
+/// by HotSync Manager
+pub fn default_data_dir() -> Option<PathBuf> {
+    #[cfg(windows)]
+    let base = std::env::var_os("APPDATA").map(PathBuf::from);
+    #[cfg(not(windows))]
+    let base = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")));
+
+    base.map(|base| base.join("Heffalump"))
+}
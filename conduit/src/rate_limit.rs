@@ -0,0 +1,453 @@
+//! Paces the requests to the instance by the rate limit it announces, and
+//! retries the ones it turns away. `download::get_client` wraps the
+//! megalodon client in `RateLimited`, so every call the sync makes goes
+//! through `RateLimited::execute`.
+//!
+//! Mastodon sends `X-RateLimit-Remaining` and `X-RateLimit-Reset` with each
+//! response, but megalodon only hands back the headers of successful ones.
+//! Its errors keep nothing but the status, so a 429's own `Retry-After` is
+//! never seen. A 429 waits for the reset the last successful response
+//! announced instead, or backs off exponentially when there wasn't one.
+
+use std::{
+    collections::hash_map::RandomState,
+    future::Future,
+    hash::{BuildHasher, Hasher},
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use async_trait::async_trait;
+use chrono::DateTime;
+use http::{HeaderMap, StatusCode};
+use log::{error, info, warn};
+use megalodon::{
+    entities::{Account, Context, Conversation, List, Notification, Poll, Relationship, Status},
+    error::Error,
+    megalodon::{
+        GetAccountStatusesInputOptions, GetBookmarksInputOptions,
+        GetConversationTimelineInputOptions, GetFavouritesInputOptions,
+        GetListTimelineInputOptions, GetNotificationsInputOptions, GetStatusContextInputOptions,
+        GetTimelineOptionsWithLocal, PostStatusInputOptions, PostStatusOutput,
+    },
+    response::Response,
+};
+
+use crate::api::MastodonApi;
+
+/// How hard to try before giving up on a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RetryPolicy {
+    /// 429s in a row before the request fails
+    pub(crate) max_retries: u32,
+    /// The first backoff, doubled on each retry
+    pub(crate) base_delay: Duration,
+    /// Longest single wait, for a backoff or an announced reset
+    pub(crate) max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 6,
+            base_delay: Duration::from_millis(500),
+            // Mastodon's rate limits reset every 5 minutes
+            max_delay: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+/// What the instance last said about its rate limit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Window {
+    /// Requests left until `reset`
+    remaining: Option<u64>,
+    reset: Option<SystemTime>,
+}
+
+impl Window {
+    fn from_headers(headers: &HeaderMap) -> Self {
+        let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+        Window {
+            remaining: header("x-ratelimit-remaining").and_then(|value| value.parse().ok()),
+            reset: header("x-ratelimit-reset")
+                .and_then(|value| DateTime::parse_from_rfc3339(value).ok())
+                .map(SystemTime::from),
+        }
+    }
+
+    /// How long to hold off before the next request, `None` while there
+    /// are requests left
+    fn wait(&self, now: SystemTime) -> Option<Duration> {
+        match self.remaining {
+            Some(0) => self.until_reset(now),
+            _ => None,
+        }
+    }
+
+    fn until_reset(&self, now: SystemTime) -> Option<Duration> {
+        self.reset?.duration_since(now).ok()
+    }
+}
+
+/// A `MastodonApi` that stays within the instance's rate limit
+pub(crate) struct RateLimited<A> {
+    inner: A,
+    policy: RetryPolicy,
+    window: Mutex<Window>,
+}
+
+impl<A: MastodonApi> RateLimited<A> {
+    pub(crate) fn new(inner: A) -> Self {
+        Self::with_policy(inner, RetryPolicy::default())
+    }
+
+    pub(crate) fn with_policy(inner: A, policy: RetryPolicy) -> Self {
+        Self {
+            inner,
+            policy,
+            window: Mutex::new(Window::default()),
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Runs `request` once the rate limit allows, retrying it while the
+    /// instance answers with 429s. `what` names the request in the logs and
+    /// errors.
+    async fn execute<T, F>(&self, what: &str, request: impl Fn() -> F) -> Result<Response<T>, Error>
+    where
+        F: Future<Output = Result<Response<T>, Error>>,
+    {
+        let mut retries = 0;
+        loop {
+            let wait = self.window.lock().unwrap().wait(SystemTime::now());
+            if let Some(wait) = wait {
+                if wait > self.policy.max_delay {
+                    return Err(gave_up(format!(
+                        "The instance's rate limit resets in {}s, try syncing again later",
+                        wait.as_secs()
+                    )));
+                }
+                info!(
+                    "Rate limit reached, waiting {}s for it to reset",
+                    wait.as_secs()
+                );
+                tokio::time::sleep(wait).await;
+                self.window.lock().unwrap().remaining = None;
+            }
+
+            match request().await {
+                Ok(response) => {
+                    *self.window.lock().unwrap() = Window::from_headers(&response.header);
+                    return Ok(response);
+                }
+                Err(Error::RequestError(e))
+                    if e.status() == Some(StatusCode::TOO_MANY_REQUESTS) =>
+                {
+                    if retries == self.policy.max_retries {
+                        error!("Giving up on {what} after {retries} retries");
+                        return Err(gave_up(format!(
+                            "The instance is still rate limiting {what} after {retries} retries, \
+                             try syncing again later"
+                        )));
+                    }
+                    let delay = self.retry_delay(retries);
+                    warn!(
+                        "received 429 for {what}, retrying in {}ms",
+                        delay.as_millis()
+                    );
+                    tokio::time::sleep(delay).await;
+                    retries += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn retry_delay(&self, retries: u32) -> Duration {
+        let announced = self.window.lock().unwrap().until_reset(SystemTime::now());
+        announced
+            .unwrap_or_else(|| backoff(&self.policy, retries, jitter()))
+            .min(self.policy.max_delay)
+    }
+}
+
+/// The wait before retry number `retries` (from 0), `jitter` between 0 and 1
+/// stretches it by up to half so concurrent requests don't retry in lockstep
+fn backoff(policy: &RetryPolicy, retries: u32, jitter: f64) -> Duration {
+    let delay = policy
+        .base_delay
+        .saturating_mul(2_u32.saturating_pow(retries))
+        .min(policy.max_delay);
+    delay + delay.mul_f64(jitter / 2.0)
+}
+
+/// Between 0 and 1, from the randomly keyed std hasher
+fn jitter() -> f64 {
+    RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64
+}
+
+fn gave_up(message: String) -> Error {
+    Error::StandardError(std::io::Error::other(message))
+}
+
+#[async_trait]
+impl<A: MastodonApi> MastodonApi for RateLimited<A> {
+    async fn verify_account_credentials(&self) -> Result<Response<Account>, Error> {
+        self.execute("the account", || self.inner.verify_account_credentials())
+            .await
+    }
+
+    async fn get_home_timeline(
+        &self,
+        options: Option<&GetTimelineOptionsWithLocal>,
+    ) -> Result<Response<Vec<Status>>, Error> {
+        self.execute("the home timeline", || {
+            self.inner.get_home_timeline(options)
+        })
+        .await
+    }
+
+    async fn get_account_statuses(
+        &self,
+        id: String,
+        options: Option<&GetAccountStatusesInputOptions>,
+    ) -> Result<Response<Vec<Status>>, Error> {
+        self.execute("the account's toots", || {
+            self.inner.get_account_statuses(id.clone(), options)
+        })
+        .await
+    }
+
+    async fn get_status_context(
+        &self,
+        id: String,
+        options: Option<&GetStatusContextInputOptions>,
+    ) -> Result<Response<Context>, Error> {
+        self.execute("a conversation", || {
+            self.inner.get_status_context(id.clone(), options)
+        })
+        .await
+    }
+
+    async fn get_notifications(
+        &self,
+        options: Option<&GetNotificationsInputOptions>,
+    ) -> Result<Response<Vec<Notification>>, Error> {
+        self.execute("notifications", || self.inner.get_notifications(options))
+            .await
+    }
+
+    async fn get_conversation_timeline(
+        &self,
+        options: Option<&GetConversationTimelineInputOptions>,
+    ) -> Result<Response<Vec<Conversation>>, Error> {
+        self.execute("direct messages", || {
+            self.inner.get_conversation_timeline(options)
+        })
+        .await
+    }
+
+    async fn get_lists(&self) -> Result<Response<Vec<List>>, Error> {
+        self.execute("lists", || self.inner.get_lists()).await
+    }
+
+    async fn get_list_timeline(
+        &self,
+        list_id: String,
+        options: Option<&GetListTimelineInputOptions>,
+    ) -> Result<Response<Vec<Status>>, Error> {
+        self.execute("a list", || {
+            self.inner.get_list_timeline(list_id.clone(), options)
+        })
+        .await
+    }
+
+    async fn get_tag_timeline(
+        &self,
+        hashtag: String,
+        options: Option<&GetTimelineOptionsWithLocal>,
+    ) -> Result<Response<Vec<Status>>, Error> {
+        self.execute("a hashtag", || {
+            self.inner.get_tag_timeline(hashtag.clone(), options)
+        })
+        .await
+    }
+
+    async fn get_public_timeline(
+        &self,
+        options: Option<&GetTimelineOptionsWithLocal>,
+    ) -> Result<Response<Vec<Status>>, Error> {
+        self.execute("the public timeline", || {
+            self.inner.get_public_timeline(options)
+        })
+        .await
+    }
+
+    async fn get_bookmarks(
+        &self,
+        options: Option<&GetBookmarksInputOptions>,
+    ) -> Result<Response<Vec<Status>>, Error> {
+        self.execute("bookmarks", || self.inner.get_bookmarks(options))
+            .await
+    }
+
+    async fn get_favourites(
+        &self,
+        options: Option<&GetFavouritesInputOptions>,
+    ) -> Result<Response<Vec<Status>>, Error> {
+        self.execute("favourites", || self.inner.get_favourites(options))
+            .await
+    }
+
+    async fn favourite_status(&self, id: String) -> Result<Response<Status>, Error> {
+        self.execute("a favourite", || self.inner.favourite_status(id.clone()))
+            .await
+    }
+
+    async fn reblog_status(&self, id: String) -> Result<Response<Status>, Error> {
+        self.execute("a boost", || self.inner.reblog_status(id.clone()))
+            .await
+    }
+
+    async fn bookmark_status(&self, id: String) -> Result<Response<Status>, Error> {
+        self.execute("a bookmark", || self.inner.bookmark_status(id.clone()))
+            .await
+    }
+
+    async fn unbookmark_status(&self, id: String) -> Result<Response<Status>, Error> {
+        self.execute("removing a bookmark", || {
+            self.inner.unbookmark_status(id.clone())
+        })
+        .await
+    }
+
+    async fn post_status(
+        &self,
+        status: String,
+        options: Option<&PostStatusInputOptions>,
+    ) -> Result<Response<PostStatusOutput>, Error> {
+        self.execute("a new toot", || {
+            self.inner.post_status(status.clone(), options)
+        })
+        .await
+    }
+
+    async fn get_relationships(
+        &self,
+        ids: Vec<String>,
+    ) -> Result<Response<Vec<Relationship>>, Error> {
+        self.execute("relationships", || {
+            self.inner.get_relationships(ids.clone())
+        })
+        .await
+    }
+
+    async fn follow_account(&self, id: String) -> Result<Response<Relationship>, Error> {
+        self.execute("a follow", || self.inner.follow_account(id.clone()))
+            .await
+    }
+
+    async fn unfollow_account(&self, id: String) -> Result<Response<Relationship>, Error> {
+        self.execute("an unfollow", || self.inner.unfollow_account(id.clone()))
+            .await
+    }
+
+    async fn vote_poll(&self, id: String, choices: Vec<u32>) -> Result<Response<Poll>, Error> {
+        self.execute("a vote", || {
+            self.inner.vote_poll(id.clone(), choices.clone())
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, SystemTime};
+
+    use http::HeaderMap;
+
+    use crate::{
+        api::MastodonApi,
+        mock::{rate_limited, timeline, Call, Endpoint, MockMastodon},
+        rate_limit::{backoff, RetryPolicy, Window},
+    };
+
+    /// 2024-01-01T00:00:00Z
+    fn new_year() -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_704_067_200)
+    }
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (http::HeaderName::from_static(name), value.parse().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_window() {
+        let now = new_year();
+        let window = Window::from_headers(&headers(&[
+            ("x-ratelimit-remaining", "0"),
+            ("x-ratelimit-reset", "2024-01-01T00:02:30.000Z"),
+        ]));
+        assert_eq!(window.wait(now), Some(Duration::from_secs(150)));
+        // an announced reset that has passed doesn't hold anything up
+        assert_eq!(window.wait(now + Duration::from_secs(200)), None);
+
+        let window = Window::from_headers(&headers(&[
+            ("x-ratelimit-remaining", "12"),
+            ("x-ratelimit-reset", "2024-01-01T00:02:30.000Z"),
+        ]));
+        assert_eq!(window.wait(now), None);
+        assert_eq!(window.until_reset(now), Some(Duration::from_secs(150)));
+
+        assert_eq!(Window::from_headers(&HeaderMap::new()), Window::default());
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy {
+            max_retries: 10,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(10),
+        };
+        assert_eq!(backoff(&policy, 0, 0.0), Duration::from_millis(500));
+        assert_eq!(backoff(&policy, 3, 0.0), Duration::from_secs(4));
+        assert_eq!(backoff(&policy, 3, 1.0), Duration::from_secs(6));
+        assert_eq!(backoff(&policy, 9, 0.0), Duration::from_secs(10));
+        assert_eq!(backoff(&policy, 40, 0.0), Duration::from_secs(10));
+    }
+
+    #[tokio::test]
+    async fn test_retries() {
+        let client = rate_limited(
+            MockMastodon::new("me")
+                .with_home(timeline(1000, 5, "alice"))
+                .with_too_many_requests(Endpoint::HomeTimeline, 3),
+        );
+        let res = client.get_home_timeline(None).await.unwrap();
+        assert_eq!(res.json().len(), 5);
+        assert_eq!(client.inner().calls().len(), 4);
+
+        // gives up after the last retry instead of asking forever
+        let client = rate_limited(
+            MockMastodon::new("me").with_too_many_requests(Endpoint::VerifyCredentials, 100),
+        );
+        let err = client.verify_account_credentials().await.unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("still rate limiting the account after 3 retries"));
+        assert!(client
+            .inner()
+            .calls()
+            .iter()
+            .all(|call| *call == Call::VerifyCredentials));
+        assert_eq!(client.inner().calls().len(), 4);
+    }
+}
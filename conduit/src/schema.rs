@@ -0,0 +1,113 @@
+//! Negotiates the record format with the device app, from the schema it
+//! reported on the previous sync (see `device`). A device that didn't report
+//! one predates the schema header and is treated as version 0.
+
+use std::{fmt::Display, ops::RangeInclusive};
+
+use crate::heffalump_hh_types::{
+    SchemaHeader, MIN_SCHEMA_VERSION, SCHEMA_VERSION, SUPPORTED_FEATURES,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum SchemaError {
+    /// The device app is older than anything this conduit still writes
+    DeviceTooOld { device: u16, minimum: u16 },
+}
+
+impl Display for SchemaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SchemaError::DeviceTooOld { device, minimum } => write!(
+                f,
+                "The device app uses record schema {device}, this conduit needs at least \
+                 {minimum}. Update Heffalump on the handheld."
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+/// The schema to write for a device that reported `device`, `None` if it
+/// never has
+pub(crate) fn negotiate(device: Option<SchemaHeader>) -> Result<SchemaHeader, SchemaError> {
+    negotiate_within(
+        device,
+        MIN_SCHEMA_VERSION..=SCHEMA_VERSION,
+        SUPPORTED_FEATURES,
+    )
+}
+
+fn negotiate_within(
+    device: Option<SchemaHeader>,
+    versions: RangeInclusive<u16>,
+    features: u16,
+) -> Result<SchemaHeader, SchemaError> {
+    let device = device.unwrap_or_default();
+    if device.version < *versions.start() {
+        return Err(SchemaError::DeviceTooOld {
+            device: device.version,
+            minimum: *versions.start(),
+        });
+    }
+    // a newer device app still understands the older layouts
+    Ok(SchemaHeader {
+        version: device.version.min(*versions.end()),
+        features: device.features & features,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        heffalump_hh_types::SchemaHeader,
+        schema::{negotiate, negotiate_within, SchemaError},
+    };
+
+    fn schema(version: u16, features: u16) -> SchemaHeader {
+        SchemaHeader { version, features }
+    }
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(negotiate(None), Ok(schema(0, 0)));
+        assert_eq!(
+            negotiate_within(Some(schema(1, 0b111)), 0..=1, 0b101),
+            Ok(schema(1, 0b101))
+        );
+        // down-converts for older devices, and caps newer ones at what we write
+        assert_eq!(
+            negotiate_within(Some(schema(1, 0)), 0..=3, 0),
+            Ok(schema(1, 0))
+        );
+        assert_eq!(
+            negotiate_within(Some(schema(5, 0)), 0..=3, 0),
+            Ok(schema(3, 0))
+        );
+        assert_eq!(
+            negotiate_within(None, 1..=3, 0),
+            Err(SchemaError::DeviceTooOld {
+                device: 0,
+                minimum: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_negotiate_refuses_old_devices() {
+        let err = negotiate_within(Some(schema(2, 0b1)), 3..=5, 0b1).unwrap_err();
+        assert_eq!(
+            err,
+            SchemaError::DeviceTooOld {
+                device: 2,
+                minimum: 3
+            }
+        );
+        assert!(err.to_string().contains("needs at least 3"));
+        // the minimum itself is still written
+        assert_eq!(
+            negotiate_within(Some(schema(3, 0)), 3..=5, 0),
+            Ok(schema(3, 0))
+        );
+    }
+}
@@ -0,0 +1,84 @@
+//! Where the last sync left off, so the next one only downloads what's new.
+//! Kept next to the timeline cache, which holds the statuses themselves.
+
+use std::path::Path;
+
+use log::warn;
+use megalodon::entities::Status;
+use serde::{Deserialize, Serialize};
+
+use crate::{heffalump_hh_types::HeffalumpPrefs, MASTODON_CACHE_NEW, SYNC_STATE_FILE};
+
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct SyncState {
+    /// Newest status shipped in the home timeline
+    #[serde(default)]
+    pub(crate) home_newest_id: Option<String>,
+    /// Stretches of the home timeline a sync ran out of budget for, newest
+    /// first. The following syncs backfill them.
+    #[serde(default)]
+    pub(crate) home_gaps: Vec<HomeGap>,
+}
+
+/// Home statuses newer than `after` and older than `before` that were never
+/// downloaded
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct HomeGap {
+    pub(crate) after: String,
+    pub(crate) before: String,
+}
+
+impl SyncState {
+    pub(crate) fn read(dir: &Path) -> Self {
+        let path = dir.join(SYNC_STATE_FILE);
+        match std::fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                warn!("Ignoring malformed {}: {e}", path.display());
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub(crate) fn save(&self, dir: &Path) -> std::io::Result<()> {
+        std::fs::write(dir.join(SYNC_STATE_FILE), serde_json::to_string(self)?)
+    }
+}
+
+/// The timelines the last sync shipped, from the timeline cache
+#[derive(Debug)]
+pub(crate) struct PreviousSync {
+    pub(crate) home: Vec<Status>,
+    pub(crate) self_posts: Vec<Status>,
+    /// Each extra timeline's title, as Latin-1 like in the prefs
+    pub(crate) timelines: Vec<(Vec<u8>, Vec<Status>)>,
+}
+
+impl PreviousSync {
+    /// `None` when there was no previous sync, or its cache can't be read
+    pub(crate) fn read(dir: &Path) -> Option<Self> {
+        let path = dir.join(MASTODON_CACHE_NEW);
+        let cache = std::fs::read(&path).ok()?;
+        let (prefs, statuses): (HeffalumpPrefs, Vec<Status>) = serde_json::from_slice(&cache)
+            .inspect_err(|e| warn!("Ignoring malformed {}: {e}", path.display()))
+            .ok()?;
+        let section = |start: usize, len: u16| {
+            statuses
+                .iter()
+                .skip(start)
+                .take(len as usize)
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        let home_len = prefs.home_timeline_len as usize;
+        Some(Self {
+            home: section(0, prefs.home_timeline_len),
+            self_posts: section(home_len, prefs.self_timeline_len),
+            timelines: prefs
+                .timelines
+                .iter()
+                .map(|t| (t.title.clone(), section(t.start as usize, t.timeline_len)))
+                .collect(),
+        })
+    }
+}
@@ -1,8 +1,100 @@
-use log::{error, info};
-use megalodon::{entities::Status, error::Error, megalodon::PostStatusInputOptions, Megalodon};
+use log::{error, info, trace};
+use megalodon::{
+    entities::{Status, StatusVisibility},
+    error::Error,
+    megalodon::PostStatusInputOptions,
+};
 use palmrs::database::record::pdb_record::RecordAttributes;
+use serde::de::DeserializeOwned;
+use std::path::Path;
 
-use crate::heffalump_hh_types::{HeffalumpPrefs, OnDevice, TootWrite};
+use crate::{
+    api::MastodonApi,
+    device::DeviceReport,
+    heffalump_hh_types::{HeffalumpPrefs, OnDevice, TootWrite},
+    log_err, AUTHORS_CACHE_OLD, CONVERSATIONS_CACHE_OLD, MASTODON_CACHE_OLD,
+};
+
+/// Parses the records of `HeffalumpWritesDB` and executes them against the
+/// timeline cache that was shipped to the device on the previous sync
+pub(crate) async fn apply_writes(
+    client: &dyn MastodonApi,
+    path: &Path,
+    from_hh: Vec<(Vec<u8>, RecordAttributes, u32)>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let parsed = match parse_writes(from_hh) {
+        Ok(v) => v,
+        Err(e) => return Err(Box::new(e)),
+    };
+    trace!("parsed writes");
+    let mut report = DeviceReport::read(path);
+    let (changed, parsed) = report.take_from(parsed);
+    if changed {
+        report.save(path).map_err(log_err)?;
+    }
+    let conversations = match parsed
+        .iter()
+        .any(|write| matches!(write, TootWrite::DirectReply(_)))
+    {
+        true => read_cache(path, CONVERSATIONS_CACHE_OLD, "conversations")?,
+        false => Vec::new(),
+    };
+    let authors = match parsed.iter().any(|write| {
+        matches!(
+            write,
+            TootWrite::FollowAuthor(_) | TootWrite::UnfollowAuthor(_)
+        )
+    }) {
+        true => read_cache(path, AUTHORS_CACHE_OLD, "authors")?,
+        false => Vec::new(),
+    };
+    let mut path = path.to_owned();
+    path.push(MASTODON_CACHE_OLD);
+    let source_file = match (std::fs::File::open(&path), parsed.len()) {
+        (Ok(f), _) => f,
+        (Err(_), 0) => {
+            // We can't find the cache, but don't have anything to write anyway
+            return Ok(());
+        }
+        (Err(e), _) => {
+            error!("Failed to open cache: {}", e);
+            return Err(Box::new(e));
+        }
+    };
+    trace!("found cache");
+    let (prefs, source) = match serde_json::from_reader(&source_file) {
+        Ok(ok) => ok,
+        Err(e) => {
+            error!("Failed to deserialize cache with error: {}", e);
+            return Err(Box::new(e));
+        }
+    };
+    trace!("deserialized cache");
+
+    if let Err(e) = execute_writes(client, parsed, source, conversations, authors, prefs).await {
+        error!("Failed to write with result: {}", e);
+        return Err(Box::new(e));
+    }
+    trace!("executed writes");
+    Ok(())
+}
+
+/// One of the caches written alongside the timeline one, `what` it holds is
+/// for the logs
+fn read_cache<T: DeserializeOwned>(
+    dir: &Path,
+    name: &str,
+    what: &str,
+) -> Result<T, Box<dyn std::error::Error>> {
+    let file = std::fs::File::open(dir.join(name)).map_err(|e| {
+        error!("Failed to open {} cache: {}", what, e);
+        e
+    })?;
+    Ok(serde_json::from_reader(&file).map_err(|e| {
+        error!("Failed to deserialize {} cache with error: {}", what, e);
+        e
+    })?)
+}
 
 pub(crate) fn parse_writes(
     raw_device_data: Vec<(Vec<u8>, RecordAttributes, u32)>,
@@ -18,21 +110,42 @@ pub(crate) fn parse_writes(
 }
 
 pub(crate) async fn execute_writes(
-    client: &(dyn Megalodon + Send + Sync),
+    client: &dyn MastodonApi,
     writes: Vec<TootWrite>,
     source: Vec<Status>,
+    conversations: Vec<Status>,
+    authors: Vec<String>,
     _prefs: HeffalumpPrefs,
 ) -> Result<(), Error> {
+    // direct replies leave us out of the accounts they mention
+    let me = match writes
+        .iter()
+        .any(|write| matches!(write, TootWrite::DirectReply(_)))
+    {
+        true => Some(client.verify_account_credentials().await?.json.acct),
+        false => None,
+    };
     for write in writes {
-        execute_single_write(client, write, &source).await?;
+        execute_single_write(
+            client,
+            write,
+            &source,
+            &conversations,
+            &authors,
+            me.as_deref(),
+        )
+        .await?;
     }
     Ok(())
 }
 
 async fn execute_single_write(
-    client: &(dyn Megalodon + Send + Sync),
+    client: &dyn MastodonApi,
     write: TootWrite,
     source: &[Status],
+    conversations: &[Status],
+    authors: &[String],
+    me: Option<&str>,
 ) -> Result<(), Error> {
     match write {
         TootWrite::Favorite(fav) => {
@@ -42,7 +155,18 @@ async fn execute_single_write(
             ))?;
             client.favourite_status(status.id.clone()).await?;
         }
-        TootWrite::Follow(_) => (),
+        TootWrite::Follow(follow) => {
+            let status = source.get(follow as usize).ok_or(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Cache seems to have incorrect data",
+            ))?;
+            // the device shows a boost as the boosted toot's author's
+            let id = &status.reblog.as_deref().unwrap_or(status).account.id;
+            info!("Following {}", id);
+            client.follow_account(id.clone()).await?;
+        }
+        // recorded by apply_writes, nothing to send
+        TootWrite::DeviceSchema(_) | TootWrite::FetchLimits(_) => (),
         TootWrite::Reblog(reblog) => {
             let status = source.get(reblog as usize).ok_or(std::io::Error::new(
                 std::io::ErrorKind::InvalidData,
@@ -50,44 +174,127 @@ async fn execute_single_write(
             ))?;
             client.reblog_status(status.id.clone()).await?;
         }
+        TootWrite::Bookmark(bookmark) => {
+            let status = source.get(bookmark as usize).ok_or(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Cache seems to have incorrect data",
+            ))?;
+            client.bookmark_status(status.id.clone()).await?;
+        }
+        TootWrite::Unbookmark(unbookmark) => {
+            let status = source.get(unbookmark as usize).ok_or(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Cache seems to have incorrect data",
+            ))?;
+            client.unbookmark_status(status.id.clone()).await?;
+        }
+        TootWrite::FollowAuthor(author) => {
+            let id = authors.get(author as usize).ok_or(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Authors cache seems to have incorrect data",
+            ))?;
+            info!("Following {}", id);
+            client.follow_account(id.clone()).await?;
+        }
+        TootWrite::UnfollowAuthor(author) => {
+            let id = authors.get(author as usize).ok_or(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Authors cache seems to have incorrect data",
+            ))?;
+            info!("Unfollowing {}", id);
+            client.unfollow_account(id.clone()).await?;
+        }
         TootWrite::Toot(toot) => {
-            let options = match toot.is_reply_to {
-                0 => None,
-                // have to emulate an option type because C.
-                //  See TootContentConstuctor in heffalump
-                val @ 1.. => {
-                    let mut options = PostStatusInputOptions::default();
-                    let status_id = source.get((val - 1) as usize).ok_or(std::io::Error::new(
-                        std::io::ErrorKind::InvalidData,
-                        "Cache seems to have incorrect data",
-                    ))?;
-                    options.in_reply_to_id = Some(status_id.id.clone());
-                    Some(options)
-                }
+            post_toot(
+                client,
+                source,
+                toot.is_reply_to,
+                &toot.contents,
+                None,
+                false,
+            )
+            .await?
+        }
+        TootWrite::TootV2(toot) => {
+            let warning = match toot.warning.is_empty() {
+                true => None,
+                false => Some(from_handheld(&toot.warning)?),
             };
+            post_toot(
+                client,
+                source,
+                toot.is_reply_to,
+                &toot.contents,
+                warning,
+                toot.sensitive,
+            )
+            .await?
+        }
+        TootWrite::Vote(vote) => {
+            let status = source
+                .get(vote.content as usize)
+                .ok_or(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "Cache seems to have incorrect data",
+                ))?;
+            // boosts carry the poll of the toot they boost
+            let poll = status
+                .reblog
+                .as_deref()
+                .unwrap_or(status)
+                .poll
+                .as_ref()
+                .ok_or(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "Voted on a toot without a poll",
+                ))?;
+            let choices = vote.choices.iter().map(|c| *c as u32).collect::<Vec<_>>();
+            let known = choices.iter().all(|c| (*c as usize) < poll.options.len());
+            let unique = choices
+                .iter()
+                .enumerate()
+                .all(|(i, c)| !choices[..i].contains(c));
+            if !known || !unique || (choices.len() > 1 && !poll.multiple) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("Invalid choices {:?} for poll {}", choices, poll.id),
+                )
+                .into());
+            }
+            info!("Voting {:?} in poll {}", choices, poll.id);
+            client.vote_poll(poll.id.clone(), choices).await?;
+        }
+        TootWrite::DirectReply(reply) => {
+            let message = conversations
+                .get(reply.message as usize)
+                .ok_or(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "Conversations cache seems to have incorrect data",
+                ))?;
+            let content = from_handheld(&reply.contents)?;
 
-            use encoding::all::ISO_8859_1;
-            use encoding::{DecoderTrap, Encoding};
-            let content = match ISO_8859_1.decode(&toot.contents, DecoderTrap::Strict) {
-                Ok(c) => c,
-                Err(e) => {
-                    error!("Error decoding text from handheld: {e}");
-                    return Err(Error::StandardError(std::io::Error::new(
-                        std::io::ErrorKind::Other,
-                        e.to_string().as_str(),
-                    )));
+            // direct toots only reach the accounts they mention
+            let mut to = Vec::new();
+            for acct in std::iter::once(&message.account.acct)
+                .chain(message.mentions.iter().map(|mention| &mention.acct))
+            {
+                if Some(acct.as_str()) != me && !to.contains(&acct) {
+                    to.push(acct);
                 }
-            };
+            }
+            let status = to
+                .iter()
+                .map(|acct| format!("@{acct} "))
+                .chain(std::iter::once(content))
+                .collect::<String>();
 
-            match options
-                .as_ref()
-                .map(|x| x.in_reply_to_id.as_ref())
-                .flatten()
-            {
-                Some(reply_id) => info!("Posting in reply to {}: {}", reply_id, &content),
-                None => info!("Posting: {}", &content),
+            let options = PostStatusInputOptions {
+                in_reply_to_id: Some(message.id.clone()),
+                visibility: Some(StatusVisibility::Direct),
+                ..Default::default()
             };
-            if let Err(e) = client.post_status(content, options.as_ref()).await {
+            info!("Replying directly to {}: {}", message.id, &status);
+            if let Err(e) = client.post_status(status, Some(&options)).await {
                 error!("{}", e);
                 return Err(e);
             };
@@ -95,3 +302,334 @@ async fn execute_single_write(
     }
     Ok(())
 }
+
+/// Posts a toot written on the handheld, with `warning` as its spoiler text
+async fn post_toot(
+    client: &dyn MastodonApi,
+    source: &[Status],
+    is_reply_to: u16,
+    contents: &[u8],
+    warning: Option<String>,
+    sensitive: bool,
+) -> Result<(), Error> {
+    let mut options = PostStatusInputOptions {
+        spoiler_text: warning,
+        sensitive: sensitive.then_some(true),
+        ..Default::default()
+    };
+    // have to emulate an option type because C.
+    //  See TootContentConstuctor in heffalump
+    if is_reply_to != 0 {
+        let status = source
+            .get((is_reply_to - 1) as usize)
+            .ok_or(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Cache seems to have incorrect data",
+            ))?;
+        options.in_reply_to_id = Some(status.id.clone());
+    }
+
+    let content = from_handheld(contents)?;
+
+    match &options.in_reply_to_id {
+        Some(reply_id) => info!("Posting in reply to {}: {}", reply_id, &content),
+        None => info!("Posting: {}", &content),
+    };
+    if let Err(e) = client.post_status(content, Some(&options)).await {
+        error!("{}", e);
+        return Err(e);
+    };
+    Ok(())
+}
+
+/// Decodes text typed on the handheld
+fn from_handheld(contents: &[u8]) -> Result<String, Error> {
+    use encoding::all::ISO_8859_1;
+    use encoding::{DecoderTrap, Encoding};
+    ISO_8859_1
+        .decode(contents, DecoderTrap::Strict)
+        .map_err(|e| {
+            error!("Error decoding text from handheld: {e}");
+            Error::StandardError(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                e.to_string().as_str(),
+            ))
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        heffalump_hh_types::{
+            DirectReply, HeffalumpPrefs, PollVote, TootContent, TootContentV2, TootWrite,
+        },
+        mock::{direct, poll, status, timeline, Call, MockMastodon},
+        upload::execute_writes,
+    };
+
+    fn toot(is_reply_to: u16, contents: &[u8]) -> TootWrite {
+        TootWrite::Toot(TootContent {
+            author: 0,
+            is_reply_to,
+            replies_start: 0,
+            contents: contents.to_vec(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_execute_writes() {
+        let source = timeline(100, 3, "alice");
+        let client = MockMastodon::new("me").with_home(source.clone());
+        let writes = vec![
+            TootWrite::Favorite(1),
+            TootWrite::Reblog(0),
+            toot(0, b"caf\xe9"),
+            toot(3, b"reply"),
+            TootWrite::Bookmark(2),
+            TootWrite::Unbookmark(0),
+            TootWrite::TootV2(TootContentV2 {
+                author: 0,
+                is_reply_to: 2,
+                replies_start: 0,
+                sensitive: true,
+                warning: b"ending".to_vec(),
+                contents: b"they lived".to_vec(),
+            }),
+        ];
+
+        execute_writes(
+            &client,
+            writes,
+            source,
+            Vec::new(),
+            Vec::new(),
+            HeffalumpPrefs::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            *client.calls(),
+            vec![
+                Call::Favourite(String::from("99")),
+                Call::Reblog(String::from("100")),
+                Call::PostStatus {
+                    status: String::from("café"),
+                    in_reply_to_id: None,
+                    direct: false,
+                    spoiler_text: None,
+                },
+                Call::PostStatus {
+                    status: String::from("reply"),
+                    in_reply_to_id: Some(String::from("98")),
+                    direct: false,
+                    spoiler_text: None,
+                },
+                Call::Bookmark(String::from("98")),
+                Call::Unbookmark(String::from("100")),
+                Call::PostStatus {
+                    status: String::from("they lived"),
+                    in_reply_to_id: Some(String::from("99")),
+                    direct: false,
+                    spoiler_text: Some(String::from("ending")),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_execute_writes_out_of_range() {
+        let source = timeline(100, 3, "alice");
+        let client = MockMastodon::new("me").with_home(source.clone());
+
+        let res = execute_writes(
+            &client,
+            vec![TootWrite::Favorite(3)],
+            source,
+            Vec::new(),
+            Vec::new(),
+            HeffalumpPrefs::default(),
+        )
+        .await;
+        assert!(res.is_err());
+        assert!(client.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_execute_direct_reply() {
+        let conversations = vec![
+            direct("300", "me", "<p>@bob @carol lunch?</p>", &["bob", "carol"]),
+            direct("301", "bob", "<p>@me @carol sure</p>", &["me", "carol"]),
+        ];
+        let client = MockMastodon::new("me");
+
+        execute_writes(
+            &client,
+            vec![
+                TootWrite::DirectReply(DirectReply {
+                    message: 1,
+                    contents: b"noon".to_vec(),
+                }),
+                TootWrite::DirectReply(DirectReply {
+                    message: 0,
+                    contents: b"anyone?".to_vec(),
+                }),
+            ],
+            Vec::new(),
+            conversations,
+            Vec::new(),
+            HeffalumpPrefs::default(),
+        )
+        .await
+        .unwrap();
+
+        // our account is looked up once for all the replies
+        assert_eq!(
+            *client.calls(),
+            vec![
+                Call::VerifyCredentials,
+                Call::PostStatus {
+                    status: String::from("@bob @carol noon"),
+                    in_reply_to_id: Some(String::from("301")),
+                    direct: true,
+                    spoiler_text: None,
+                },
+                Call::PostStatus {
+                    status: String::from("@bob @carol anyone?"),
+                    in_reply_to_id: Some(String::from("300")),
+                    direct: true,
+                    spoiler_text: None,
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_execute_follows() {
+        let authors = vec![String::from("10"), String::from("11")];
+        let client = MockMastodon::new("me");
+
+        execute_writes(
+            &client,
+            vec![TootWrite::FollowAuthor(1), TootWrite::UnfollowAuthor(0)],
+            Vec::new(),
+            Vec::new(),
+            authors.clone(),
+            HeffalumpPrefs::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            *client.calls(),
+            vec![
+                Call::Follow(String::from("11")),
+                Call::Unfollow(String::from("10"))
+            ]
+        );
+
+        // the author of a toot, or of the toot a boost boosts
+        let mut boost = status("201", "bob", "");
+        boost.reblog = Some(Box::new(status("200", "alice", "<p>hi</p>")));
+        let source = vec![status("199", "carol", "<p>yo</p>"), boost];
+        let client = MockMastodon::new("me");
+        execute_writes(
+            &client,
+            vec![TootWrite::Follow(0), TootWrite::Follow(1)],
+            source,
+            Vec::new(),
+            Vec::new(),
+            HeffalumpPrefs::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            *client.calls(),
+            vec![
+                Call::Follow(String::from("carol")),
+                Call::Follow(String::from("alice"))
+            ]
+        );
+
+        let res = execute_writes(
+            &client,
+            vec![TootWrite::FollowAuthor(2)],
+            Vec::new(),
+            Vec::new(),
+            authors,
+            HeffalumpPrefs::default(),
+        )
+        .await;
+        assert!(res.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_execute_vote() {
+        let mut polled = status("200", "alice", "<p>lunch?</p>");
+        polled.poll = Some(poll("7", &[("pizza", Some(1)), ("soup", None)]));
+        let mut boost = status("201", "bob", "");
+        boost.reblog = Some(Box::new(polled));
+        let source = vec![boost, status("199", "alice", "<p>no poll</p>")];
+        let client = MockMastodon::new("me").with_home(source.clone());
+
+        let vote = |content, choices: &[u8]| {
+            TootWrite::Vote(PollVote {
+                content,
+                choices: choices.to_vec(),
+            })
+        };
+        execute_writes(
+            &client,
+            vec![vote(0, &[1])],
+            source.clone(),
+            Vec::new(),
+            Vec::new(),
+            HeffalumpPrefs::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            *client.calls(),
+            vec![Call::Vote {
+                poll: String::from("7"),
+                choices: vec![1],
+            }]
+        );
+
+        let res = execute_writes(
+            &client,
+            vec![vote(1, &[0])],
+            source,
+            Vec::new(),
+            Vec::new(),
+            HeffalumpPrefs::default(),
+        )
+        .await;
+        assert!(res.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_execute_vote_invalid_choices() {
+        let mut polled = status("200", "alice", "<p>lunch?</p>");
+        polled.poll = Some(poll("7", &[("pizza", Some(1)), ("soup", None)]));
+        let source = vec![polled];
+        let client = MockMastodon::new("me").with_home(source.clone());
+
+        // past the options, repeated, and several in a single choice poll
+        for choices in [&[2][..], &[1, 1], &[0, 1]] {
+            let res = execute_writes(
+                &client,
+                vec![TootWrite::Vote(PollVote {
+                    content: 0,
+                    choices: choices.to_vec(),
+                })],
+                source.clone(),
+                Vec::new(),
+                Vec::new(),
+                HeffalumpPrefs::default(),
+            )
+            .await;
+            assert!(res.is_err(), "{choices:?}");
+        }
+        assert!(client.calls().is_empty());
+    }
+}
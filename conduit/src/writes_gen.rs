@@ -0,0 +1,284 @@
+//! Builds a `HeffalumpWritesDB` from a description of handheld actions, so the
+//! write path can be exercised without a device.
+//!
+//! ```json
+//! { "writes": [
+//!     { "favorite": 3 },
+//!     { "reblog": 7 },
+//!     { "toot": { "text": "hello from the palm", "in_reply_to": 12 } },
+//!     { "toot": { "text": "it was the butler", "warning": "spoilers" } },
+//!     { "direct_reply": { "text": "see you there", "message": 2 } },
+//!     { "bookmark": 5 },
+//!     { "follow_author": 2 },
+//!     { "vote": { "toot": 4, "choices": [0, 2] } }
+//! ] }
+//! ```
+//!
+//! Indices (and `toot`) are positions in the content database (and the
+//! timeline cache) from the previous sync, `choices` are positions in the
+//! toot's poll, `message` is one in the conversations database
+//! and the `*_author` ones are in the author database.
+//! The same structure can be written as TOML.
+
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::{
+    heffalump_hh_types::{
+        DirectReply, FetchLimits, OnDevice, PollVote, SchemaHeader, TootContent, TootContentV2,
+        TootWrite,
+    },
+    pdb::PdbBuilder,
+    to_latin_1, DB_NAME_WRITES,
+};
+
+const WRITES_DB_TYPE: [u8; 4] = [b'W', b'r', b't', b'e'];
+const CREATOR: [u8; 4] = [b'H', b'E', b'F', b'f'];
+/// dmRecAttrDirty, set on every record the device app creates
+const ATTR_DIRTY: u8 = 0x40;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WritesSpec {
+    pub writes: Vec<WriteSpec>,
+    /// Seconds since 1904 to stamp the database with, for reproducible output
+    #[serde(default)]
+    pub timestamp: Option<u32>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WriteSpec {
+    Favorite(u16),
+    Follow(u16),
+    Reblog(u16),
+    Toot {
+        text: String,
+        #[serde(default)]
+        in_reply_to: Option<u16>,
+        /// Written as a `TootV2` when set
+        #[serde(default)]
+        warning: Option<String>,
+    },
+    DeviceSchema {
+        version: u16,
+        #[serde(default)]
+        features: u16,
+    },
+    /// 0 keeps the configured limit
+    FetchLimits {
+        #[serde(default)]
+        home_timeline: u16,
+        #[serde(default)]
+        self_timeline: u16,
+        #[serde(default)]
+        replies: u16,
+    },
+    DirectReply {
+        text: String,
+        message: u16,
+    },
+    Bookmark(u16),
+    Unbookmark(u16),
+    FollowAuthor(u16),
+    UnfollowAuthor(u16),
+    Vote {
+        toot: u16,
+        choices: Vec<u8>,
+    },
+}
+
+impl WritesSpec {
+    /// Reads a spec from a `.json` or `.toml` file
+    pub fn from_file(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let contents = std::fs::read_to_string(path)?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => Ok(toml::from_str(&contents)?),
+            _ => Ok(serde_json::from_str(&contents)?),
+        }
+    }
+
+    /// The records as the device app would write them
+    pub(crate) fn to_writes(&self) -> Vec<TootWrite> {
+        self.writes
+            .iter()
+            .map(|write| match write {
+                WriteSpec::Favorite(index) => TootWrite::Favorite(*index),
+                WriteSpec::Follow(index) => TootWrite::Follow(*index),
+                WriteSpec::Reblog(index) => TootWrite::Reblog(*index),
+                WriteSpec::Toot {
+                    text,
+                    in_reply_to,
+                    warning,
+                } => {
+                    // the device stores replies 1-based, 0 is a new thread
+                    let is_reply_to = in_reply_to.map_or(0, |index| index + 1);
+                    let contents = to_latin_1(text, None, false);
+                    match warning {
+                        Some(warning) => TootWrite::TootV2(TootContentV2 {
+                            author: 0,
+                            is_reply_to,
+                            replies_start: 0,
+                            sensitive: true,
+                            warning: to_latin_1(warning, Some(u8::MAX as usize), false),
+                            contents,
+                        }),
+                        None => TootWrite::Toot(TootContent {
+                            author: 0,
+                            is_reply_to,
+                            replies_start: 0,
+                            contents,
+                        }),
+                    }
+                }
+                WriteSpec::DeviceSchema { version, features } => {
+                    TootWrite::DeviceSchema(SchemaHeader {
+                        version: *version,
+                        features: *features,
+                    })
+                }
+                WriteSpec::FetchLimits {
+                    home_timeline,
+                    self_timeline,
+                    replies,
+                } => TootWrite::FetchLimits(FetchLimits {
+                    home_timeline: *home_timeline,
+                    self_timeline: *self_timeline,
+                    replies: *replies,
+                }),
+                WriteSpec::DirectReply { text, message } => TootWrite::DirectReply(DirectReply {
+                    message: *message,
+                    contents: to_latin_1(text, None, false),
+                }),
+                WriteSpec::Bookmark(index) => TootWrite::Bookmark(*index),
+                WriteSpec::Unbookmark(index) => TootWrite::Unbookmark(*index),
+                WriteSpec::FollowAuthor(index) => TootWrite::FollowAuthor(*index),
+                WriteSpec::UnfollowAuthor(index) => TootWrite::UnfollowAuthor(*index),
+                WriteSpec::Vote { toot, choices } => TootWrite::Vote(PollVote {
+                    content: *toot,
+                    choices: choices.clone(),
+                }),
+            })
+            .collect()
+    }
+
+    /// Serializes the spec as a `HeffalumpWritesDB` pdb
+    pub fn to_pdb(&self) -> std::io::Result<Vec<u8>> {
+        let mut pdb = PdbBuilder::new(DB_NAME_WRITES, WRITES_DB_TYPE, CREATOR);
+        if let Some(timestamp) = self.timestamp {
+            pdb = pdb.with_timestamp(timestamp);
+        }
+        for write in self.to_writes() {
+            pdb = pdb.with_record(ATTR_DIRTY, write.to_hh_bytes()?);
+        }
+        pdb.to_bytes()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use palmrs::database::{PalmDatabase, PdbDatabase};
+
+    use crate::{
+        headless::database_records,
+        heffalump_hh_types::{DirectReply, HeffalumpPrefs, TootContent, TootContentV2, TootWrite},
+        mock::{timeline, Call, MockMastodon, TempDir},
+        upload::{apply_writes, parse_writes},
+        writes_gen::WritesSpec,
+        MASTODON_CACHE_OLD,
+    };
+
+    const SPEC: &str = r#"{ "writes": [
+        { "favorite": 3 },
+        { "reblog": 7 },
+        { "toot": { "text": "café", "in_reply_to": 12 } },
+        { "toot": { "text": "new thread" } }
+    ] }"#;
+
+    #[test]
+    fn test_round_trip() {
+        let spec: WritesSpec = serde_json::from_str(SPEC).unwrap();
+        let pdb = spec.to_pdb().unwrap();
+        let db = PalmDatabase::<PdbDatabase>::from_bytes(&pdb).unwrap();
+
+        let parsed = parse_writes(database_records(&db)).unwrap();
+        assert_eq!(parsed, spec.to_writes());
+        assert_eq!(parsed[1], TootWrite::Reblog(7));
+        assert_eq!(
+            parsed[2],
+            TootWrite::Toot(TootContent {
+                author: 0,
+                is_reply_to: 13,
+                replies_start: 0,
+                contents: b"caf\xe9".to_vec(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_toml() {
+        let spec: WritesSpec = toml::from_str(
+            r#"
+            [[writes]]
+            favorite = 3
+
+            [[writes]]
+            toot = { text = "hi", in_reply_to = 0, warning = "cw" }
+
+            [[writes]]
+            direct_reply = { text = "ok", message = 4 }
+            "#,
+        )
+        .unwrap();
+        let writes = spec.to_writes();
+        assert_eq!(writes.len(), 3);
+        assert_eq!(
+            writes[1],
+            TootWrite::TootV2(TootContentV2 {
+                author: 0,
+                is_reply_to: 1,
+                replies_start: 0,
+                sensitive: true,
+                warning: b"cw".to_vec(),
+                contents: b"hi".to_vec(),
+            })
+        );
+        assert_eq!(
+            writes[2],
+            TootWrite::DirectReply(DirectReply {
+                message: 4,
+                contents: b"ok".to_vec(),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_apply_generated_writes() {
+        let dir = TempDir::new("writes");
+        let source = timeline(100, 13, "alice");
+        std::fs::write(
+            dir.path().join(MASTODON_CACHE_OLD),
+            serde_json::to_string(&(HeffalumpPrefs::default(), &source)).unwrap(),
+        )
+        .unwrap();
+
+        let spec: WritesSpec = serde_json::from_str(SPEC).unwrap();
+        let db = PalmDatabase::<PdbDatabase>::from_bytes(&spec.to_pdb().unwrap()).unwrap();
+        let client = MockMastodon::new("me").with_home(source);
+        apply_writes(&client, dir.path(), database_records(&db))
+            .await
+            .unwrap();
+
+        assert_eq!(client.calls()[0], Call::Favourite(String::from("97")));
+        assert_eq!(client.calls()[1], Call::Reblog(String::from("93")));
+        assert_eq!(
+            client.calls()[2],
+            Call::PostStatus {
+                status: String::from("café"),
+                in_reply_to_id: Some(String::from("88")),
+                direct: false,
+                spoiler_text: None,
+            }
+        );
+    }
+}